@@ -1,4 +1,6 @@
 use io::Write;
+#[cfg(feature = "snapshot")]
+use rusty_v8 as v8;
 use std::{env, fs, io, path::Path, process::Command};
 
 const XML_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -10,6 +12,25 @@ const XML_FOOTER: &str = r#"    </gresource>
 </gresources>
 "#;
 
+#[cfg(feature = "snapshot")]
+const LIBRARIES_DIR: &str = "submodules/Boop/Boop/Boop/scripts/lib";
+
+// libraries are evaluated like a require and their exports stored on the global object, the
+// executor moves them into its require cache
+#[cfg(feature = "snapshot")]
+const LIBRARY_START: &str = "globalThis.__boopLibraries = globalThis.__boopLibraries || {};
+(function () {
+    var module = { exports: {} };
+    (function (exports, module) {
+";
+
+#[cfg(feature = "snapshot")]
+const LIBRARY_END: &str = "
+    }).apply(module.exports, [module.exports, module]);
+    globalThis.__boopLibraries[__BOOP_LIBRARY_PATH__] = module.exports;
+})();
+";
+
 fn add_files(xml: &mut String, folder: &str) {
     for path in fs::read_dir(folder).unwrap() {
         let path = path.as_ref().unwrap();
@@ -19,6 +40,7 @@ fn add_files(xml: &mut String, folder: &str) {
         }
 
         if path.path().is_file() {
+            println!("cargo:rerun-if-changed={}", path.path().display());
             xml.push_str(&format!(
                 "\t\t<file>{}</file>\n",
                 path.path()
@@ -35,8 +57,105 @@ fn add_files(xml: &mut String, folder: &str) {
     }
 }
 
+// snapshot of a context with the @boop/ libraries already evaluated, isolates created from it
+// skip compiling and running them, returns false if no snapshot was created
+#[cfg(feature = "snapshot")]
+fn build_snapshot(out_dir: &Path) -> bool {
+    // the snapshot is created by V8 running on the host, V8 built for another target can't load it
+    if env::var("TARGET") != env::var("HOST") {
+        println!(
+            "cargo:warning=no snapshot is created when cross compiling, the @boop/ libraries are loaded at runtime"
+        );
+        return false;
+    }
+
+    let platform = v8::new_default_platform().unwrap();
+    v8::V8::initialize_platform(platform);
+    v8::V8::initialize();
+
+    let mut creator = v8::SnapshotCreator::new(None);
+    // SAFETY: this is the only time the isolate is taken from the creator, it still owns it and
+    // disposes it in create_blob, so it is forgotten below instead of dropped
+    let mut isolate = unsafe { creator.get_owned_isolate() };
+
+    let mut libraries: Vec<_> = fs::read_dir(LIBRARIES_DIR)
+        .map(|dir| dir.filter_map(Result::ok).map(|f| f.path()).collect())
+        .unwrap_or_default();
+    libraries.sort();
+
+    // the snapshot only has to be rebuilt when a library changes
+    println!("cargo:rerun-if-changed={}", LIBRARIES_DIR);
+    for path in &libraries {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+
+    {
+        let scope = &mut v8::HandleScope::new(&mut isolate);
+        let context = v8::Context::new(scope);
+        let scope = &mut v8::ContextScope::new(scope, context);
+
+        for path in libraries {
+            if path.extension().map_or(true, |extension| extension != "js") {
+                continue;
+            }
+
+            let name = format!("lib/{}", path.file_name().unwrap().to_string_lossy());
+            let source = [
+                LIBRARY_START,
+                &fs::read_to_string(&path).unwrap(),
+                &LIBRARY_END.replace("__BOOP_LIBRARY_PATH__", &format!("{:?}", name)),
+            ]
+            .concat();
+
+            let tc_scope = &mut v8::TryCatch::new(scope);
+            let code = v8::String::new(tc_scope, &source).unwrap();
+            let result =
+                v8::Script::compile(tc_scope, code, None).and_then(|script| script.run(tc_scope));
+
+            if result.is_none() {
+                let exception = tc_scope
+                    .exception()
+                    .and_then(|exception| exception.to_string(tc_scope))
+                    .map(|exception| exception.to_rust_string_lossy(tc_scope))
+                    .unwrap_or_default();
+                println!(
+                    "cargo:warning=failed to preload {}, it will be loaded at runtime: {}",
+                    name, exception
+                );
+            }
+        }
+
+        creator.set_default_context(context);
+    }
+
+    std::mem::forget(isolate);
+
+    let blob = creator
+        .create_blob(v8::FunctionCodeHandling::Keep)
+        .expect("failed to create snapshot");
+    fs::write(out_dir.join("BOOP_SNAPSHOT.bin"), &*blob).unwrap();
+
+    true
+}
+
+#[cfg(not(feature = "snapshot"))]
+fn build_snapshot(_out_dir: &Path) -> bool {
+    false
+}
+
 fn main() {
     let out_dir = env::var("OUT_DIR").unwrap();
+
+    // listing inputs replaces cargo's default of rerunning on any change in the package, the
+    // library and resource files are listed as they are read
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=resources");
+    println!("cargo:rustc-check-cfg=cfg(boop_snapshot)");
+    // without a snapshot the executor compiles the libraries when they are first loaded
+    if build_snapshot(Path::new(&out_dir)) {
+        println!("cargo:rustc-cfg=boop_snapshot");
+    }
+
     let mut resources = Path::new(&out_dir).to_path_buf();
     resources.push("resources");
 
@@ -0,0 +1,16 @@
+// Boop-GTK forbids unsafe code, the one scope it can't get safely is created here
+
+use rusty_v8 as v8;
+
+// runs f in a scope for context, for V8 callbacks that aren't given a scope (like the module
+// resolve callback), context must be the one the callback was called with
+pub fn with_callback_scope<'s, R>(
+    context: v8::Local<'s, v8::Context>,
+    f: impl FnOnce(&mut v8::HandleScope<'s>) -> R,
+) -> R {
+    // SAFETY: a Local<Context> only exists inside an open handle scope of the isolate that owns
+    // it, and V8 calls back on that isolate's thread with the context entered, so the isolate is
+    // the current one and the scope created here is nested inside the active scopes
+    let scope = &mut unsafe { v8::CallbackScope::new(context) };
+    f(scope)
+}
@@ -0,0 +1,500 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{Context, Result};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    config::{CatalogScript, Config},
+    install::{self, Package},
+    native,
+    scriptmap::ScriptMap,
+};
+
+// index of a catalog in a local directory
+pub const INDEX_FILE: &str = "index.json";
+
+// larger downloads are rejected, scripts are nowhere near this
+const MAX_DOWNLOAD_SIZE: u64 = 16 * 1024 * 1024;
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Index {
+    #[serde(default)]
+    pub name: String,
+    pub scripts: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IndexEntry {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub version: String,
+    // hex encoded SHA-256 of the script file
+    pub checksum: String,
+    // location of the script file, relative to the index
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryStatus {
+    NotInstalled,
+    Installed,
+    // installed from a catalog, holds the installed version
+    UpdateAvailable(String),
+    // a user script with the same name that wasn't installed from a catalog
+    Conflict,
+}
+
+impl Display for EntryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryStatus::NotInstalled => write!(f, "Not installed"),
+            EntryStatus::Installed => write!(f, "Installed"),
+            EntryStatus::UpdateAvailable(version) => write!(f, "Update from {}", version),
+            EntryStatus::Conflict => write!(f, "Name already used"),
+        }
+    }
+}
+
+// an index and where it was loaded from, script URLs are relative to it
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    pub url: Url,
+    pub index: Index,
+}
+
+// a script downloaded and validated, downloading doesn't need the scripts or config so it can be
+// done on another thread
+#[derive(Debug)]
+pub struct Download {
+    // file name the script is installed as
+    file: PathBuf,
+    package: Package,
+}
+
+impl Catalog {
+    pub fn load(location: &str) -> Result<Catalog> {
+        let url = index_url(location)?;
+        info!("loading script catalog from {}", url);
+
+        let json =
+            String::from_utf8(fetch(&url)?).wrap_err_with(|| format!("{} isn't UTF-8", url))?;
+        let index = serde_jsonrc::from_str(&json)
+            .wrap_err_with(|| format!("Failed to parse script index {}", url))?;
+
+        Ok(Catalog { url, index })
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&IndexEntry> {
+        self.index.scripts.iter().find(|entry| entry.name == name)
+    }
+
+    fn entry_url(&self, entry: &IndexEntry) -> Result<Url> {
+        let url = self
+            .url
+            .join(&entry.url)
+            .wrap_err_with(|| format!("Invalid URL for \"{}\": {}", entry.name, entry.url))?;
+
+        // an index on the web shouldn't be able to read local files
+        if url.scheme() == "file" && self.url.scheme() != "file" {
+            return Err(eyre!("\"{}\" points to a local file", entry.name));
+        }
+
+        Ok(url)
+    }
+
+    // downloads a script, fails if it doesn't match the checksum in the index
+    pub fn download(&self, entry: &IndexEntry) -> Result<Vec<u8>> {
+        let url = self.entry_url(entry)?;
+        let bytes = fetch(&url)?;
+
+        let checksum = native::sha256(&bytes);
+        if !checksum.eq_ignore_ascii_case(entry.checksum.trim()) {
+            return Err(eyre!(
+                "Checksum of {} doesn't match the index, expected {} but got {}",
+                url,
+                entry.checksum,
+                checksum
+            ));
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn status(&self, entry: &IndexEntry, scripts: &ScriptMap, config: &Config) -> EntryStatus {
+        self.status_in(entry, scripts, config, &ScriptMap::user_scripts_dir())
+    }
+
+    fn status_in(
+        &self,
+        entry: &IndexEntry,
+        scripts: &ScriptMap,
+        config: &Config,
+        dir: &Path,
+    ) -> EntryStatus {
+        let installed = config.catalog.installed_script(&entry.name);
+
+        match (scripts.scripts.get(&entry.name), installed) {
+            (Some(script), Some(installed)) if script.path == dir.join(&installed.file) => {
+                if installed.version == entry.version
+                    && installed.checksum.eq_ignore_ascii_case(&entry.checksum)
+                {
+                    EntryStatus::Installed
+                } else {
+                    EntryStatus::UpdateAvailable(installed.version.clone())
+                }
+            }
+            (Some(script), _) if script.path.starts_with(dir) => EntryStatus::Conflict,
+            _ => EntryStatus::NotInstalled,
+        }
+    }
+
+    // downloads a script so it can be installed, checks it's the script the index describes
+    pub fn download_script(&self, entry: &IndexEntry) -> Result<Download> {
+        let url = self.entry_url(entry)?;
+        let file = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+            .filter(|file| !file.is_empty() && !file.contains('/'))
+            .map(PathBuf::from)
+            .ok_or_else(|| eyre!("{} doesn't name a file", url))?;
+
+        let source = String::from_utf8(self.download(entry)?)
+            .wrap_err_with(|| format!("{} isn't UTF-8", url))?;
+        let package = Package::script(source, &file)?;
+        if package.script_names().all(|name| name != entry.name) {
+            return Err(eyre!(
+                "{} isn't named \"{}\" like the index says",
+                url,
+                entry.name
+            ));
+        }
+
+        Ok(Download { file, package })
+    }
+
+    // installs a downloaded script to the user scripts directory, replacing the version installed
+    // before
+    pub fn install(
+        &self,
+        entry: &IndexEntry,
+        download: Download,
+        scripts: &mut ScriptMap,
+        config: &mut Config,
+    ) -> Result<()> {
+        self.install_in(
+            entry,
+            download,
+            scripts,
+            config,
+            &ScriptMap::user_scripts_dir(),
+        )
+    }
+
+    fn install_in(
+        &self,
+        entry: &IndexEntry,
+        download: Download,
+        scripts: &mut ScriptMap,
+        config: &mut Config,
+        dir: &Path,
+    ) -> Result<()> {
+        let Download { file, package } = download;
+
+        // replace the previous version, unless it was deleted already
+        let status = self.status_in(entry, scripts, config, dir);
+        if let Some(index) = config
+            .catalog
+            .installed
+            .iter()
+            .position(|installed| installed.name == entry.name)
+        {
+            let previous = config.catalog.installed.remove(index);
+            if matches!(
+                status,
+                EntryStatus::Installed | EntryStatus::UpdateAvailable(_)
+            ) {
+                install::remove_installed(&[previous.name], &[previous.file], scripts, dir)?;
+            }
+        }
+
+        package.install_in(scripts, config, dir)?;
+        config.catalog.installed.push(CatalogScript {
+            name: entry.name.clone(),
+            version: entry.version.clone(),
+            checksum: entry.checksum.trim().to_lowercase(),
+            file,
+        });
+
+        Ok(())
+    }
+}
+
+// the index at a path, or file://, http:// or https:// URL
+fn index_url(location: &str) -> Result<Url> {
+    let location = location.trim();
+    if location.is_empty() {
+        return Err(eyre!("No script index set"));
+    }
+
+    let url = if location.contains("://") {
+        Url::parse(location).wrap_err_with(|| format!("Invalid URL {}", location))?
+    } else {
+        let path = env::current_dir()
+            .wrap_err("Failed to get current directory")?
+            .join(location);
+        Url::from_file_path(&path).map_err(|_| eyre!("Invalid path {}", path.display()))?
+    };
+
+    // a local directory is loaded from its index file
+    match url.to_file_path() {
+        Ok(path) if url.scheme() == "file" && path.is_dir() => {
+            Url::from_file_path(path.join(INDEX_FILE))
+                .map_err(|_| eyre!("Invalid path {}", path.display()))
+        }
+        _ => Ok(url),
+    }
+}
+
+fn fetch(url: &Url) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    match url.scheme() {
+        "file" => {
+            let path = url
+                .to_file_path()
+                .map_err(|_| eyre!("{} isn't a local path", url))?;
+            bytes =
+                fs::read(&path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        }
+        "http" | "https" => {
+            ureq::get(url.as_str())
+                .timeout(DOWNLOAD_TIMEOUT)
+                .call()
+                .wrap_err_with(|| format!("Failed to download {}", url))?
+                .into_reader()
+                .take(MAX_DOWNLOAD_SIZE + 1)
+                .read_to_end(&mut bytes)
+                .wrap_err_with(|| format!("Failed to download {}", url))?;
+        }
+        scheme => {
+            return Err(eyre!(
+                "Can't load {}, {} URLs aren't supported",
+                url,
+                scheme
+            ))
+        }
+    }
+
+    if bytes.len() as u64 > MAX_DOWNLOAD_SIZE {
+        return Err(eyre!("{} is too large", url));
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn source(version: &str) -> String {
+        format!(
+            r#"/**
+                {{
+                    "api": 1,
+                    "name": "Shout",
+                    "description": "Shouts version {}",
+                    "icon": "type"
+                }}
+            **/
+
+            function main(state) {{
+                state.text = state.text.toUpperCase();
+            }}"#,
+            version
+        )
+    }
+
+    // writes a catalog with a single script to dir
+    fn write_catalog(dir: &Path, version: &str, checksum: Option<&str>) {
+        let source = source(version);
+        fs::create_dir_all(dir.join("scripts")).unwrap();
+        fs::write(dir.join("scripts/Shout.js"), &source).unwrap();
+
+        let checksum = checksum
+            .map(str::to_string)
+            .unwrap_or_else(|| native::sha256(source.as_bytes()));
+        fs::write(
+            dir.join(INDEX_FILE),
+            format!(
+                r#"{{
+                    "name": "Test Catalog",
+                    "scripts": [{{
+                        "name": "Shout",
+                        "description": "Converts text to upper case",
+                        "version": "{}",
+                        "checksum": "{}",
+                        "url": "scripts/Shout.js"
+                    }}]
+                }}"#,
+                version, checksum
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_index_url() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(
+            index_url(dir.path().to_str().unwrap()).unwrap(),
+            Url::from_file_path(dir.path().join(INDEX_FILE)).unwrap()
+        );
+        assert_eq!(
+            index_url(Url::from_file_path(dir.path()).unwrap().as_str()).unwrap(),
+            Url::from_file_path(dir.path().join(INDEX_FILE)).unwrap()
+        );
+        assert_eq!(
+            index_url("https://example.com/boop/index.json")
+                .unwrap()
+                .as_str(),
+            "https://example.com/boop/index.json"
+        );
+        assert!(index_url("  ").is_err());
+    }
+
+    #[test]
+    fn test_entry_url() {
+        let catalog = |url: &str| Catalog {
+            url: Url::parse(url).unwrap(),
+            index: Index {
+                name: String::new(),
+                scripts: Vec::new(),
+            },
+        };
+        let entry = |url: &str| IndexEntry {
+            name: "Test".to_string(),
+            description: String::new(),
+            version: "1.0.0".to_string(),
+            checksum: String::new(),
+            url: url.to_string(),
+        };
+
+        let web = catalog("https://example.com/boop/index.json");
+        assert_eq!(
+            web.entry_url(&entry("scripts/Test.js")).unwrap().as_str(),
+            "https://example.com/boop/scripts/Test.js"
+        );
+        assert!(web.entry_url(&entry("file:///etc/passwd")).is_err());
+
+        let local = catalog("file:///srv/boop/index.json");
+        assert_eq!(
+            local.entry_url(&entry("../Test.js")).unwrap().as_str(),
+            "file:///srv/Test.js"
+        );
+    }
+
+    #[test]
+    fn test_install_and_update() {
+        let catalog_dir = tempfile::tempdir().unwrap();
+        let scripts_dir = tempfile::tempdir().unwrap();
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        let mut config = Config::default();
+
+        write_catalog(catalog_dir.path(), "1.0.0", None);
+        let catalog = Catalog::load(catalog_dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(catalog.index.name, "Test Catalog");
+        let entry = catalog.entry("Shout").unwrap();
+        assert_eq!(
+            catalog.status_in(entry, &scripts, &config, scripts_dir.path()),
+            EntryStatus::NotInstalled
+        );
+
+        catalog
+            .install_in(
+                entry,
+                catalog.download_script(entry).unwrap(),
+                &mut scripts,
+                &mut config,
+                scripts_dir.path(),
+            )
+            .unwrap();
+        assert_eq!(
+            scripts.scripts["Shout"].path,
+            scripts_dir.path().join("Shout.js")
+        );
+        assert_eq!(
+            catalog.status_in(entry, &scripts, &config, scripts_dir.path()),
+            EntryStatus::Installed
+        );
+
+        write_catalog(catalog_dir.path(), "1.1.0", None);
+        let catalog = Catalog::load(catalog_dir.path().to_str().unwrap()).unwrap();
+        let entry = catalog.entry("Shout").unwrap();
+        assert_eq!(
+            catalog.status_in(entry, &scripts, &config, scripts_dir.path()),
+            EntryStatus::UpdateAvailable("1.0.0".to_string())
+        );
+
+        catalog
+            .install_in(
+                entry,
+                catalog.download_script(entry).unwrap(),
+                &mut scripts,
+                &mut config,
+                scripts_dir.path(),
+            )
+            .unwrap();
+        assert_eq!(
+            catalog.status_in(entry, &scripts, &config, scripts_dir.path()),
+            EntryStatus::Installed
+        );
+        assert_eq!(config.catalog.installed.len(), 1);
+        assert_eq!(
+            fs::read_to_string(scripts_dir.path().join("Shout.js")).unwrap(),
+            source("1.1.0")
+        );
+
+        // a user script with the same name is left alone
+        config.catalog.installed.clear();
+        assert_eq!(
+            catalog.status_in(entry, &scripts, &config, scripts_dir.path()),
+            EntryStatus::Conflict
+        );
+        assert!(catalog
+            .install_in(
+                entry,
+                catalog.download_script(entry).unwrap(),
+                &mut scripts,
+                &mut config,
+                scripts_dir.path(),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        write_catalog(dir.path(), "1.0.0", Some(&"0".repeat(64)));
+
+        let catalog = Catalog::load(dir.path().to_str().unwrap()).unwrap();
+        let err = catalog
+            .download(catalog.entry("Shout").unwrap())
+            .unwrap_err();
+        assert!(err.to_string().contains("Checksum"), "{}", err);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{config::Config, scriptmap::ScriptMap};
+use eyre::Result;
+use std::{ffi::OsString, path::PathBuf};
+
+pub const USAGE: &str = "Usage: boop-gtk [OPTION...]
+       boop-gtk test [SCRIPT|DIRECTORY...]
+       boop-gtk list [--all]
+
+Options:
+  --clipboard        Use the clipboard as input and copy the result back to it
+  --primary          Use the primary selection as input and copy the result back to it
+  --script <NAME>    Script to run on the clipboard, the command palette is opened otherwise
+  --quit             Quit after the result is copied, unless Boop-GTK was already open
+  -h, --help         Show this help
+
+Commands:
+  test               Run the fixtures (MyScript.test.toml) of scripts, the user scripts
+                     directory is tested if no scripts are given
+  list               List the names and descriptions of scripts, disabled scripts are only
+                     listed with --all";
+
+// selection used as input and output by the clipboard transform mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    // name of the app action that transforms this selection
+    pub fn action_name(self) -> &'static str {
+        match self {
+            Selection::Clipboard => "transform_clipboard",
+            Selection::Primary => "transform_primary",
+        }
+    }
+
+    pub fn atom(self) -> gdk::Atom {
+        match self {
+            Selection::Clipboard => gdk::SELECTION_CLIPBOARD,
+            Selection::Primary => gdk::SELECTION_PRIMARY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandLine {
+    pub selection: Option<Selection>,
+    pub script: Option<String>,
+    pub quit: bool,
+    pub help: bool,
+    // scripts and directories to run the fixtures of, set by the test command
+    pub test: Option<Vec<PathBuf>>,
+    // set by the list command, all includes disabled scripts
+    pub list: bool,
+    pub all: bool,
+    // started by D-Bus activation to serve the scripts interface and the search provider
+    pub service: bool,
+}
+
+impl CommandLine {
+    // parses the arguments passed to the application, the first is the program name
+    pub fn parse(args: &[OsString]) -> Result<Self> {
+        let mut command_line = CommandLine::default();
+
+        if args.get(1).map_or(false, |arg| arg == "test") {
+            command_line.test = Some(args.iter().skip(2).map(PathBuf::from).collect());
+            return Ok(command_line);
+        }
+
+        if args.get(1).map_or(false, |arg| arg == "list") {
+            command_line.list = true;
+            for arg in args.iter().skip(2) {
+                match arg.to_string_lossy().as_ref() {
+                    "--all" => command_line.all = true,
+                    arg => return Err(eyre!("Unknown option \"{}\"", arg)),
+                }
+            }
+            return Ok(command_line);
+        }
+
+        let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--clipboard" => command_line.selection = Some(Selection::Clipboard),
+                "--primary" => command_line.selection = Some(Selection::Primary),
+                "--script" => {
+                    let script = args
+                        .next()
+                        .ok_or_else(|| eyre!("--script requires the name of a script"))?;
+                    command_line.script = Some(script.to_string());
+                }
+                "--quit" => command_line.quit = true,
+                "--gapplication-service" => command_line.service = true,
+                "-h" | "--help" => command_line.help = true,
+                arg => return Err(eyre!("Unknown option \"{}\"", arg)),
+            }
+        }
+
+        if command_line.selection.is_none() && (command_line.script.is_some() || command_line.quit)
+        {
+            return Err(eyre!(
+                "--script and --quit can only be used with --clipboard or --primary"
+            ));
+        }
+
+        Ok(command_line)
+    }
+}
+
+// `boop-gtk list`, a line with the name and description of each script
+pub fn list_scripts(scripts: &ScriptMap, config: &Config, all: bool) -> Vec<String> {
+    scripts
+        .scripts
+        .iter()
+        .filter_map(|(name, script)| {
+            let disabled = config.is_disabled(name);
+            if disabled && !all {
+                return None;
+            }
+
+            Some(format!(
+                "{}\t{}{}",
+                name,
+                script.metadata.description,
+                if disabled { " (disabled)" } else { "" }
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CommandLine> {
+        let args: Vec<OsString> = std::iter::once("boop-gtk")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect();
+        CommandLine::parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&[]).unwrap(), CommandLine::default());
+        assert_eq!(
+            parse(&["--clipboard", "--script", "Base64 Encode", "--quit"]).unwrap(),
+            CommandLine {
+                selection: Some(Selection::Clipboard),
+                script: Some("Base64 Encode".to_string()),
+                quit: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--primary"]).unwrap(),
+            CommandLine {
+                selection: Some(Selection::Primary),
+                ..Default::default()
+            }
+        );
+        assert!(parse(&["--help"]).unwrap().help);
+        assert_eq!(
+            parse(&["test", "MyScript.js", "scripts"]).unwrap().test,
+            Some(vec![PathBuf::from("MyScript.js"), PathBuf::from("scripts")])
+        );
+        assert_eq!(parse(&["test"]).unwrap().test, Some(Vec::new()));
+        assert_eq!(
+            parse(&["list", "--all"]).unwrap(),
+            CommandLine {
+                list: true,
+                all: true,
+                ..Default::default()
+            }
+        );
+        assert!(parse(&["--gapplication-service"]).unwrap().service);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["--script"]).is_err());
+        assert!(parse(&["--script", "Base64 Encode"]).is_err());
+        assert!(parse(&["--quit"]).is_err());
+        assert!(parse(&["--foo"]).is_err());
+        assert!(parse(&["list", "--foo"]).is_err());
+    }
+
+    #[test]
+    fn test_list_scripts() {
+        use crate::script::Script;
+        use std::collections::BTreeMap;
+
+        let mut scripts = BTreeMap::new();
+        for name in &["Base64 Encode", "Format JSON"] {
+            let script = Script::from_source(
+                format!(
+                    r#"/** {{ "api": 1, "name": "{}", "description": "Test script", "icon": "html" }} **/"#,
+                    name
+                ),
+                PathBuf::new(),
+            )
+            .unwrap();
+            scripts.insert(name.to_string(), script);
+        }
+        let scripts = ScriptMap::from(scripts);
+
+        let mut config = Config::default();
+        config.set_disabled("Base64 Encode", true);
+
+        assert_eq!(
+            list_scripts(&scripts, &config, false),
+            vec!["Format JSON\tTest script".to_string()]
+        );
+        assert_eq!(
+            list_scripts(&scripts, &config, true),
+            vec![
+                "Base64 Encode\tTest script (disabled)".to_string(),
+                "Format JSON\tTest script".to_string()
+            ]
+        );
+
+        config.set_disabled("Base64 Encode", false);
+        assert_eq!(list_scripts(&scripts, &config, false).len(), 2);
+    }
+}
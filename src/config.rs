@@ -1,14 +1,69 @@
-use std::{fs::File, io::Write};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use crate::XDG_DIRS;
+use crate::{executor::DEFAULT_HEAP_LIMIT, permission::Permission, XDG_DIRS};
 use eyre::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+// toml needs plain values before tables, so fields holding tables come last
 #[derive(Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub show_shortcuts_on_open: bool,
+    // names of scripts hidden from the command palette and script listings
+    pub disabled_scripts: Vec<String>,
     pub editor: EditorConfig,
+    pub executor: ExecutorConfig,
+    pub catalog: CatalogConfig,
+    pub script_permissions: Vec<ScriptPermissions>,
+    pub installed_packs: Vec<InstalledPack>,
+}
+
+// permissions the user allowed a script to use, stored as a list since config keys are lowercased.
+// grants belong to one version of one file, an edited script or a different script installed under
+// the same name has to ask again
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScriptPermissions {
+    // only there to make the config readable
+    pub script: String,
+    #[serde(default)]
+    pub path: PathBuf,
+    // sha256 of the source the permissions were granted to
+    #[serde(default)]
+    pub checksum: String,
+    pub granted: Vec<Permission>,
+}
+
+// what a script pack installed, so the whole pack can be uninstalled
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstalledPack {
+    pub name: String,
+    pub version: String,
+    // names of the scripts in the pack
+    pub scripts: Vec<String>,
+    // every file written, relative to the user scripts directory
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CatalogConfig {
+    // path or file://, http:// or https:// URL of a script index, directories use their index.json
+    pub index: String,
+    // scripts installed from a catalog, so updates can be found
+    pub installed: Vec<CatalogScript>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CatalogScript {
+    pub name: String,
+    pub version: String,
+    pub checksum: String,
+    // relative to the user scripts directory
+    pub file: PathBuf,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -16,6 +71,30 @@ pub struct EditorConfig {
     pub colour_scheme_id: String,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutorConfig {
+    // maximum heap size of each script in MiB, scripts using more are stopped
+    pub heap_limit_mb: usize,
+    // number of idle scripts kept running, least recently used ones are stopped first
+    pub pool_size: usize,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        ExecutorConfig {
+            heap_limit_mb: DEFAULT_HEAP_LIMIT / 1024 / 1024,
+            pool_size: 4,
+        }
+    }
+}
+
+impl ExecutorConfig {
+    pub fn heap_limit(&self) -> usize {
+        self.heap_limit_mb * 1024 * 1024
+    }
+}
+
 impl Default for EditorConfig {
     fn default() -> Self {
         EditorConfig {
@@ -72,6 +151,75 @@ impl Config {
     pub fn set_show_shortcuts_on_open(&mut self, enable: bool) {
         self.show_shortcuts_on_open = enable;
     }
+
+    // permissions granted to the script at path with the given source checksum
+    pub fn granted_permissions(&self, path: &Path, checksum: &str) -> Vec<Permission> {
+        self.script_permissions
+            .iter()
+            .find(|permissions| permissions.path == path && permissions.checksum == checksum)
+            .map(|permissions| permissions.granted.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn grant_permissions(
+        &mut self,
+        script: &str,
+        path: &Path,
+        checksum: &str,
+        permissions: &[Permission],
+    ) {
+        // grants for older versions of the file are stale
+        self.script_permissions
+            .retain(|granted| granted.path != path || granted.checksum == checksum);
+
+        match self
+            .script_permissions
+            .iter_mut()
+            .find(|granted| granted.path == path && granted.checksum == checksum)
+        {
+            Some(granted) => {
+                granted.granted.extend_from_slice(permissions);
+                granted.granted.sort();
+                granted.granted.dedup();
+            }
+            None => {
+                let mut granted = permissions.to_vec();
+                granted.sort();
+                granted.dedup();
+                self.script_permissions.push(ScriptPermissions {
+                    script: script.to_string(),
+                    path: path.to_path_buf(),
+                    checksum: checksum.to_string(),
+                    granted,
+                });
+            }
+        }
+    }
+
+    pub fn is_disabled(&self, script: &str) -> bool {
+        self.disabled_scripts.iter().any(|name| name == script)
+    }
+
+    pub fn set_disabled(&mut self, script: &str, disabled: bool) {
+        self.disabled_scripts.retain(|name| name != script);
+        if disabled {
+            self.disabled_scripts.push(script.to_string());
+            self.disabled_scripts.sort();
+        }
+    }
+
+    // the installed pack a script came from
+    pub fn pack_of(&self, script: &str) -> Option<&InstalledPack> {
+        self.installed_packs
+            .iter()
+            .find(|pack| pack.scripts.iter().any(|name| name == script))
+    }
+}
+
+impl CatalogConfig {
+    pub fn installed_script(&self, name: &str) -> Option<&CatalogScript> {
+        self.installed.iter().find(|script| script.name == name)
+    }
 }
 
 impl EditorConfig {
@@ -79,3 +227,70 @@ impl EditorConfig {
         self.colour_scheme_id = String::from(id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let mut config = Config::default();
+        config.set_disabled("Format JSON", true);
+        config.installed_packs.push(InstalledPack {
+            name: "Pack".to_string(),
+            version: "1.0.0".to_string(),
+            scripts: vec!["Packed".to_string()],
+            files: vec![PathBuf::from("Packed.js"), PathBuf::from("lib/packed.js")],
+        });
+        config.catalog.index = "https://example.com/index.json".to_string();
+        config.catalog.installed.push(CatalogScript {
+            name: "Catalogued".to_string(),
+            version: "2.0.0".to_string(),
+            checksum: "00".to_string(),
+            file: PathBuf::from("Catalogued.js"),
+        });
+
+        config.grant_permissions("Runner", Path::new("Runner.js"), "aa", &[Permission::Exec]);
+
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert!(config.is_disabled("Format JSON"));
+        assert_eq!(config.pack_of("Packed").unwrap().name, "Pack");
+        assert!(config.pack_of("Format JSON").is_none());
+        assert_eq!(config.catalog.index, "https://example.com/index.json");
+        assert_eq!(
+            config
+                .catalog
+                .installed_script("Catalogued")
+                .unwrap()
+                .version,
+            "2.0.0"
+        );
+        assert_eq!(
+            config.granted_permissions(Path::new("Runner.js"), "aa"),
+            vec![Permission::Exec]
+        );
+    }
+
+    #[test]
+    fn test_permissions_follow_source() {
+        let mut config = Config::default();
+        let path = Path::new("Runner.js");
+        config.grant_permissions("Runner", path, "aa", &[Permission::Exec]);
+        config.grant_permissions("Runner", path, "aa", &[Permission::Clipboard]);
+        assert_eq!(
+            config.granted_permissions(path, "aa"),
+            vec![Permission::Clipboard, Permission::Exec]
+        );
+
+        // another script with the same name, or the same file after an edit, asks again
+        assert!(config.granted_permissions(path, "bb").is_empty());
+        assert!(config
+            .granted_permissions(Path::new("Other/Runner.js"), "aa")
+            .is_empty());
+
+        config.grant_permissions("Runner", path, "bb", &[Permission::Clipboard]);
+        assert!(config.granted_permissions(path, "aa").is_empty());
+        assert_eq!(config.script_permissions.len(), 1);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{
+    config::Config,
+    executor::{ExecutionRequest, ExecutorError, TextRange},
+    scriptmap::ScriptMap,
+    search_provider::{self, SearchProvider},
+};
+use eyre::{eyre, Context, Result};
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+};
+use std::{
+    convert::TryFrom,
+    os::unix::io::AsRawFd,
+    sync::{Arc, RwLock},
+};
+use zbus::{dbus_interface, fdo};
+use zvariant::ObjectPath;
+
+// GApplication owns fyi.zoey.Boop-GTK on its own connection, so the interface is served under a
+// name of its own, interface names can't contain dashes, see docs/DBus.md
+pub const BUS_NAME: &str = "fyi.zoey.Boop-GTK.Scripts";
+pub const OBJECT_PATH: &str = "/fyi/zoey/BoopGTK/Scripts";
+
+// work D-Bus clients ask of the window, handled by the main thread
+#[derive(Debug)]
+pub enum Request {
+    // show text in the buffer
+    OpenText(String),
+    // run a script on the contents of the clipboard
+    RunScript(String),
+    // open the command palette
+    ShowCommandPalette,
+}
+
+// serves the scripts interface and the search provider until the connection is closed, which is
+// returned as an error, blocks the calling thread
+pub fn serve(
+    connection: &zbus::Connection,
+    scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    requests: glib::Sender<Request>,
+) -> Result<()> {
+    fdo::DBusProxy::new(connection)
+        .wrap_err("Failed to create D-Bus proxy")?
+        .request_name(BUS_NAME, fdo::RequestNameFlags::ReplaceExisting.into())
+        .wrap_err_with(|| format!("Failed to request name {}", BUS_NAME))?;
+
+    let mut object_server = object_server(connection, scripts, config, requests)?;
+
+    info!("serving {}", BUS_NAME);
+
+    loop {
+        // zbus keeps reading a closed socket without ever returning, so the connection is polled
+        // first and messages are only read while it is open
+        let mut fds = [PollFd::new(connection.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, -1) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).wrap_err("Failed to poll the D-Bus connection"),
+        }
+
+        let events = fds[0].revents().unwrap_or_else(PollFlags::empty);
+        if events.intersects(PollFlags::POLLHUP | PollFlags::POLLERR | PollFlags::POLLNVAL) {
+            return Err(eyre!("D-Bus connection was closed"));
+        }
+
+        match object_server.try_handle_next() {
+            Ok(_) => {}
+            Err(zbus::Error::Io(err)) => {
+                return Err(err).wrap_err("Failed to read from the D-Bus connection")
+            }
+            Err(err) => error!("failed to handle D-Bus message: {}", err),
+        }
+    }
+}
+
+// registers the scripts interface and the search provider on a connection
+fn object_server<'a>(
+    connection: &zbus::Connection,
+    scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    requests: glib::Sender<Request>,
+) -> Result<zbus::ObjectServer<'a>> {
+    let mut object_server = zbus::ObjectServer::new(connection);
+    object_server
+        .at(
+            &ObjectPath::try_from(OBJECT_PATH)?,
+            ScriptsInterface::new(scripts.clone(), config.clone(), requests.clone()),
+        )
+        .wrap_err_with(|| format!("Failed to register {}", OBJECT_PATH))?;
+    object_server
+        .at(
+            &ObjectPath::try_from(search_provider::OBJECT_PATH)?,
+            SearchProvider::new(scripts, config, requests),
+        )
+        .wrap_err_with(|| format!("Failed to register {}", search_provider::OBJECT_PATH))?;
+
+    Ok(object_server)
+}
+
+// fyi.zoey.BoopGTK.Scripts interface, lets other applications run scripts while Boop-GTK is open
+pub struct ScriptsInterface {
+    scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    requests: glib::Sender<Request>,
+}
+
+impl ScriptsInterface {
+    pub fn new(
+        scripts: Arc<RwLock<ScriptMap>>,
+        config: Arc<RwLock<Config>>,
+        requests: glib::Sender<Request>,
+    ) -> Self {
+        ScriptsInterface {
+            scripts,
+            config,
+            requests,
+        }
+    }
+}
+
+// offsets over D-Bus are in characters, scripts use UTF-16 code units
+fn char_to_utf16_offset(text: &str, offset: u32) -> usize {
+    text.chars()
+        .take(offset as usize)
+        .map(char::len_utf16)
+        .sum()
+}
+
+#[dbus_interface(name = "fyi.zoey.BoopGTK.Scripts")]
+impl ScriptsInterface {
+    // names and descriptions of every script, except disabled ones which can still be run by name
+    fn list_scripts(&self) -> Vec<(String, String)> {
+        let config = self.config.read().expect("Config lock poisoned");
+
+        self.scripts
+            .read()
+            .expect("Scripts lock is poisoned")
+            .scripts
+            .values()
+            .filter(|script| !config.is_disabled(&script.metadata.name))
+            .map(|script| {
+                (
+                    script.metadata.name.clone(),
+                    script.metadata.description.clone(),
+                )
+            })
+            .collect()
+    }
+
+    // runs a script on text, selection is the start and end of the selected text in characters,
+    // equal for no selection with the cursor placed there, returns the resulting text and the info
+    // and error posted by the script
+    fn run_script(
+        &self,
+        name: &str,
+        text: &str,
+        selection: (u32, u32),
+    ) -> fdo::Result<(String, String, String)> {
+        info!("running {} for D-Bus client", name);
+
+        // the cursor is placed at the end, which comes first for backward selections
+        let cursor = char_to_utf16_offset(text, selection.1);
+        let range = TextRange::new(char_to_utf16_offset(text, selection.0), cursor);
+        let request = ExecutionRequest {
+            full_text: text.to_string(),
+            selection: if range.is_empty() { None } else { Some(range) },
+            cursor,
+            ..Default::default()
+        };
+
+        let pool_size = self
+            .config
+            .read()
+            .expect("Config lock poisoned")
+            .executor
+            .pool_size;
+
+        let channel = {
+            let mut script_map = self.scripts.write().expect("Scripts lock is poisoned");
+            let script = script_map
+                .scripts
+                .get_mut(name)
+                .ok_or_else(|| fdo::Error::InvalidArgs(format!("No script named \"{}\"", name)))?;
+
+            let (granted, heap_limit) = {
+                let config = self.config.read().expect("Config lock poisoned");
+                (
+                    config.granted_permissions(&script.path, &script.checksum()),
+                    config.executor.heap_limit(),
+                )
+            };
+
+            // there is no one to ask for consent, permissions must have been granted in the app
+            let missing = script.missing_permissions(&granted);
+            if !missing.is_empty() {
+                return Err(fdo::Error::AccessDenied(format!(
+                    "\"{}\" needs permissions that haven't been granted, run it in Boop-GTK first",
+                    name
+                )));
+            }
+
+            script.set_granted_permissions(&granted);
+            script.set_heap_limit(heap_limit);
+            script.executor_channel()
+        };
+
+        // the script runs without holding the lock, so it doesn't block the window, the watcher
+        // or the search provider
+        let response = channel.execute(request.clone());
+
+        let status_result = {
+            let mut script_map = self.scripts.write().expect("Scripts lock is poisoned");
+            let status_result = match script_map.scripts.get_mut(name) {
+                Some(script) => script.finish_execution(&channel, response),
+                // removed while it ran
+                None => response.and_then(|(status, _)| status.map_err(eyre::Report::from)),
+            };
+            script_map.enforce_pool_size(pool_size);
+            status_result
+        };
+
+        match status_result {
+            Ok(status) => {
+                let info = status.info().cloned().unwrap_or_default();
+                let error = status.error().cloned().unwrap_or_default();
+                let result = String::from_utf8(status.into_replacement().apply(&request))
+                    .ok()
+                    .filter(|result| !result.contains('\0'))
+                    .ok_or_else(|| fdo::Error::Failed("Result is binary".to_string()))?;
+
+                Ok((result, info, error))
+            }
+            Err(err) => {
+                let executor_err = err
+                    .downcast::<ExecutorError>()
+                    .map_err(|err| fdo::Error::Failed(err.to_string()))?;
+
+                Ok((text.to_string(), String::new(), executor_err.to_string()))
+            }
+        }
+    }
+
+    // opens text in the Boop-GTK window
+    fn open_text(&self, text: &str) -> fdo::Result<()> {
+        self.requests
+            .send(Request::OpenText(text.to_string()))
+            .map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use std::{
+        collections::BTreeMap,
+        io::{BufRead, BufReader},
+        path::PathBuf,
+        process::{Command, Stdio},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    const UPPER_CASE: &str = r#"/**
+        {
+            "api": 1,
+            "name": "Upper Case",
+            "description": "Converts text to upper case",
+            "author": "Zoey",
+            "icon": "type",
+            "tags": "upper"
+        }
+        **/
+        function main(state) {
+            state.text = state.text.toUpperCase();
+            state.postInfo("done");
+        }"#;
+
+    fn script_map(source: &str) -> Arc<RwLock<ScriptMap>> {
+        let script = Script::from_source(source.to_string(), PathBuf::new()).unwrap();
+        let mut scripts = BTreeMap::new();
+        scripts.insert(script.metadata.name.clone(), script);
+
+        Arc::new(RwLock::new(ScriptMap::from(scripts)))
+    }
+
+    fn interface(source: &str) -> ScriptsInterface {
+        let (sender, _) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+
+        ScriptsInterface::new(
+            script_map(source),
+            Arc::new(RwLock::new(Config::default())),
+            sender,
+        )
+    }
+
+    #[test]
+    fn test_run_script() {
+        let interface = interface(UPPER_CASE);
+
+        assert_eq!(
+            interface.list_scripts(),
+            vec![(
+                "Upper Case".to_string(),
+                "Converts text to upper case".to_string()
+            )]
+        );
+        assert_eq!(
+            interface
+                .run_script("Upper Case", "fôo bar", (0, 3))
+                .unwrap(),
+            ("FÔO bar".to_string(), "done".to_string(), "".to_string())
+        );
+        assert_eq!(
+            interface.run_script("Upper Case", "foo", (0, 0)).unwrap(),
+            ("FOO".to_string(), "done".to_string(), "".to_string())
+        );
+        assert!(interface.run_script("Lower Case", "foo", (0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_run_script_without_permissions() {
+        let interface = interface(
+            r#"/**
+            {
+                "api": 1,
+                "name": "Paste",
+                "description": "Pastes the clipboard",
+                "author": "Zoey",
+                "icon": "type",
+                "tags": "paste",
+                "permissions": ["clipboard"]
+            }
+            **/
+            function main(state) {
+                state.text = readClipboard();
+            }"#,
+        );
+
+        assert!(interface.run_script("Paste", "foo", (0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_serve_on_private_bus() {
+        // a bus of its own, so the test doesn't depend on or disturb the session bus
+        let mut daemon = match Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(err) => {
+                eprintln!("skipping, dbus-daemon is unavailable: {}", err);
+                return;
+            }
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim().to_string();
+
+        let scripts = script_map(UPPER_CASE);
+        let server_address = address.clone();
+        let server = thread::spawn(move || {
+            let connection = zbus::Connection::new_for_address(&server_address, true).unwrap();
+            let (sender, _) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+
+            serve(
+                &connection,
+                scripts,
+                Arc::new(RwLock::new(Config::default())),
+                sender,
+            )
+        });
+
+        let connection = zbus::Connection::new_for_address(&address, true).unwrap();
+        let proxy = fdo::DBusProxy::new(&connection).unwrap();
+        let start = Instant::now();
+        while !proxy.name_has_owner(BUS_NAME).unwrap() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "name wasn't acquired"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let reply = connection
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some("fyi.zoey.BoopGTK.Scripts"),
+                "RunScript",
+                &("Upper Case", "foo bar", (4u32, 7u32)),
+            )
+            .unwrap();
+        assert_eq!(
+            reply.body::<(String, String, String)>().unwrap(),
+            ("foo BAR".to_string(), "done".to_string(), "".to_string())
+        );
+
+        assert!(connection
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some("fyi.zoey.BoopGTK.Scripts"),
+                "RunScript",
+                &("Lower Case", "foo", (0u32, 0u32)),
+            )
+            .is_err());
+
+        // serving stops once the bus goes away
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+        assert!(server.join().unwrap().is_err());
+    }
+}
@@ -1,58 +1,86 @@
-use crate::{scriptmap::Scripts, XDG_DIRS};
+use crate::{
+    host::{self, HostState},
+    modules::{self, ModuleMap, ModulePath},
+    native,
+    permission::Permission,
+    util::Utf16Ext,
+};
 use dirty2::Dirty;
 use eyre::{Context, ContextCompat, Result};
 use rusty_v8 as v8;
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     convert::TryFrom,
-    env,
     error::Error,
+    ffi::c_void,
     fmt::{Debug, Display},
-    fs::File,
-    io::Read,
+    path::PathBuf,
     rc::Rc,
     sync::Once,
     time::Instant,
 };
 
-static BOOP_WRAPPER_START: &str = "
-/***********************************
-*     Start of Boop's wrapper      *
-***********************************/
-            
-(function() {
-    var module = {
-        exports: {}
-    };
-            
-    const moduleWrapper = (function (exports, module) {
-
-/***********************************
-*      End of Boop's wrapper      *
-***********************************/
-
-";
-
-static BOOP_WRAPPER_END: &str = "
-            
-/***********************************
-*     Start of Boop's wrapper      *
-***********************************/
-            
-    }).apply(module.exports, [module.exports, module]);
-
-    return module.exports;
-})();
-            
-/***********************************
-*      End of Boop's wrapper      *
-***********************************/
-";
-
 static INIT_V8: Once = Once::new();
 
+// context with the @boop/ libraries already loaded, created by build.rs when the snapshot feature
+// is enabled, without it the libraries are compiled when they are first loaded
+#[cfg(boop_snapshot)]
+static SNAPSHOT: Option<&[u8]> = Some(include_bytes!(concat!(
+    env!("OUT_DIR"),
+    "/BOOP_SNAPSHOT.bin"
+)));
+#[cfg(not(boop_snapshot))]
+static SNAPSHOT: Option<&[u8]> = None;
+
+// heap limit used unless configured otherwise
+pub const DEFAULT_HEAP_LIMIT: usize = 256 * 1024 * 1024;
+
+thread_local! {
+    // isolate running JS on this thread, it is terminated when it nears its heap limit
+    static RUNNING_ISOLATE: RefCell<Option<v8::IsolateHandle>> = RefCell::new(None);
+    // set when the running isolate was terminated for running out of memory
+    static OUT_OF_MEMORY: Cell<bool> = Cell::new(false);
+}
+
+// latest script API supported, scripts declare the version they target with `api` in their metadata
+// 1: original Boop API
+// 2: selectionStart, selectionEnd, cursor, lines and select(), buffer edits take precedence over insert(),
+//    main can return a result object describing the replacement
+// 3: bytes, a Uint8Array of the text that scripts can set to binary data
+pub const API_VERSION: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct ExecutorOptions {
+    // API version the script was written against
+    pub api: u32,
+    // location of the script, relative imports are resolved against it
+    pub path: Option<PathBuf>,
+    // compile the script as an ES module instead of a classic script
+    pub module: bool,
+    // permissions granted to the script, host functions are only installed for these
+    pub permissions: Vec<Permission>,
+    // where the storage host functions persist values
+    pub storage_path: Option<PathBuf>,
+    // maximum size of the V8 heap in bytes, scripts exceeding it are terminated
+    pub heap_limit: Option<usize>,
+}
+
+impl Default for ExecutorOptions {
+    fn default() -> Self {
+        ExecutorOptions {
+            api: API_VERSION,
+            path: None,
+            module: false,
+            permissions: Vec::new(),
+            storage_path: None,
+            heap_limit: Some(DEFAULT_HEAP_LIMIT),
+        }
+    }
+}
+
 pub struct Executor {
     isolate: v8::OwnedIsolate,
+    options: ExecutorOptions,
 }
 
 impl Debug for Executor {
@@ -66,8 +94,66 @@ struct ExecutorState {
     main_function: Option<v8::Global<v8::Function>>,
 }
 
+// range of the full text, offsets are in UTF-16 code units to match JavaScript strings
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl TextRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        TextRange {
+            start: start.min(end),
+            end: start.max(end),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+// everything a script can see about the buffer when it is executed
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionRequest {
+    pub full_text: String,
+    pub selection: Option<TextRange>,
+    pub cursor: usize,
+    // only provided to scripts with the clipboard permission
+    pub clipboard: Option<String>,
+    // raw contents of the buffer when it holds binary data, full_text is only a view of it
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl ExecutionRequest {
+    // builds a request for a selected range of full_text, the cursor is placed at the end of the
+    // selection (or of the text) and can be moved by setting `cursor`
+    pub fn new(full_text: &str, selection: Option<TextRange>) -> Self {
+        ExecutionRequest {
+            full_text: full_text.to_owned(),
+            cursor: selection
+                .map(|range| range.end)
+                .unwrap_or_else(|| full_text.utf16_len()),
+            selection,
+            clipboard: None,
+            bytes: None,
+        }
+    }
+
+    fn selection_text(&self) -> Option<&str> {
+        self.selection.map(|range| {
+            &self.full_text[self.full_text.utf16_to_byte_offset(range.start)
+                ..self.full_text.utf16_to_byte_offset(range.end)]
+        })
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct ExecutionStatus {
+    // API version of the script, determines how the status is turned into a replacement
+    api: u32,
+
     // true if text was selected when execution began
     is_text_selected: bool,
 
@@ -78,6 +164,20 @@ pub struct ExecutionStatus {
     full_text: Dirty<String>,
     text: Dirty<String>,
     selection: Dirty<String>,
+    // selected range after execution, start and end are equal when only the cursor is placed
+    selection_range: Dirty<TextRange>,
+    // cursor position, one of the edges of selection_range
+    cursor: usize,
+    // replacement explicitly returned by main, takes precedence over everything else
+    result: Option<TextReplacement>,
+    // text written to the clipboard
+    clipboard: Option<String>,
+    // bytes used by the isolate's heap after execution
+    heap_size: usize,
+    // binary contents of the buffer, if any
+    input_bytes: Option<Vec<u8>>,
+    // bytes assigned by the script, replace the text like `text` does
+    bytes: Option<Vec<u8>>,
 }
 
 impl ExecutionStatus {
@@ -89,6 +189,13 @@ impl ExecutionStatus {
         Dirty::clear(&mut self.full_text);
         self.text.write().clear();
         Dirty::clear(&mut self.text);
+        *self.selection_range.write() = TextRange::default();
+        Dirty::clear(&mut self.selection_range);
+        self.cursor = 0;
+        self.result = None;
+        self.clipboard = None;
+        self.input_bytes = None;
+        self.bytes = None;
     }
 
     pub fn info(&self) -> Option<&String> {
@@ -99,7 +206,74 @@ impl ExecutionStatus {
         self.error.as_ref()
     }
 
+    pub fn clipboard(&self) -> Option<&String> {
+        self.clipboard.as_ref()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
     pub fn into_replacement(self) -> TextReplacement {
+        if self.selection_range.dirty() {
+            let mut status = self;
+            let range = *status.selection_range.read();
+            Dirty::clear(&mut status.selection_range);
+
+            info!("found new selection {}..{}", range.start, range.end);
+            return TextReplacement::WithSelection(Box::new(status.into_replacement()), range);
+        }
+
+        if let Some(result) = self.result {
+            info!("found result returned by main");
+            return result;
+        }
+
+        // no explicit result, guess the replacement from the fields the script wrote to
+        if self.api >= 2 {
+            self.into_replacement_v2()
+        } else {
+            self.into_legacy_replacement()
+        }
+    }
+
+    // since api 2 edits to the buffer take precedence over insertions:
+    // 0. bytes
+    // 1. fullText
+    // 2. selection
+    // 3. text (with selection)
+    // 4. text (without selection)
+    // 5. insertion
+    fn into_replacement_v2(self) -> TextReplacement {
+        if let Some(bytes) = self.bytes {
+            // binary buffers can't be partially selected
+            if self.is_text_selected && self.input_bytes.is_none() {
+                info!("found selection bytes replacement");
+                TextReplacement::SelectionBytes(bytes)
+            } else {
+                info!("found full bytes replacement");
+                TextReplacement::FullBytes(bytes)
+            }
+        } else if self.full_text.dirty() {
+            info!("found full_text replacement");
+            TextReplacement::Full(self.full_text.unwrap())
+        } else if self.selection.dirty() {
+            info!("found selection replacement");
+            TextReplacement::Selection(self.selection.unwrap())
+        } else if self.is_text_selected && self.text.dirty() {
+            info!("found text (with selection) replacement");
+            TextReplacement::Selection(self.text.unwrap())
+        } else if self.text.dirty() {
+            info!("found text (without selection) replacement");
+            TextReplacement::Full(self.text.unwrap())
+        } else if !self.insert.is_empty() {
+            info!("found insertion");
+            TextReplacement::Insert(self.insert)
+        } else {
+            TextReplacement::None
+        }
+    }
+
+    fn into_legacy_replacement(self) -> TextReplacement {
         // not quite sure what the correct behaviour here should be
         // right now the order of presidence is:
         // 0. insertion
@@ -129,14 +303,68 @@ impl ExecutionStatus {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum TextReplacement {
     Full(String),
     Selection(String),
     Insert(Vec<String>),
+    // insert at a UTF-16 offset of the full text
+    InsertAt(String, usize),
+    // replacement followed by selecting a range of the resulting text
+    WithSelection(Box<TextReplacement>, TextRange),
+    // bytes that may not be valid UTF-8
+    FullBytes(Vec<u8>),
+    SelectionBytes(Vec<u8>),
     None,
 }
 
+impl TextReplacement {
+    // contents of the buffer described by request after the replacement, the same edits the UI
+    // makes without needing a buffer
+    pub fn apply(self, request: &ExecutionRequest) -> Vec<u8> {
+        let full_text = request.full_text.as_str();
+        let splice = |range: TextRange, bytes: &[u8]| {
+            let start = full_text.utf16_to_byte_offset(range.start);
+            let end = full_text.utf16_to_byte_offset(range.end);
+            [
+                &full_text.as_bytes()[..start],
+                bytes,
+                &full_text.as_bytes()[end..],
+            ]
+            .concat()
+        };
+
+        match self {
+            TextReplacement::Full(text) => text.into_bytes(),
+            TextReplacement::FullBytes(bytes) => bytes,
+            TextReplacement::Selection(text) => {
+                TextReplacement::SelectionBytes(text.into_bytes()).apply(request)
+            }
+            TextReplacement::SelectionBytes(bytes) => match request.selection {
+                Some(range) if !range.is_empty() => splice(range, &bytes),
+                _ => {
+                    error!("tried to do a selection replacement, but no text is selected!");
+                    full_text.as_bytes().to_vec()
+                }
+            },
+            TextReplacement::Insert(insertions) => splice(
+                request
+                    .selection
+                    .unwrap_or_else(|| TextRange::new(request.cursor, request.cursor)),
+                insertions.join("").as_bytes(),
+            ),
+            TextReplacement::InsertAt(text, offset) => {
+                splice(TextRange::new(offset, offset), text.as_bytes())
+            }
+            TextReplacement::WithSelection(replacement, _) => replacement.apply(request),
+            TextReplacement::None => request
+                .bytes
+                .clone()
+                .unwrap_or_else(|| full_text.as_bytes().to_vec()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct JSException {
     pub exception_str: String,
@@ -152,6 +380,12 @@ pub enum ExecutorError {
     Compile(JSException),
     Execute(JSException),
     NoMain,
+    UnsupportedApi(u32),
+    InvalidResult(String),
+    // require or import of a file outside of the script directories
+    RequireDenied(String),
+    // script was terminated after reaching the heap limit
+    OutOfMemory,
 }
 
 impl Display for ExecutorError {
@@ -163,6 +397,16 @@ impl Display for ExecutorError {
                 write!(f, "JS execution exception: {:?}", exception)
             }
             ExecutorError::NoMain => write!(f, "no main function"),
+            ExecutorError::UnsupportedApi(api) => write!(
+                f,
+                "script requires API version {}, but only {} is supported",
+                api, API_VERSION
+            ),
+            ExecutorError::InvalidResult(reason) => write!(f, "invalid result: {}", reason),
+            ExecutorError::RequireDenied(specifier) => {
+                write!(f, "access to \"{}\" denied", specifier)
+            }
+            ExecutorError::OutOfMemory => write!(f, "heap limit reached"),
         }
     }
 }
@@ -193,12 +437,37 @@ impl ExecutorError {
             ExecutorError::NoMain => {
                 String::from(r#"<span foreground="red">ERROR:</span> No main function"#)
             }
+            ExecutorError::UnsupportedApi(api) => format!(
+                r#"<span foreground="red">ERROR:</span> Script requires API version {}, update Boop-GTK to run it"#,
+                api
+            ),
+            ExecutorError::InvalidResult(reason) => format!(
+                r#"<span foreground="red">ERROR:</span> Invalid result returned by main: {}"#,
+                reason
+            ),
+            ExecutorError::RequireDenied(specifier) => format!(
+                r#"<span foreground="red">ERROR:</span> Script is not allowed to load "{}", only files in the scripts directory can be loaded"#,
+                specifier
+            ),
+            ExecutorError::OutOfMemory => String::from(
+                r#"<span foreground="red">ERROR:</span> Script ran out of memory and was stopped"#,
+            ),
         }
     }
 }
 
 impl Executor {
+    // scripts always pass their options, this shorthand is left for the tests
+    #[cfg(test)]
     pub fn new(source: &str) -> eyre::Result<Self> {
+        Executor::with_options(source, ExecutorOptions::default())
+    }
+
+    pub fn with_options(source: &str, options: ExecutorOptions) -> eyre::Result<Self> {
+        if options.api > API_VERSION {
+            return Err(ExecutorError::UnsupportedApi(options.api).into());
+        }
+
         INIT_V8.call_once(|| {
             let start = Instant::now();
 
@@ -214,21 +483,45 @@ impl Executor {
         let mut isolate = {
             let start = Instant::now();
 
-            let isolate = v8::Isolate::new(Default::default());
+            let params = match SNAPSHOT {
+                Some(snapshot) => v8::CreateParams::default().snapshot_blob(snapshot),
+                None => v8::CreateParams::default(),
+            };
+            let params = match options.heap_limit {
+                Some(heap_limit) => params.heap_limits(0, heap_limit),
+                None => params,
+            };
+
+            let mut isolate = v8::Isolate::new(params);
+            isolate.add_near_heap_limit_callback(Executor::near_heap_limit, std::ptr::null_mut());
             info!("isolate initialized in {:?}", start.elapsed());
 
             isolate
         };
+
+        Executor::set_running_isolate(&isolate);
+
+        // set module slot, caches ES modules imported by the script
+        isolate.set_slot(Rc::new(RefCell::new(ModuleMap::default())));
+
+        // set host slot, shared with the host functions of granted permissions
+        isolate.set_slot(Rc::new(RefCell::new(HostState {
+            storage_path: options.storage_path.clone(),
+            ..Default::default()
+        })));
+
         let (global_context, main_function) = {
             let scope = &mut v8::HandleScope::new(&mut isolate);
             // let context = v8::Context::new(scope);
-            let (context, main_function) = Executor::initialize_context(source, scope)?;
+            let (context, main_function) = Executor::initialize_context(source, &options, scope)?;
             (v8::Global::new(scope, context), main_function)
         };
 
         // set status slot, stores execution infomation
-        let status_slot: Rc<RefCell<ExecutionStatus>> =
-            Rc::new(RefCell::new(ExecutionStatus::default()));
+        let status_slot: Rc<RefCell<ExecutionStatus>> = Rc::new(RefCell::new(ExecutionStatus {
+            api: options.api,
+            ..Default::default()
+        }));
         isolate.set_slot(status_slot);
 
         // set state slot, stores v8 details
@@ -238,55 +531,12 @@ impl Executor {
         }));
         isolate.set_slot(state_slot);
 
-        Ok(Executor { isolate })
-    }
-
-    // load source code from internal files or external filesystem depending on the path
-    fn load_raw_source(path: String) -> Result<String> {
-        if path.starts_with("@boop/") {
-            // script is internal
-
-            let internal_path = path.replace("@boop/", "lib/");
-            info!(
-                "found internal script, real path: #BINARY#/{}",
-                internal_path
-            );
-
-            let raw_source = String::from_utf8(
-                Scripts::get(&internal_path)
-                    .ok_or_else(|| eyre!("No internal script with path \"{}\"", path))?
-                    .to_vec(),
-            )
-            .wrap_err("Problem with file encoding")?;
-
-            return Ok(raw_source);
-        }
-
-        let mut external_path = if cfg!(test) {
-            env::temp_dir()
-        } else {
-            let mut path = XDG_DIRS.get_config_home();
-            path.push("scripts");
-            path
-        };
-        external_path.push(&path);
-
-        info!(
-            "found external script, real path: {}",
-            external_path.display()
-        );
-
-        let mut raw_source = String::new();
-        File::open(external_path)
-            .wrap_err_with(|| format!("Could not open \"{}\"", path))?
-            .read_to_string(&mut raw_source)
-            .wrap_err("Problem reading file")?;
-
-        Ok(raw_source)
+        Ok(Executor { isolate, options })
     }
 
     fn initialize_context<'s>(
         source: &str,
+        options: &ExecutorOptions,
         scope: &mut v8::HandleScope<'s, ()>,
     ) -> eyre::Result<(v8::Local<'s, v8::Context>, v8::Global<v8::Function>)> {
         let scope = &mut v8::EscapableHandleScope::new(scope);
@@ -294,43 +544,116 @@ impl Executor {
         let global = context.global(scope);
         let scope = &mut v8::ContextScope::new(scope, context);
 
+        modules::take_preloaded(scope, global).wrap_err("failed to load preloaded libraries")?;
+
         let require_key =
             v8::String::new(scope, "require").wrap_err("failed to created 'require' string")?;
-        let require_val = v8::Function::new(scope, Executor::global_require)
-            .wrap_err("failed to created require function")?;
+        let require_val =
+            modules::require_function(scope, options.path.clone().map(ModulePath::External))
+                .wrap_err("failed to created require function")?;
         global.set(scope, require_key.into(), require_val.into());
 
-        // complile and run script
-        let code = v8::String::new(scope, source).ok_or(ExecutorError::SourceExceedsMaxLength)?;
+        host::install(scope, global, &options.permissions)
+            .wrap_err("failed to install host functions")?;
 
         let tc_scope = &mut v8::TryCatch::new(scope);
-        let compiled_script = v8::Script::compile(tc_scope, code, None)
-            .ok_or_else(|| {
-                Executor::extract_exception(tc_scope)
-                    .expect("exception occored but no exception was caught")
-            })
-            .map_err(ExecutorError::Compile)?;
+        let main_key =
+            v8::String::new(tc_scope, "main").wrap_err("failed to create JS string 'main'")?;
 
-        compiled_script
-            .run(tc_scope)
-            .ok_or_else(|| {
-                Executor::extract_exception(tc_scope)
-                    .expect("exception occored but no exception was caught")
-            })
-            .map_err(ExecutorError::Execute)?;
+        let main_value = if options.module {
+            // compile, link and evaluate script as an ES module
+            let path = options.path.clone().map(ModulePath::External);
+            let module = match modules::compile_module(tc_scope, path.as_ref(), source) {
+                Some(module) => module,
+                None if tc_scope.has_caught() => {
+                    return Err(ExecutorError::Compile(
+                        Executor::extract_exception(tc_scope)
+                            .expect("exception occored but no exception was caught"),
+                    )
+                    .into())
+                }
+                None => return Err(ExecutorError::SourceExceedsMaxLength.into()),
+            };
+
+            module
+                .instantiate_module(tc_scope, modules::resolve_module)
+                .ok_or_else(|| Executor::execute_error(tc_scope))?;
+
+            let result = module
+                .evaluate(tc_scope)
+                .ok_or_else(|| Executor::execute_error(tc_scope))?;
+
+            // evaluation returns a promise when the module uses top level await
+            tc_scope.perform_microtask_checkpoint();
+            if let Ok(promise) = v8::Local::<v8::Promise>::try_from(result) {
+                if promise.state() == v8::PromiseState::Rejected {
+                    let exception = promise.result(tc_scope);
+                    return Err(ExecutorError::Execute(JSException {
+                        exception_str: exception
+                            .to_string(tc_scope)
+                            .map(|e| e.to_rust_string_lossy(tc_scope))
+                            .unwrap_or_default(),
+                        ..Default::default()
+                    })
+                    .into());
+                }
+            }
+
+            // main can be exported or assigned to the global object
+            let namespace = module
+                .get_module_namespace()
+                .to_object(tc_scope)
+                .wrap_err("module namespace is not an object")?;
+            namespace
+                .get(tc_scope, main_key.into())
+                .filter(|main| main.is_function())
+                .or_else(|| global.get(tc_scope, main_key.into()))
+        } else {
+            // complile and run script
+            let code =
+                v8::String::new(tc_scope, source).ok_or(ExecutorError::SourceExceedsMaxLength)?;
+
+            let compiled_script = v8::Script::compile(tc_scope, code, None)
+                .ok_or_else(|| {
+                    Executor::extract_exception(tc_scope)
+                        .expect("exception occored but no exception was caught")
+                })
+                .map_err(ExecutorError::Compile)?;
+
+            compiled_script
+                .run(tc_scope)
+                .ok_or_else(|| Executor::execute_error(tc_scope))?;
+
+            global.get(tc_scope, main_key.into())
+        };
 
         // extract main function
-        let main_key =
-            v8::String::new(tc_scope, "main").wrap_err("failed to create JS string 'main'")?;
-        let main_function =
-            v8::Local::<v8::Function>::try_from(global.get(tc_scope, main_key.into()).unwrap())
-                .map_err(|_e| ExecutorError::NoMain)?;
+        let main_function = main_value
+            .and_then(|main| v8::Local::<v8::Function>::try_from(main).ok())
+            .ok_or(ExecutorError::NoMain)?;
         let main_function = v8::Global::new(tc_scope, main_function);
 
         Ok((tc_scope.escape(context), main_function))
     }
 
-    pub fn execute(&mut self, full_text: &str, selection: Option<&str>) -> Result<ExecutionStatus> {
+    // scripts build a full request, this shorthand is left for the tests
+    #[cfg(test)]
+    pub fn execute(
+        &mut self,
+        full_text: &str,
+        selection: Option<TextRange>,
+    ) -> Result<ExecutionStatus> {
+        self.execute_request(&ExecutionRequest::new(full_text, selection))
+    }
+
+    pub fn execute_request(&mut self, request: &ExecutionRequest) -> Result<ExecutionStatus> {
+        let full_text = request.full_text.as_str();
+        let selection = request.selection_text();
+        let api = self.options.api;
+        let clipboard_granted = self.options.permissions.contains(&Permission::Clipboard);
+
+        Executor::set_running_isolate(&self.isolate);
+
         // setup execution status
         {
             let status_slot = self
@@ -341,12 +664,30 @@ impl Executor {
             let mut status = status_slot.borrow_mut();
 
             status.reset();
+            status.is_text_selected = selection.is_some();
             *status.full_text.write() = full_text.to_string();
             status.full_text.clear();
             *status.text.write() = selection.unwrap_or(full_text).to_string();
             status.text.clear();
             *status.selection.write() = selection.unwrap_or("").to_string();
             status.selection.clear();
+            *status.selection_range.write() = request
+                .selection
+                .unwrap_or_else(|| TextRange::new(request.cursor, request.cursor));
+            status.selection_range.clear();
+            status.cursor = request.cursor;
+            status.input_bytes = request.bytes.clone();
+        }
+
+        {
+            let host_slot = self
+                .isolate
+                .get_slot::<Rc<RefCell<HostState>>>()
+                .wrap_err("failed to get host slot")?;
+
+            let mut host_state = host_slot.borrow_mut();
+            host_state.clipboard = request.clipboard.clone();
+            host_state.clipboard_written = None;
         }
 
         // prepare payload and execute main
@@ -382,6 +723,35 @@ impl Executor {
                     .wrap_err("Failed to set 'isSelection' value")?;
             }
 
+            // value: apiVersion
+            {
+                let api_version_key = v8::String::new(scope, "apiVersion")
+                    .wrap_err("Failed to construct 'apiVersion' JS string")?;
+
+                let api_version_value = v8::Integer::new_from_unsigned(scope, API_VERSION);
+
+                payload
+                    .set(scope, api_version_key.into(), api_version_value.into())
+                    .wrap_err("Failed to set 'apiVersion' value")?;
+            }
+
+            // value: clipboard (clipboard permission)
+            if clipboard_granted {
+                let clipboard_key = v8::String::new(scope, "clipboard")
+                    .wrap_err("Failed to construct 'clipboard' JS string")?;
+
+                let clipboard_value: v8::Local<v8::Value> = match &request.clipboard {
+                    Some(clipboard) => v8::String::new(scope, clipboard)
+                        .wrap_err("Failed to construct clipboard JS string")?
+                        .into(),
+                    None => v8::null(scope).into(),
+                };
+
+                payload
+                    .set(scope, clipboard_key.into(), clipboard_value)
+                    .wrap_err("Failed to set 'clipboard' value")?;
+            }
+
             // getter/setters: full_text, text, selection
             {
                 let full_text_key = v8::String::new(scope, "fullText")
@@ -417,7 +787,67 @@ impl Executor {
                     .wrap_err("Failed to set 'selection' accessor")?;
             }
 
-            // functions: post_info, post_error, insert
+            // getter/setters: selectionStart, selectionEnd, cursor, lines (api 2)
+            if api >= 2 {
+                let selection_start_key = v8::String::new(scope, "selectionStart")
+                    .wrap_err("Failed to construct 'selectionStart' JS string")?;
+                let selection_end_key = v8::String::new(scope, "selectionEnd")
+                    .wrap_err("Failed to construct 'selectionEnd' JS string")?;
+                let cursor_key = v8::String::new(scope, "cursor")
+                    .wrap_err("Failed to construct 'cursor' JS string")?;
+                let lines_key = v8::String::new(scope, "lines")
+                    .wrap_err("Failed to construct 'lines' JS string")?;
+
+                payload
+                    .set_accessor_with_setter(
+                        scope,
+                        selection_start_key.into(),
+                        Executor::payload_selection_start_getter,
+                        Executor::payload_selection_start_setter,
+                    )
+                    .wrap_err("Failed to set 'selectionStart' accessor")?;
+                payload
+                    .set_accessor_with_setter(
+                        scope,
+                        selection_end_key.into(),
+                        Executor::payload_selection_end_getter,
+                        Executor::payload_selection_end_setter,
+                    )
+                    .wrap_err("Failed to set 'selectionEnd' accessor")?;
+                payload
+                    .set_accessor_with_setter(
+                        scope,
+                        cursor_key.into(),
+                        Executor::payload_cursor_getter,
+                        Executor::payload_cursor_setter,
+                    )
+                    .wrap_err("Failed to set 'cursor' accessor")?;
+                payload
+                    .set_accessor_with_setter(
+                        scope,
+                        lines_key.into(),
+                        Executor::payload_lines_getter,
+                        Executor::payload_lines_setter,
+                    )
+                    .wrap_err("Failed to set 'lines' accessor")?;
+            }
+
+            // getter/setter: bytes (api 3)
+            if api >= 3 {
+                let bytes_key = v8::String::new(scope, "bytes")
+                    .wrap_err("Failed to construct 'bytes' JS string")?;
+
+                payload
+                    .set_accessor_with_setter(
+                        scope,
+                        bytes_key.into(),
+                        Executor::payload_bytes_getter,
+                        Executor::payload_bytes_setter,
+                    )
+                    .wrap_err("Failed to set 'bytes' accessor")?;
+            }
+
+            // functions: post_info, post_error, insert, select
 
             let post_info_key = v8::String::new(scope, "postInfo")
                 .wrap_err("Failed to create JS string 'postInfo'")?;
@@ -443,6 +873,16 @@ impl Executor {
                 .set(scope, insert_key.into(), insert_val.into())
                 .wrap_err("Failed to set 'insert' function")?;
 
+            if api >= 2 {
+                let select_key = v8::String::new(scope, "select")
+                    .wrap_err("Failed to create JS string 'select'")?;
+                let select_val = v8::Function::new(scope, Executor::payload_select)
+                    .wrap_err("Failed to create payload_select function")?;
+                payload
+                    .set(scope, select_key.into(), select_val.into())
+                    .wrap_err("Failed to set 'select' function")?;
+            }
+
             let main_function = state_slot
                 .main_function
                 .as_ref()
@@ -451,30 +891,232 @@ impl Executor {
             let escape_scope = &mut v8::EscapableHandleScope::new(scope);
             let tc_scope = &mut v8::TryCatch::new(escape_scope);
 
-            main_function
+            let result = main_function
                 .call(tc_scope, payload.into(), &[payload.into()])
-                .ok_or_else(|| {
-                    ExecutorError::Execute(
-                        Executor::extract_exception(tc_scope)
-                            .wrap_err("Exception occored but no exception was caught")
-                            .unwrap(),
-                    )
-                })?;
+                .ok_or_else(|| Executor::execute_error(tc_scope))?;
+
+            if api >= 2 {
+                Executor::extract_result(tc_scope, result)?;
+            }
         }
 
+        // main returned, so any denied require was caught by the script
+        modules::clear_denied(&mut self.isolate);
+
         // extract execution status
         {
+            let heap_size = self.heap_size();
+            let clipboard = self
+                .isolate
+                .get_slot::<Rc<RefCell<HostState>>>()
+                .wrap_err("failed to get host slot")?
+                .borrow_mut()
+                .clipboard_written
+                .take();
+
             let status_slot = self
                 .isolate
                 .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
                 .ok_or_else(|| eyre!("Failed to get mutable access to status slot"))?;
 
-            let status = status_slot.borrow();
+            let mut status = status_slot.borrow_mut();
+            status.clipboard = clipboard;
+            status.heap_size = heap_size;
 
             Ok(status.clone())
         }
     }
 
+    // error for an exception thrown while running the script, denied requires are only reported as
+    // such when the error thrown for them wasn't caught
+    fn execute_error(tc_scope: &mut v8::TryCatch<v8::EscapableHandleScope>) -> ExecutorError {
+        // terminating doesn't leave an exception behind
+        if OUT_OF_MEMORY.with(|out_of_memory| out_of_memory.replace(false)) {
+            return ExecutorError::OutOfMemory;
+        }
+
+        let thrown = tc_scope.exception();
+        let exception = Executor::extract_exception(tc_scope)
+            .expect("exception occored but no exception was caught");
+
+        match modules::take_denied(tc_scope, thrown) {
+            Some(specifier) => ExecutorError::RequireDenied(specifier),
+            None => ExecutorError::Execute(exception),
+        }
+    }
+
+    // bytes currently used by the isolate's heap
+    pub fn heap_size(&mut self) -> usize {
+        let mut statistics = v8::HeapStatistics::default();
+        self.isolate.get_heap_statistics(&mut statistics);
+        statistics.used_heap_size()
+    }
+
+    // files the isolate imported or required so far, other than the script itself
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        let mut dependencies = self
+            .isolate
+            .get_slot::<Rc<RefCell<ModuleMap>>>()
+            .expect("failed to get module map slot")
+            .borrow()
+            .dependencies();
+        if let Some(path) = &self.options.path {
+            dependencies.retain(|dependency| dependency != path);
+        }
+
+        dependencies
+    }
+
+    fn set_running_isolate(isolate: &v8::Isolate) {
+        let handle = isolate.thread_safe_handle();
+        RUNNING_ISOLATE.with(|running| running.replace(Some(handle)));
+        OUT_OF_MEMORY.with(|out_of_memory| out_of_memory.set(false));
+    }
+
+    // called by V8 when the heap is almost full, the script is terminated and the limit raised so
+    // it can unwind instead of crashing the whole app
+    extern "C" fn near_heap_limit(
+        _data: *mut c_void,
+        current_heap_limit: usize,
+        _initial_heap_limit: usize,
+    ) -> usize {
+        warn!("heap limit of {} bytes reached", current_heap_limit);
+
+        OUT_OF_MEMORY.with(|out_of_memory| out_of_memory.set(true));
+        RUNNING_ISOLATE.with(|running| {
+            if let Some(handle) = running.borrow().as_ref() {
+                handle.terminate_execution();
+            }
+        });
+
+        current_heap_limit * 2
+    }
+
+    // reads an object returned from main into the status, supported shapes are:
+    // { replace: "fullText" | "selection" | "text", text: string, select?: [start, end] }
+    // { insert: string, at?: number, select?: [start, end] }
+    // anything else that isn't an object is ignored
+    fn extract_result(
+        scope: &mut v8::HandleScope<'_>,
+        result: v8::Local<'_, v8::Value>,
+    ) -> Result<(), ExecutorError> {
+        if !result.is_object() || result.is_array() {
+            return Ok(());
+        }
+
+        let result = result
+            .to_object(scope)
+            .ok_or_else(|| ExecutorError::InvalidResult("not an object".to_string()))?;
+
+        let get_string = |scope: &mut v8::HandleScope<'_>, key: &str| {
+            let key = v8::String::new(scope, key).expect("failed to create JS string");
+            result
+                .get(scope, key.into())
+                .filter(|value| !value.is_null_or_undefined())
+                .map(|value| {
+                    value
+                        .to_string(scope)
+                        .map(|value| value.to_rust_string_lossy(scope))
+                        .unwrap_or_default()
+                })
+        };
+
+        let replace = get_string(scope, "replace");
+        let insert = get_string(scope, "insert");
+        let text = get_string(scope, "text");
+        let at = get_string(scope, "at");
+        let select = {
+            let key = v8::String::new(scope, "select").expect("failed to create JS string");
+            result
+                .get(scope, key.into())
+                .filter(|value| !value.is_null_or_undefined())
+        };
+
+        let is_text_selected = scope
+            .get_slot::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get status slot")
+            .borrow()
+            .is_text_selected;
+
+        let replacement = match (replace.as_deref(), insert) {
+            (Some(_), Some(_)) => {
+                return Err(ExecutorError::InvalidResult(
+                    "replace and insert can't be used together".to_string(),
+                ))
+            }
+            (Some(replace), None) => {
+                let text = text.ok_or_else(|| {
+                    ExecutorError::InvalidResult(format!("replace \"{}\" is missing text", replace))
+                })?;
+
+                match replace {
+                    "fullText" => TextReplacement::Full(text),
+                    "selection" => TextReplacement::Selection(text),
+                    "text" if is_text_selected => TextReplacement::Selection(text),
+                    "text" => TextReplacement::Full(text),
+                    other => {
+                        return Err(ExecutorError::InvalidResult(format!(
+                            "can't replace \"{}\", expected fullText, selection or text",
+                            other
+                        )))
+                    }
+                }
+            }
+            (None, Some(insert)) => match at {
+                Some(at) => TextReplacement::InsertAt(
+                    insert,
+                    at.parse().map_err(|_| {
+                        ExecutorError::InvalidResult(format!("\"{}\" is not a valid offset", at))
+                    })?,
+                ),
+                None => TextReplacement::Insert(vec![insert]),
+            },
+            (None, None) => {
+                if select.is_none() {
+                    // plain object, not meant as a result
+                    return Ok(());
+                }
+                TextReplacement::None
+            }
+        };
+
+        let select = match select {
+            Some(select) => {
+                let range = v8::Local::<v8::Array>::try_from(select)
+                    .ok()
+                    .filter(|range| range.length() == 2)
+                    .ok_or_else(|| {
+                        ExecutorError::InvalidResult("select must be [start, end]".to_string())
+                    })?;
+
+                let offsets: Vec<usize> = (0..2)
+                    .map(|index| {
+                        range
+                            .get_index(scope, index)
+                            .map(|offset| Executor::value_to_offset(scope, offset))
+                            .unwrap_or(0)
+                    })
+                    .collect();
+
+                Some(TextRange::new(offsets[0], offsets[1]))
+            }
+            None => None,
+        };
+
+        let slot = scope
+            .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get mutable access to status slot");
+        let mut status = slot.borrow_mut();
+
+        if let Some(range) = select {
+            *status.selection_range.write() = range;
+            status.cursor = range.end;
+        }
+        status.result = Some(replacement);
+
+        Ok(())
+    }
+
     fn extract_exception(
         tc_scope: &mut v8::TryCatch<v8::EscapableHandleScope>,
     ) -> Result<JSException> {
@@ -509,59 +1151,6 @@ impl Executor {
         })
     }
 
-    fn global_require(
-        scope: &mut v8::HandleScope<'_>,
-        args: v8::FunctionCallbackArguments<'_>,
-        mut rv: v8::ReturnValue<'_>,
-    ) {
-        let code = args
-            .get(0)
-            .to_string(scope)
-            .ok_or_else(|| eyre!("argument to require is not a string"))
-            .map(|string_arg| string_arg.to_rust_string_lossy(scope))
-            .map(|mut path| {
-                if !path.ends_with(".js") {
-                    path.push_str(".js");
-                }
-                info!("loading {}", path);
-                path
-            })
-            // grab the source
-            .and_then(Executor::load_raw_source)
-            // add boop wrapper
-            .map(|raw_source| [BOOP_WRAPPER_START, &raw_source, BOOP_WRAPPER_END].concat())
-            // create JS string
-            .and_then(|source| {
-                v8::String::new(scope, &source)
-                    .ok_or_else(|| eyre!("failed to create JS string from source"))
-            });
-
-        if let Err(err) = code {
-            let exception_str = v8::String::new(scope, &err.to_string())
-                .expect("failed to create string for exception");
-            let exception = v8::Exception::error(scope, exception_str);
-
-            scope.throw_exception(exception);
-
-            return;
-        }
-
-        let code = code.unwrap();
-
-        let export = v8::Script::compile(scope, code, None)
-            .ok_or_else(|| eyre!("failed to compile JS"))
-            .and_then(|script| {
-                script
-                    .run(scope)
-                    .ok_or_else(|| eyre!("failed to execute JS"))
-            });
-
-        match export {
-            Ok(export) => rv.set(export),
-            Err(err) => error!("failed to require script: {}", err),
-        }
-    }
-
     fn payload_post_info(
         scope: &mut v8::HandleScope<'_>,
         args: v8::FunctionCallbackArguments<'_>,
@@ -762,18 +1351,303 @@ impl Executor {
 
         *selection = new_value;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // converts a JS value into an offset, negative numbers and non-numbers become 0
+    fn value_to_offset(scope: &mut v8::HandleScope<'_>, value: v8::Local<'_, v8::Value>) -> usize {
+        value.integer_value(scope).unwrap_or(0).max(0) as usize
+    }
+
+    fn payload_selection_start_getter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        _args: v8::PropertyCallbackArguments<'_>,
+        mut rv: v8::ReturnValue<'_>,
+    ) {
+        let start = scope
+            .get_slot::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get status slot")
+            .borrow()
+            .selection_range
+            .read()
+            .start;
+
+        rv.set(v8::Number::new(scope, start as f64).into());
+    }
+
+    fn payload_selection_start_setter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        value: v8::Local<'_, v8::Value>,
+        _args: v8::PropertyCallbackArguments<'_>,
+    ) {
+        let start = Executor::value_to_offset(scope, value);
+
+        info!("setting selection start ({})", start);
+
+        let slot = scope
+            .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get mutable access to status slot");
+
+        let mut slot = slot.borrow_mut();
+
+        let range = *slot.selection_range.read();
+        let selected = TextRange::new(start, range.end);
+
+        // the cursor stays on the edge it was on
+        if slot.cursor == range.start {
+            slot.cursor = selected.start;
+        } else {
+            slot.cursor = selected.end;
+        }
+        *slot.selection_range.write() = selected;
+    }
+
+    fn payload_selection_end_getter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        _args: v8::PropertyCallbackArguments<'_>,
+        mut rv: v8::ReturnValue<'_>,
+    ) {
+        let end = scope
+            .get_slot::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get status slot")
+            .borrow()
+            .selection_range
+            .read()
+            .end;
+
+        rv.set(v8::Number::new(scope, end as f64).into());
+    }
+
+    fn payload_selection_end_setter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        value: v8::Local<'_, v8::Value>,
+        _args: v8::PropertyCallbackArguments<'_>,
+    ) {
+        let end = Executor::value_to_offset(scope, value);
+
+        info!("setting selection end ({})", end);
+
+        let slot = scope
+            .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get mutable access to status slot");
+
+        let mut slot = slot.borrow_mut();
+
+        let range = *slot.selection_range.read();
+        let selected = TextRange::new(range.start, end);
+
+        // the cursor stays on the edge it was on
+        if slot.cursor == range.start {
+            slot.cursor = selected.start;
+        } else {
+            slot.cursor = selected.end;
+        }
+        *slot.selection_range.write() = selected;
+    }
+
+    fn payload_cursor_getter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        _args: v8::PropertyCallbackArguments<'_>,
+        mut rv: v8::ReturnValue<'_>,
+    ) {
+        let cursor = scope
+            .get_slot::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get status slot")
+            .borrow()
+            .cursor;
+
+        rv.set(v8::Number::new(scope, cursor as f64).into());
+    }
+
+    // moving the cursor collapses the selection
+    fn payload_cursor_setter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        value: v8::Local<'_, v8::Value>,
+        _args: v8::PropertyCallbackArguments<'_>,
+    ) {
+        let cursor = Executor::value_to_offset(scope, value);
+
+        info!("setting cursor ({})", cursor);
+
+        let slot = scope
+            .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get mutable access to status slot");
+
+        let mut slot = slot.borrow_mut();
+
+        *slot.selection_range.write() = TextRange::new(cursor, cursor);
+        slot.cursor = cursor;
+    }
+
+    // bytes of the buffer, or of text when the buffer isn't binary
+    fn payload_bytes_getter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        _args: v8::PropertyCallbackArguments<'_>,
+        mut rv: v8::ReturnValue<'_>,
+    ) {
+        let bytes = {
+            let status_slot = scope
+                .get_slot::<Rc<RefCell<ExecutionStatus>>>()
+                .expect("failed to get status slot");
+            let status = status_slot.borrow();
+
+            status
+                .bytes
+                .clone()
+                .or_else(|| status.input_bytes.clone())
+                .unwrap_or_else(|| status.text.read().clone().into_bytes())
+        };
+
+        rv.set(
+            native::uint8_array(scope, bytes)
+                .expect("failed to create Uint8Array from bytes")
+                .into(),
+        );
+    }
+
+    fn payload_bytes_setter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        value: v8::Local<'_, v8::Value>,
+        _args: v8::PropertyCallbackArguments<'_>,
+    ) {
+        // strings are stored as UTF-8
+        let bytes = native::value_to_bytes(scope, value).unwrap_or_else(|| {
+            value
+                .to_string(scope)
+                .expect("failed to convert value to string")
+                .to_rust_string_lossy(scope)
+                .into_bytes()
+        });
+
+        info!("setting bytes ({} bytes)", bytes.len());
+
+        scope
+            .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get mutable access to status slot")
+            .borrow_mut()
+            .bytes
+            .replace(bytes);
+    }
+
+    // lines of text (the selection if there is one, otherwise the full text)
+    fn payload_lines_getter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        _args: v8::PropertyCallbackArguments<'_>,
+        mut rv: v8::ReturnValue<'_>,
+    ) {
+        let text = scope
+            .get_slot::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get status slot")
+            .borrow()
+            .text
+            .read()
+            .clone();
+
+        let lines: Vec<v8::Local<v8::Value>> = text
+            .split('\n')
+            .map(|line| {
+                v8::String::new(scope, line)
+                    .expect("failed to create JS string from line")
+                    .into()
+            })
+            .collect();
+
+        rv.set(v8::Array::new_with_elements(scope, &lines).into());
+    }
+
+    fn payload_lines_setter(
+        scope: &mut v8::HandleScope<'_>,
+        _key: v8::Local<'_, v8::Name>,
+        value: v8::Local<'_, v8::Value>,
+        _args: v8::PropertyCallbackArguments<'_>,
+    ) {
+        let new_value = match v8::Local::<v8::Array>::try_from(value) {
+            Ok(array) => (0..array.length())
+                .map(|index| {
+                    array
+                        .get_index(scope, index)
+                        .and_then(|line| line.to_string(scope))
+                        .map(|line| line.to_rust_string_lossy(scope))
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Err(_) => value
+                .to_string(scope)
+                .expect("failed to convert value to string")
+                .to_rust_string_lossy(scope),
+        };
+
+        info!("setting lines ({} bytes)", new_value.len());
+
+        let slot = scope
+            .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+            .expect("failed to get mutable access to status slot");
+
+        let mut slot = slot.borrow_mut();
+
+        let text = slot.text.write();
+
+        *text = new_value;
+    }
+
+    fn payload_select(
+        scope: &mut v8::HandleScope<'_>,
+        args: v8::FunctionCallbackArguments<'_>,
+        mut rv: v8::ReturnValue<'_>,
+    ) {
+        let start = Executor::value_to_offset(scope, args.get(0));
+        let end = if args.length() > 1 {
+            Executor::value_to_offset(scope, args.get(1))
+        } else {
+            start
+        };
+
+        info!("selecting {}..{}", start, end);
+
+        {
+            let slot = scope
+                .get_slot_mut::<Rc<RefCell<ExecutionStatus>>>()
+                .expect("failed to get mutable access to status slot");
+
+            let mut status = slot.borrow_mut();
+            *status.selection_range.write() = TextRange::new(start, end);
+            // the cursor is placed at the given end, which comes first for backward selections
+            status.cursor = end;
+        }
+
+        let undefined = v8::undefined(scope).into();
+        rv.set(undefined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     extern crate tempfile;
-    use std::io::prelude::*;
+    use std::{fs, io::prelude::*};
 
     fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
     }
 
+    fn module_executor(source: &str) -> eyre::Result<Executor> {
+        Executor::with_options(
+            source,
+            ExecutorOptions {
+                module: true,
+                ..Default::default()
+            },
+        )
+    }
+
     #[test]
     fn test_error_new_big_string() {
         init();
@@ -858,128 +1732,1307 @@ mod tests {
     }
 
     #[test]
-    fn test_error_require_internal_script() {
+    fn test_selection_offsets() {
         init();
-        let source = r#"function main() {
-            let foo = require("@boop/non-existant");
+        let source = r#"function main(state) {
+            state.postInfo([state.selectionStart, state.selectionEnd, state.cursor].join(","));
         }"#;
 
-        assert_eq!(
-            Executor::new(source)
-                .unwrap()
-                .execute("full_text", None)
-                .unwrap_err()
-                .downcast::<ExecutorError>()
-                .unwrap(),
-            ExecutorError::Execute(JSException {
-                exception_str: "Error: No internal script with path \"@boop/non-existant.js\""
-                    .to_string(),
-                resource_name: Some("undefined".to_string()),
-                source_line: Some(
-                    "            let foo = require(\"@boop/non-existant\");".to_string()
-                ),
-                line_number: Some(2),
-                columns: Some((22, 23))
-            }),
-        );
+        let mut executor = Executor::new(source).unwrap();
+
+        let status = executor
+            .execute("foo bar", Some(TextRange::new(4, 7)))
+            .unwrap();
+        assert_eq!(status.info(), Some(&"4,7,7".to_string()));
+
+        // offsets are UTF-16 code units
+        let status = executor
+            .execute("😁 bar", Some(TextRange::new(3, 6)))
+            .unwrap();
+        assert_eq!(status.info(), Some(&"3,6,6".to_string()));
+
+        let status = executor.execute("foo bar", None).unwrap();
+        assert_eq!(status.info(), Some(&"7,7,7".to_string()));
     }
 
     #[test]
-    fn test_error_require_script_missing() {
+    fn test_backward_selection_cursor() {
         init();
-        let source = r#"function main() {
-            let foo = require("this-script-does-not-exist.js");
+        let source = r#"function main(state) {
+            const before = state.cursor;
+            state.select(5, 2);
+            state.postInfo([before, state.selectionStart, state.selectionEnd, state.cursor].join(","));
+        }"#;
+
+        let mut request = ExecutionRequest::new("foo bar", Some(TextRange::new(4, 7)));
+        request.cursor = 4;
+
+        let status = Executor::new(source)
+            .unwrap()
+            .execute_request(&request)
+            .unwrap();
+        assert_eq!(status.info(), Some(&"4,2,5,2".to_string()));
+    }
+
+    #[test]
+    fn test_select_after_replacement() {
+        init();
+        let source = r#"function main(state) {
+            state.fullText = state.fullText.toUpperCase();
+            state.select(4, 7);
         }"#;
 
         assert_eq!(
             Executor::new(source)
                 .unwrap()
-                .execute("full_text", None)
-                .unwrap_err()
-                .downcast::<ExecutorError>()
-                .unwrap(),
-            ExecutorError::Execute(JSException {
-                exception_str: "Error: Could not open \"this-script-does-not-exist.js\""
-                    .to_string(),
-                resource_name: Some("undefined".to_string()),
-                source_line: Some(
-                    "            let foo = require(\"this-script-does-not-exist.js\");".to_string()
-                ),
-                line_number: Some(2),
-                columns: Some((22, 23))
-            }),
+                .execute("foo bar", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::WithSelection(
+                Box::new(TextReplacement::Full("FOO BAR".to_string())),
+                TextRange::new(4, 7)
+            )
         );
     }
 
     #[test]
-    fn test_error_require_script_compile_error() {
+    fn test_cursor_collapses_selection() {
         init();
-
-        let mut file = tempfile::Builder::new().suffix(".js").tempfile().unwrap();
-        write!(file, r#"┻━┻ ︵ ¯\(ツ)/¯ ︵ ┻━┻"#).unwrap();
-
-        let file_name = file.path().file_name().unwrap().to_str().unwrap();
-
-        let source = format!(
-            "function main() {{
-                let foo = require(\"{}\");
-            }}",
-            file_name
-        );
+        let source = r#"function main(state) {
+            state.cursor = 1;
+        }"#;
 
         assert_eq!(
-            Executor::new(&source)
+            Executor::new(source)
                 .unwrap()
-                .execute("full_text", None)
-                .unwrap_err()
-                .downcast::<ExecutorError>()
-                .unwrap(),
-            ExecutorError::Execute(JSException {
-                exception_str: "SyntaxError: Invalid or unexpected token".to_string(),
-                resource_name: Some("undefined".to_string()),
-                source_line: Some(r#"┻━┻ ︵ ¯\(ツ)/¯ ︵ ┻━┻"#.to_string()),
-                line_number: Some(17),
-                columns: Some((0, 0))
-            }),
+                .execute("foo bar", Some(TextRange::new(4, 7)))
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::WithSelection(Box::new(TextReplacement::None), TextRange::new(1, 1))
         );
     }
 
     #[test]
-    fn test_error_require_script_execute_error() {
+    fn test_lines() {
+        init();
+        let source = r#"function main(state) {
+            state.lines = state.lines.reverse();
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("a\nb\nc", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("c\nb\na".to_string())
+        );
+    }
+
+    #[test]
+    fn test_error_unsupported_api() {
+        init();
+        let source = r#"function main() {}"#;
+
+        assert_eq!(
+            Executor::with_options(
+                source,
+                ExecutorOptions {
+                    api: API_VERSION + 1,
+                    ..Default::default()
+                }
+            )
+            .unwrap_err()
+            .downcast::<ExecutorError>()
+            .unwrap(),
+            ExecutorError::UnsupportedApi(API_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn test_api_version_gating() {
+        init();
+        let source = r#"function main(state) {
+            state.postInfo(state.apiVersion + "," + typeof state.cursor + "," + typeof state.select);
+        }"#;
+
+        let status = Executor::with_options(
+            source,
+            ExecutorOptions {
+                api: 1,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .execute("foo", None)
+        .unwrap();
+        assert_eq!(
+            status.info(),
+            Some(&format!("{},undefined,undefined", API_VERSION))
+        );
+
+        let status = Executor::with_options(
+            source,
+            ExecutorOptions {
+                api: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .execute("foo", None)
+        .unwrap();
+        assert_eq!(
+            status.info(),
+            Some(&format!("{},number,function", API_VERSION))
+        );
+    }
+
+    #[test]
+    fn test_api_version_precedence() {
+        init();
+        let source = r#"function main(state) {
+            state.insert("bar");
+            state.fullText = "baz";
+        }"#;
+
+        assert_eq!(
+            Executor::with_options(
+                source,
+                ExecutorOptions {
+                    api: 1,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+            .execute("foo", None)
+            .unwrap()
+            .into_replacement(),
+            TextReplacement::Insert(vec!["bar".to_string()])
+        );
+
+        assert_eq!(
+            Executor::with_options(
+                source,
+                ExecutorOptions {
+                    api: 2,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+            .execute("foo", None)
+            .unwrap()
+            .into_replacement(),
+            TextReplacement::Full("baz".to_string())
+        );
+    }
+
+    fn status_with(
+        api: u32,
+        is_text_selected: bool,
+        insert: bool,
+        full_text: bool,
+        selection: bool,
+        text: bool,
+    ) -> ExecutionStatus {
+        let mut status = ExecutionStatus {
+            api,
+            is_text_selected,
+            ..Default::default()
+        };
+
+        if insert {
+            status.insert.push("insert".to_string());
+        }
+        if full_text {
+            *status.full_text.write() = "full_text".to_string();
+        }
+        if selection {
+            *status.selection.write() = "selection".to_string();
+        }
+        if text {
+            *status.text.write() = "text".to_string();
+        }
+
+        status
+    }
+
+    #[test]
+    fn test_legacy_replacement_precedence() {
+        let cases = vec![
+            (
+                status_with(1, true, true, true, true, true),
+                TextReplacement::Insert(vec!["insert".to_string()]),
+            ),
+            (
+                status_with(1, true, false, true, true, true),
+                TextReplacement::Full("full_text".to_string()),
+            ),
+            (
+                status_with(1, true, false, false, true, true),
+                TextReplacement::Selection("selection".to_string()),
+            ),
+            (
+                status_with(1, true, false, false, false, true),
+                TextReplacement::Selection("text".to_string()),
+            ),
+            (
+                status_with(1, false, false, false, false, true),
+                TextReplacement::Full("text".to_string()),
+            ),
+            (
+                status_with(1, false, false, false, false, false),
+                TextReplacement::None,
+            ),
+        ];
+
+        for (status, expected) in cases {
+            assert_eq!(status.into_replacement(), expected);
+        }
+    }
+
+    #[test]
+    fn test_replacement_precedence() {
+        let cases = vec![
+            (
+                status_with(2, true, true, true, true, true),
+                TextReplacement::Full("full_text".to_string()),
+            ),
+            (
+                status_with(2, true, true, false, true, true),
+                TextReplacement::Selection("selection".to_string()),
+            ),
+            (
+                status_with(2, true, true, false, false, true),
+                TextReplacement::Selection("text".to_string()),
+            ),
+            (
+                status_with(2, false, true, false, false, true),
+                TextReplacement::Full("text".to_string()),
+            ),
+            (
+                status_with(2, false, true, false, false, false),
+                TextReplacement::Insert(vec!["insert".to_string()]),
+            ),
+            (
+                status_with(2, false, false, false, false, false),
+                TextReplacement::None,
+            ),
+        ];
+
+        for (status, expected) in cases {
+            assert_eq!(status.into_replacement(), expected);
+        }
+    }
+
+    #[test]
+    fn test_result_takes_precedence() {
+        let mut status = status_with(2, true, true, true, true, true);
+        status.result = Some(TextReplacement::InsertAt("result".to_string(), 2));
+
+        assert_eq!(
+            status.into_replacement(),
+            TextReplacement::InsertAt("result".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn test_result_object() {
+        init();
+
+        let cases = vec![
+            (
+                r#"return { replace: "selection", text: "X" };"#,
+                TextReplacement::Selection("X".to_string()),
+            ),
+            (
+                r#"return { replace: "text", text: "X" };"#,
+                TextReplacement::Selection("X".to_string()),
+            ),
+            (
+                r#"state.insert("ignored"); return { replace: "fullText", text: "X" };"#,
+                TextReplacement::Full("X".to_string()),
+            ),
+            (
+                r#"return { insert: "X" };"#,
+                TextReplacement::Insert(vec!["X".to_string()]),
+            ),
+            (
+                r#"return { insert: "X", at: 1 };"#,
+                TextReplacement::InsertAt("X".to_string(), 1),
+            ),
+            (
+                r#"return { insert: "X", select: [0, 1] };"#,
+                TextReplacement::WithSelection(
+                    Box::new(TextReplacement::Insert(vec!["X".to_string()])),
+                    TextRange::new(0, 1),
+                ),
+            ),
+            (
+                r#"state.text = "Y"; return "not a result";"#,
+                TextReplacement::Selection("Y".to_string()),
+            ),
+        ];
+
+        for (body, expected) in cases {
+            let source = format!("function main(state) {{ {} }}", body);
+
+            assert_eq!(
+                Executor::new(&source)
+                    .unwrap()
+                    .execute("foo bar", Some(TextRange::new(4, 7)))
+                    .unwrap()
+                    .into_replacement(),
+                expected,
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn test_result_object_ignored_by_legacy_api() {
+        init();
+        let source = r#"function main(state) {
+            return { replace: "fullText", text: "X" };
+        }"#;
+
+        assert_eq!(
+            Executor::with_options(
+                source,
+                ExecutorOptions {
+                    api: 1,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+            .execute("foo", None)
+            .unwrap()
+            .into_replacement(),
+            TextReplacement::None
+        );
+    }
+
+    #[test]
+    fn test_error_invalid_result() {
+        init();
+        let source = r#"function main(state) {
+            return { replace: "everything", text: "X" };
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::InvalidResult(
+                "can't replace \"everything\", expected fullText, selection or text".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_import_module() {
         init();
 
         let mut file = tempfile::Builder::new().suffix(".js").tempfile().unwrap();
         write!(
             file,
-            r#"(function() {{ throw "༼ﾉຈل͜ຈ༽ﾉ︵┻━┻"; return 123 }})()"#
+            "export function shout(text) {{ return text.toUpperCase(); }}"
         )
         .unwrap();
 
         let file_name = file.path().file_name().unwrap().to_str().unwrap();
 
         let source = format!(
-            "function main() {{
-                let foo = require(\"{}\");
+            "import {{ shout }} from \"./{}\";
+
+            export function main(state) {{
+                state.text = shout(state.text);
             }}",
             file_name
         );
 
         assert_eq!(
-            Executor::new(&source)
+            module_executor(&source)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("FOO".to_string())
+        );
+    }
+
+    #[test]
+    fn test_classic_script_mentioning_import() {
+        init();
+        let source = r#"/*
+        import and export lines in comments don't make this a module
+        */
+        const usage = `
+        import text from "clipboard"
+        `;
+
+        function main(state) {
+            state.text = "classic";
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("classic".to_string())
+        );
+    }
+
+    #[test]
+    fn test_import_commonjs_module() {
+        init();
+
+        let mut file = tempfile::Builder::new().suffix(".cjs").tempfile().unwrap();
+        write!(
+            file,
+            "module.exports = {{ shout: (text) => text.toUpperCase() }};"
+        )
+        .unwrap();
+
+        let file_name = file.path().file_name().unwrap().to_str().unwrap();
+
+        let source = format!(
+            "import lib from \"{}\";
+
+            globalThis.main = function (state) {{
+                state.text = lib.shout(state.text);
+            }}",
+            file_name
+        );
+
+        assert_eq!(
+            module_executor(&source)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("FOO".to_string())
+        );
+    }
+
+    #[test]
+    fn test_error_import_missing() {
+        init();
+        let source = r#"import foo from "./this-module-does-not-exist.js";
+        export function main() {}"#;
+
+        match module_executor(source)
+            .unwrap_err()
+            .downcast::<ExecutorError>()
+            .unwrap()
+        {
+            ExecutorError::Execute(exception) => assert_eq!(
+                exception.exception_str,
+                "Error: Could not open \"./this-module-does-not-exist.js\""
+            ),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_error_require_internal_script() {
+        init();
+        let source = r#"function main() {
+            let foo = require("@boop/non-existant");
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
                 .unwrap()
                 .execute("full_text", None)
                 .unwrap_err()
                 .downcast::<ExecutorError>()
                 .unwrap(),
             ExecutorError::Execute(JSException {
-                exception_str: "༼ﾉຈل\u{35c}ຈ༽ﾉ︵┻━┻".to_string(),
+                exception_str: "Error: No internal script with path \"@boop/non-existant.js\""
+                    .to_string(),
                 resource_name: Some("undefined".to_string()),
                 source_line: Some(
-                    "(function() { throw \"༼ﾉຈل\u{35c}ຈ༽ﾉ︵┻━┻\"; return 123 })()".to_string()
+                    "            let foo = require(\"@boop/non-existant\");".to_string()
                 ),
-                line_number: Some(17),
-                columns: Some((14, 15))
-            })
-        )
+                line_number: Some(2),
+                columns: Some((22, 23))
+            }),
+        );
+    }
+
+    #[test]
+    fn test_error_require_script_missing() {
+        init();
+        let source = r#"function main() {
+            let foo = require("this-script-does-not-exist.js");
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("full_text", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::Execute(JSException {
+                exception_str: "Error: Could not open \"this-script-does-not-exist.js\""
+                    .to_string(),
+                resource_name: Some("undefined".to_string()),
+                source_line: Some(
+                    "            let foo = require(\"this-script-does-not-exist.js\");".to_string()
+                ),
+                line_number: Some(2),
+                columns: Some((22, 23))
+            }),
+        );
+    }
+
+    #[test]
+    fn test_error_require_traversal() {
+        init();
+        let source = r#"function main() {
+            let foo = require("../../../../../../etc/passwd");
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("full_text", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::RequireDenied("../../../../../../etc/passwd".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_error_require_absolute_path() {
+        init();
+        let source = r#"function main() {
+            let foo = require("/etc/passwd");
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("full_text", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::RequireDenied("/etc/passwd".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_error_after_caught_require_denial() {
+        init();
+        let source = r#"function main(state) {
+            try {
+                require("/etc/passwd");
+            } catch (err) {}
+
+            if (state.text === "throw") {
+                throw new Error("something else");
+            }
+        }"#;
+
+        let mut executor = Executor::new(source).unwrap();
+
+        // the denial was caught, the error is the one thrown afterwards
+        match executor
+            .execute("throw", None)
+            .unwrap_err()
+            .downcast::<ExecutorError>()
+            .unwrap()
+        {
+            ExecutorError::Execute(exception) => {
+                assert_eq!(exception.exception_str, "Error: something else")
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert!(executor.execute("", None).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_error_require_symlink() {
+        init();
+
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", dir.path().join("passwd.js")).unwrap();
+
+        let dir_name = dir.path().file_name().unwrap().to_str().unwrap();
+        let specifier = format!("{}/passwd", dir_name);
+
+        let source = format!(
+            "function main() {{
+                let foo = require(\"{}\");
+            }}",
+            specifier
+        );
+
+        assert_eq!(
+            Executor::new(&source)
+                .unwrap()
+                .execute("full_text", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::RequireDenied(specifier),
+        );
+    }
+
+    #[test]
+    fn test_error_import_traversal() {
+        init();
+        let source = r#"import passwd from "../../../../../../etc/passwd";
+
+        export function main() {}"#;
+
+        assert_eq!(
+            module_executor(source)
+                .err()
+                .unwrap()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::RequireDenied("../../../../../../etc/passwd".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_error_require_script_compile_error() {
+        init();
+
+        let mut file = tempfile::Builder::new().suffix(".js").tempfile().unwrap();
+        write!(file, r#"┻━┻ ︵ ¯\(ツ)/¯ ︵ ┻━┻"#).unwrap();
+
+        let file_name = file.path().file_name().unwrap().to_str().unwrap();
+
+        let source = format!(
+            "function main() {{
+                let foo = require(\"{}\");
+            }}",
+            file_name
+        );
+
+        assert_eq!(
+            Executor::new(&source)
+                .unwrap()
+                .execute("full_text", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::Execute(JSException {
+                exception_str: "SyntaxError: Invalid or unexpected token".to_string(),
+                resource_name: Some("undefined".to_string()),
+                source_line: Some(r#"┻━┻ ︵ ¯\(ツ)/¯ ︵ ┻━┻"#.to_string()),
+                line_number: Some(17),
+                columns: Some((0, 0))
+            }),
+        );
+    }
+
+    #[test]
+    fn test_error_require_script_execute_error() {
+        init();
+
+        let mut file = tempfile::Builder::new().suffix(".js").tempfile().unwrap();
+        write!(
+            file,
+            r#"(function() {{ throw "༼ﾉຈل͜ຈ༽ﾉ︵┻━┻"; return 123 }})()"#
+        )
+        .unwrap();
+
+        let file_name = file.path().file_name().unwrap().to_str().unwrap();
+
+        let source = format!(
+            "function main() {{
+                let foo = require(\"{}\");
+            }}",
+            file_name
+        );
+
+        assert_eq!(
+            Executor::new(&source)
+                .unwrap()
+                .execute("full_text", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::Execute(JSException {
+                exception_str: "༼ﾉຈل\u{35c}ຈ༽ﾉ︵┻━┻".to_string(),
+                resource_name: Some("undefined".to_string()),
+                source_line: Some(
+                    "(function() { throw \"༼ﾉຈل\u{35c}ຈ༽ﾉ︵┻━┻\"; return 123 })()".to_string()
+                ),
+                line_number: Some(17),
+                columns: Some((14, 15))
+            })
+        )
+    }
+
+    #[test]
+    fn test_require_preloaded_library() {
+        init();
+        let source = r#"function main(state) {
+            const base64 = require("@boop/base64");
+            state.text = [typeof __boopLibraries, base64 === require("@boop/base64.js")].join();
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("undefined,true".to_string())
+        );
+    }
+
+    #[test]
+    fn test_import_preloaded_library() {
+        init();
+        let source = r#"import base64 from "@boop/base64";
+
+        globalThis.main = function (state) {
+            state.text = [typeof base64, base64 === require("@boop/base64")].join();
+        }"#;
+
+        // the import shares the preloaded exports with require, without a snapshot each of them
+        // compiles the library
+        assert_eq!(
+            module_executor(source)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full(format!("object,{}", cfg!(boop_snapshot)))
+        );
+    }
+
+    #[test]
+    fn test_require_native() {
+        init();
+        let source = r#"function main(state) {
+            const native = require("@boop/native");
+            state.text = [
+                native.sha256(state.text),
+                native.base64Decode(native.base64Encode(state.text)),
+                native.hexEncode(new Uint8Array([0, 255])),
+                native.randomBytes(8).length,
+            ].join();
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("abc", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad,abc,00ff,8"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_native_decode_bytes() {
+        init();
+        let source = r#"function main(state) {
+            const native = require("@boop/native");
+            const bytes = native.hexDecodeBytes("00ff");
+            let error = "";
+            try {
+                native.hexDecode("00ff");
+            } catch (err) {
+                error = err.message;
+            }
+            state.text = [
+                bytes instanceof Uint8Array,
+                Array.from(bytes),
+                Array.from(native.base64DecodeBytes("AP8=")),
+                Array.from(native.base64UrlDecodeBytes("AP8")),
+                error,
+            ].join();
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full(
+                "true,0,255,0,255,0,255,decoded bytes aren't valid UTF-8, use the Bytes variant to get a Uint8Array"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_import_native() {
+        init();
+        let source = r#"import { md5, percentEncode } from "@boop/native";
+
+        export function main(state) {
+            state.text = md5(state.text) + " " + percentEncode("a b");
+        }"#;
+
+        assert_eq!(
+            module_executor(source)
+                .unwrap()
+                .execute("abc", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("900150983cd24fb0d6963f7d28e17f72 a%20b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_require_cache() {
+        init();
+
+        let mut file = tempfile::Builder::new().suffix(".js").tempfile().unwrap();
+        write!(
+            file,
+            "globalThis.loads = (globalThis.loads || 0) + 1; module.exports = {{ loads }};"
+        )
+        .unwrap();
+
+        let file_name = file.path().file_name().unwrap().to_str().unwrap();
+
+        let source = format!(
+            "function main(state) {{
+                require(\"{0}\");
+                state.text = String(require(\"{0}\").loads + globalThis.loads);
+            }}",
+            file_name
+        );
+
+        let mut executor = Executor::new(&source).unwrap();
+        assert_eq!(
+            executor.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("2".to_string())
+        );
+        // the cache lives as long as the isolate
+        assert_eq!(
+            executor.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_require_relative() {
+        init();
+
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(
+            dir.path().join("library.js"),
+            "module.exports = require(\"./nested/helper\").shout;",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("nested").join("helper.js"),
+            "module.exports.shout = (text) => require(\"../suffix\").suffix(text.toUpperCase());",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("suffix.js"),
+            "exports.suffix = (text) => text + \"!\";",
+        )
+        .unwrap();
+
+        let dir_name = dir.path().file_name().unwrap().to_str().unwrap();
+
+        let source = format!(
+            "function main(state) {{
+                state.text = require(\"{}/library\")(state.text);
+            }}",
+            dir_name
+        );
+
+        assert_eq!(
+            Executor::new(&source)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("FOO!".to_string())
+        );
+    }
+
+    #[test]
+    fn test_require_circular() {
+        init();
+
+        // a requires b at the top level before it is done, b requires a back
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        fs::write(
+            dir.path().join("a.js"),
+            "exports.before = \"a\"; const b = require(\"./b\"); exports.after = \"a\"; exports.seen = b.seen;",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.js"),
+            "const a = require(\"./a\"); exports.seen = JSON.stringify(a); exports.a = a;",
+        )
+        .unwrap();
+
+        let dir_name = dir.path().file_name().unwrap().to_str().unwrap();
+
+        // b sees the exports a defined before requiring it, and later gets the same object
+        let source = format!(
+            "function main(state) {{
+                const a = require(\"{0}/a\");
+                state.text = [a.seen, a.after, require(\"{0}/b\").a === a].join(\" \");
+            }}",
+            dir_name
+        );
+
+        assert_eq!(
+            Executor::new(&source)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("{\"before\":\"a\"} a true".to_string())
+        );
+    }
+
+    #[test]
+    fn test_host_functions_require_permission() {
+        init();
+        let source = r#"function main(state) {
+            state.text = [typeof readClipboard, typeof storage, typeof readFile, typeof exec].join();
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("undefined,undefined,undefined,undefined".to_string())
+        );
+
+        let options = ExecutorOptions {
+            permissions: vec![Permission::FilesRead, Permission::Exec],
+            ..Default::default()
+        };
+        assert_eq!(
+            Executor::with_options(source, options)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("undefined,undefined,function,function".to_string())
+        );
+    }
+
+    #[test]
+    fn test_clipboard() {
+        init();
+        let source = r#"function main(state) {
+            state.text = readClipboard();
+            writeClipboard(state.text.toUpperCase());
+        }"#;
+        let options = ExecutorOptions {
+            permissions: vec![Permission::Clipboard],
+            ..Default::default()
+        };
+
+        let mut request = ExecutionRequest::new("", None);
+        request.clipboard = Some("foo".to_string());
+
+        let status = Executor::with_options(source, options)
+            .unwrap()
+            .execute_request(&request)
+            .unwrap();
+        assert_eq!(status.clipboard(), Some(&"FOO".to_string()));
+        assert_eq!(
+            status.into_replacement(),
+            TextReplacement::Full("foo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_storage() {
+        init();
+        let source = r#"function main(state) {
+            const count = Number(storage.get("count") || 0) + 1;
+            storage.set("count", count);
+            state.text = count;
+        }"#;
+
+        let dir = tempfile::Builder::new().tempdir().unwrap();
+        let options = ExecutorOptions {
+            permissions: vec![Permission::Storage],
+            storage_path: Some(dir.path().join("storage").join("Counter.json")),
+            ..Default::default()
+        };
+
+        // values outlive the executor
+        for count in 1..3 {
+            assert_eq!(
+                Executor::with_options(source, options.clone())
+                    .unwrap()
+                    .execute("", None)
+                    .unwrap()
+                    .into_replacement(),
+                TextReplacement::Full(count.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_file() {
+        init();
+
+        let mut file = tempfile::Builder::new().suffix(".txt").tempfile().unwrap();
+        write!(file, "file contents").unwrap();
+
+        let source = format!(
+            "function main(state) {{
+                state.text = readFile({:?});
+            }}",
+            file.path().to_str().unwrap()
+        );
+        let options = ExecutorOptions {
+            permissions: vec![Permission::FilesRead],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Executor::with_options(&source, options)
+                .unwrap()
+                .execute("", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("file contents".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_exec() {
+        init();
+        let source = r#"function main(state) {
+            state.text = exec("tr", ["a-z", "A-Z"], state.text);
+        }"#;
+        let options = ExecutorOptions {
+            permissions: vec![Permission::Exec],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Executor::with_options(source, options)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("FOO".to_string())
+        );
+    }
+
+    #[test]
+    fn test_error_out_of_memory() {
+        init();
+        let source = r#"function main(state) {
+            const strings = [];
+            while (true) {
+                strings.push(state.text.repeat(strings.length));
+            }
+        }"#;
+        let options = ExecutorOptions {
+            heap_limit: Some(32 * 1024 * 1024),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Executor::with_options(source, options)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::OutOfMemory
+        );
+    }
+
+    #[test]
+    fn test_bytes() {
+        init();
+        let source = r#"function main(state) {
+            state.text = Array.from(state.bytes).join(",");
+        }"#;
+
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute("hé", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("104,195,169".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_bytes() {
+        init();
+        let cases = vec![
+            (
+                "state.bytes = new Uint8Array([0, 255]);",
+                None,
+                TextReplacement::FullBytes(vec![0, 255]),
+            ),
+            (
+                "state.bytes = [1, 2, 3];",
+                None,
+                TextReplacement::FullBytes(vec![1, 2, 3]),
+            ),
+            (
+                r#"state.bytes = "foo";"#,
+                None,
+                TextReplacement::FullBytes(b"foo".to_vec()),
+            ),
+            (
+                "state.bytes = new Uint8Array([0]);",
+                Some(TextRange::new(0, 1)),
+                TextReplacement::SelectionBytes(vec![0]),
+            ),
+        ];
+
+        for (source, selection, expected) in cases {
+            let source = format!("function main(state) {{ {} }}", source);
+            assert_eq!(
+                Executor::new(&source)
+                    .unwrap()
+                    .execute("foo", selection)
+                    .unwrap()
+                    .into_replacement(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_input() {
+        init();
+        let source = r#"function main(state) {
+            state.bytes = state.bytes.map(byte => byte ^ 0xff);
+        }"#;
+
+        let mut request = ExecutionRequest::new("00000000  00 ff", Some(TextRange::new(0, 4)));
+        request.bytes = Some(vec![0, 255]);
+
+        // binary buffers are always replaced as a whole
+        assert_eq!(
+            Executor::new(source)
+                .unwrap()
+                .execute_request(&request)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::FullBytes(vec![255, 0])
+        );
+    }
+
+    #[test]
+    fn test_bytes_requires_api_3() {
+        init();
+        let source = r#"function main(state) {
+            state.text = typeof state.bytes;
+        }"#;
+        let options = ExecutorOptions {
+            api: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Executor::with_options(source, options)
+                .unwrap()
+                .execute("foo", None)
+                .unwrap()
+                .into_replacement(),
+            TextReplacement::Full("undefined".to_string())
+        );
+    }
+
+    #[test]
+    fn test_state_clipboard() {
+        init();
+        let source = r#"function main(state) {
+            state.text = state.clipboard;
+        }"#;
+
+        let mut request = ExecutionRequest::new("", None);
+        request.clipboard = Some("foo".to_string());
+
+        let cases = vec![
+            (vec![Permission::Clipboard], "foo"),
+            (Vec::new(), "undefined"),
+        ];
+
+        for (permissions, expected) in cases {
+            let options = ExecutorOptions {
+                permissions,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                Executor::with_options(source, options)
+                    .unwrap()
+                    .execute_request(&request)
+                    .unwrap()
+                    .into_replacement(),
+                TextReplacement::Full(expected.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_replacement() {
+        let request = ExecutionRequest::new("foo bär baz", Some(TextRange::new(4, 7)));
+        let cases = vec![
+            (TextReplacement::Full("qux".to_string()), "qux"),
+            (TextReplacement::Selection("qux".to_string()), "foo qux baz"),
+            (
+                TextReplacement::Insert(vec!["q".to_string(), "ux".to_string()]),
+                "foo qux baz",
+            ),
+            (
+                TextReplacement::InsertAt("qux".to_string(), 3),
+                "fooqux bär baz",
+            ),
+            (
+                TextReplacement::WithSelection(
+                    Box::new(TextReplacement::Full("qux".to_string())),
+                    TextRange::new(0, 1),
+                ),
+                "qux",
+            ),
+            (TextReplacement::None, "foo bär baz"),
+        ];
+
+        for (replacement, expected) in cases {
+            assert_eq!(replacement.apply(&request), expected.as_bytes());
+        }
+
+        let request = ExecutionRequest::new("foo", None);
+        assert_eq!(
+            TextReplacement::Insert(vec!["bar".to_string()]).apply(&request),
+            b"foobar"
+        );
+        assert_eq!(
+            TextReplacement::Selection("bar".to_string()).apply(&request),
+            b"foo"
+        );
     }
 }
@@ -0,0 +1,298 @@
+use crate::{modules::throw_error, permission::Permission};
+use eyre::{ContextCompat, Result};
+use rusty_v8 as v8;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    convert::TryFrom,
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    rc::Rc,
+    thread,
+};
+
+// state shared with the host functions, stored in a slot
+#[derive(Debug, Default)]
+pub struct HostState {
+    // JSON file storage is persisted to
+    pub storage_path: Option<PathBuf>,
+    // clipboard contents when execution began
+    pub clipboard: Option<String>,
+    // text the script wrote to the clipboard
+    pub clipboard_written: Option<String>,
+}
+
+// installs the host functions of each granted permission on the global object
+pub fn install(
+    scope: &mut v8::HandleScope<'_>,
+    global: v8::Local<'_, v8::Object>,
+    permissions: &[Permission],
+) -> Result<()> {
+    for permission in permissions {
+        info!("installing host functions for {}", permission);
+
+        match permission {
+            Permission::Clipboard => {
+                set_function(scope, global, "readClipboard", read_clipboard)?;
+                set_function(scope, global, "writeClipboard", write_clipboard)?;
+            }
+            Permission::Storage => {
+                let storage = v8::Object::new(scope);
+                set_function(scope, storage, "get", storage_get)?;
+                set_function(scope, storage, "set", storage_set)?;
+                set_function(scope, storage, "remove", storage_remove)?;
+
+                let storage_key = v8::String::new(scope, "storage")
+                    .wrap_err("failed to create JS string 'storage'")?;
+                global
+                    .set(scope, storage_key.into(), storage.into())
+                    .wrap_err("failed to set 'storage'")?;
+            }
+            Permission::FilesRead => set_function(scope, global, "readFile", read_file)?,
+            Permission::Exec => set_function(scope, global, "exec", exec)?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn set_function(
+    scope: &mut v8::HandleScope<'_>,
+    object: v8::Local<'_, v8::Object>,
+    name: &str,
+    callback: impl v8::MapFnTo<v8::FunctionCallback>,
+) -> Result<()> {
+    let key = v8::String::new(scope, name)
+        .wrap_err_with(|| format!("failed to create JS string '{}'", name))?;
+    let function = v8::Function::new(scope, callback)
+        .wrap_err_with(|| format!("failed to create {} function", name))?;
+    object
+        .set(scope, key.into(), function.into())
+        .wrap_err_with(|| format!("failed to set '{}'", name))?;
+
+    Ok(())
+}
+
+fn host_state(scope: &mut v8::HandleScope<'_>) -> Rc<RefCell<HostState>> {
+    scope
+        .get_slot::<Rc<RefCell<HostState>>>()
+        .expect("failed to get host slot")
+        .clone()
+}
+
+fn string_arg(
+    scope: &mut v8::HandleScope<'_>,
+    args: &v8::FunctionCallbackArguments<'_>,
+    index: i32,
+) -> Option<String> {
+    let value = args.get(index);
+    if value.is_null_or_undefined() {
+        return None;
+    }
+
+    value
+        .to_string(scope)
+        .map(|value| value.to_rust_string_lossy(scope))
+}
+
+fn set_string(scope: &mut v8::HandleScope<'_>, rv: &mut v8::ReturnValue<'_>, value: &str) {
+    match v8::String::new(scope, value) {
+        Some(value) => rv.set(value.into()),
+        None => throw_error(scope, "failed to create JS string, value is too long"),
+    }
+}
+
+fn read_clipboard(
+    scope: &mut v8::HandleScope<'_>,
+    _args: v8::FunctionCallbackArguments<'_>,
+    mut rv: v8::ReturnValue<'_>,
+) {
+    let clipboard = host_state(scope).borrow().clipboard.clone();
+    set_string(scope, &mut rv, &clipboard.unwrap_or_default());
+}
+
+fn write_clipboard(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    _rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0).unwrap_or_default();
+
+    let host_state = host_state(scope);
+    let mut host_state = host_state.borrow_mut();
+    host_state.clipboard = Some(text.clone());
+    host_state.clipboard_written = Some(text);
+}
+
+fn load_storage(path: Option<&Path>) -> Result<BTreeMap<String, String>, String> {
+    let path = path.ok_or("storage is not available")?;
+
+    match fs::read_to_string(path) {
+        Ok(json) => serde_jsonrc::from_str(&json).map_err(|err| err.to_string()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn save_storage(path: Option<&Path>, storage: &BTreeMap<String, String>) -> Result<(), String> {
+    let path = path.ok_or("storage is not available")?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+
+    let json = serde_jsonrc::to_string_pretty(storage).map_err(|err| err.to_string())?;
+    fs::write(path, json).map_err(|err| err.to_string())
+}
+
+fn storage_get(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    mut rv: v8::ReturnValue<'_>,
+) {
+    let key = string_arg(scope, &args, 0).unwrap_or_default();
+    let path = host_state(scope).borrow().storage_path.clone();
+
+    match load_storage(path.as_deref()) {
+        Ok(storage) => {
+            if let Some(value) = storage.get(&key) {
+                set_string(scope, &mut rv, value);
+            }
+        }
+        Err(err) => throw_error(scope, &format!("Failed to read storage: {}", err)),
+    }
+}
+
+fn storage_set(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    _rv: v8::ReturnValue<'_>,
+) {
+    let key = string_arg(scope, &args, 0).unwrap_or_default();
+    let value = string_arg(scope, &args, 1).unwrap_or_default();
+    let path = host_state(scope).borrow().storage_path.clone();
+
+    let result = load_storage(path.as_deref()).and_then(|mut storage| {
+        storage.insert(key, value);
+        save_storage(path.as_deref(), &storage)
+    });
+
+    if let Err(err) = result {
+        throw_error(scope, &format!("Failed to write storage: {}", err));
+    }
+}
+
+fn storage_remove(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    _rv: v8::ReturnValue<'_>,
+) {
+    let key = string_arg(scope, &args, 0).unwrap_or_default();
+    let path = host_state(scope).borrow().storage_path.clone();
+
+    let result = load_storage(path.as_deref()).and_then(|mut storage| {
+        storage.remove(&key);
+        save_storage(path.as_deref(), &storage)
+    });
+
+    if let Err(err) = result {
+        throw_error(scope, &format!("Failed to write storage: {}", err));
+    }
+}
+
+// paths starting with ~/ are relative to the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(path), Some(home)) => PathBuf::from(home).join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn read_file(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    mut rv: v8::ReturnValue<'_>,
+) {
+    let path = match string_arg(scope, &args, 0) {
+        Some(path) => path,
+        None => {
+            throw_error(scope, "readFile requires a path");
+            return;
+        }
+    };
+
+    info!("reading {}", path);
+
+    match fs::read_to_string(expand_home(&path)) {
+        Ok(contents) => set_string(scope, &mut rv, &contents),
+        Err(err) => throw_error(scope, &format!("Could not read \"{}\": {}", path, err)),
+    }
+}
+
+// exec(program, args, input) runs a program and returns its output, an exception is thrown if it
+// exits with a non-zero status
+fn exec(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    mut rv: v8::ReturnValue<'_>,
+) {
+    let program = match string_arg(scope, &args, 0) {
+        Some(program) => program,
+        None => {
+            throw_error(scope, "exec requires a program");
+            return;
+        }
+    };
+
+    let mut arguments = Vec::new();
+    if let Ok(array) = v8::Local::<v8::Array>::try_from(args.get(1)) {
+        for index in 0..array.length() {
+            if let Some(argument) = array
+                .get_index(scope, index)
+                .and_then(|argument| argument.to_string(scope))
+            {
+                arguments.push(argument.to_rust_string_lossy(scope));
+            }
+        }
+    }
+
+    let input = string_arg(scope, &args, 2);
+
+    info!("executing {} {:?}", program, arguments);
+
+    let output = Command::new(&program)
+        .args(&arguments)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+                // written from another thread so a full stdout pipe can't block it
+                thread::spawn(move || stdin.write_all(input.as_bytes()));
+            }
+            child.wait_with_output()
+        });
+
+    match output {
+        Ok(output) if output.status.success() => {
+            set_string(scope, &mut rv, &String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => throw_error(
+            scope,
+            &format!(
+                "\"{}\" failed with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ),
+        Err(err) => throw_error(scope, &format!("Could not run \"{}\": {}", program, err)),
+    }
+}
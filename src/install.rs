@@ -0,0 +1,692 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    config::{Config, InstalledPack},
+    script::{Script, ScriptOrigin},
+    scriptmap::ScriptMap,
+};
+
+// describes the contents of a script pack, found at the root of the archive or in its only folder
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    // script files, installed next to the user's own scripts
+    pub scripts: Vec<PathBuf>,
+    // files under lib/ the scripts require, installed to the lib folder of the user scripts
+    // directory so packs can share them
+    #[serde(default)]
+    pub lib: Vec<PathBuf>,
+}
+
+// a script that was validated, waiting to be written
+#[derive(Debug)]
+struct PackageScript {
+    name: String,
+    path: PathBuf,
+    source: String,
+}
+
+// a single script or a pack of scripts, read and validated before anything is installed
+#[derive(Debug)]
+pub struct Package {
+    pub manifest: Option<Manifest>,
+    scripts: Vec<PackageScript>,
+    // contents of the shared files by path relative to the user scripts directory
+    lib: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+// something already installed that an installation would replace
+#[derive(Debug, PartialEq)]
+pub enum Collision {
+    // a built-in or global script with the same name, uninstalling the pack brings it back
+    Overrides(String, ScriptOrigin),
+    // a user script with the same name, it has to be deleted first
+    Script(String),
+    // a file that wasn't installed by the same pack
+    File(PathBuf),
+}
+
+impl Collision {
+    // true if the installation can't go ahead
+    pub fn is_blocking(&self) -> bool {
+        !matches!(self, Collision::Overrides(..))
+    }
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Collision::Overrides(name, origin) => {
+                write!(
+                    f,
+                    "\"{}\" replaces the {} script with that name",
+                    name, origin
+                )
+            }
+            Collision::Script(name) => write!(f, "A user script named \"{}\" exists", name),
+            Collision::File(path) => write!(f, "{} already exists", path.display()),
+        }
+    }
+}
+
+impl Package {
+    // reads a .js script, or a .zip or .tar pack
+    pub fn open(path: &Path) -> Result<Package> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            Some("js") => {
+                let source = fs::read_to_string(path)
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+                let file_name = path
+                    .file_name()
+                    .ok_or_else(|| eyre!("{} has no file name", path.display()))?;
+
+                Package::script(source, Path::new(file_name))
+            }
+            Some("zip") => Package::pack(read_zip(path)?),
+            Some("tar") => Package::pack(read_tar(path)?),
+            _ => Err(eyre!("Expected a .js, .zip or .tar file")),
+        }
+        .wrap_err_with(|| format!("Failed to install {}", path.display()))
+    }
+
+    // path is where the script is installed, relative to the user scripts directory
+    pub fn script(source: String, path: &Path) -> Result<Package> {
+        Ok(Package {
+            manifest: None,
+            scripts: vec![PackageScript::validate(source, path)?],
+            lib: BTreeMap::new(),
+        })
+    }
+
+    // files holds the contents of the archive by path
+    fn pack(mut files: BTreeMap<PathBuf, Vec<u8>>) -> Result<Package> {
+        // packs are often zipped with their folder
+        let manifest_path = files
+            .keys()
+            .filter(|path| path.file_name() == Some(OsStr::new(MANIFEST_FILE)))
+            .min_by_key(|path| path.components().count())
+            .cloned()
+            .ok_or_else(|| eyre!("Pack has no {}", MANIFEST_FILE))?;
+        let root = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+
+        let manifest: Manifest = serde_jsonrc::from_str(
+            std::str::from_utf8(&files[&manifest_path])
+                .wrap_err_with(|| format!("{} isn't UTF-8", MANIFEST_FILE))?,
+        )
+        .wrap_err_with(|| format!("Failed to parse {}", MANIFEST_FILE))?;
+
+        if manifest.scripts.is_empty() {
+            return Err(eyre!("{} doesn't list any scripts", MANIFEST_FILE));
+        }
+
+        let mut take = |path: &Path| -> Result<Vec<u8>> {
+            let relative = relative_path(path)
+                .ok_or_else(|| eyre!("{} is outside the pack", path.display()))?;
+            files
+                .remove(&root.join(&relative))
+                .ok_or_else(|| eyre!("{} is missing from the pack", path.display()))
+        };
+
+        let mut scripts: Vec<PackageScript> = Vec::new();
+        for path in &manifest.scripts {
+            let source = String::from_utf8(take(path)?)
+                .wrap_err_with(|| format!("{} isn't UTF-8", path.display()))?;
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| eyre!("{} has no file name", path.display()))?;
+            let script = PackageScript::validate(source, Path::new(file_name))?;
+
+            if scripts.iter().any(|other| other.name == script.name) {
+                return Err(eyre!("Pack has two scripts named \"{}\"", script.name));
+            }
+            if scripts.iter().any(|other| other.path == script.path) {
+                return Err(eyre!("Pack has two files named {}", script.path.display()));
+            }
+
+            scripts.push(script);
+        }
+
+        let mut lib = BTreeMap::new();
+        for path in &manifest.lib {
+            let relative = relative_path(path)
+                .filter(|relative| relative.starts_with("lib"))
+                .ok_or_else(|| eyre!("{} isn't in the lib folder", path.display()))?;
+            lib.insert(relative, take(path)?);
+        }
+
+        Ok(Package {
+            manifest: Some(manifest),
+            scripts,
+            lib,
+        })
+    }
+
+    pub fn script_names(&self) -> impl Iterator<Item = &str> {
+        self.scripts.iter().map(|script| script.name.as_str())
+    }
+
+    // name of the pack, scripts installed on their own aren't tracked
+    pub fn pack_name(&self) -> Option<&str> {
+        self.manifest
+            .as_ref()
+            .map(|manifest| manifest.name.as_str())
+    }
+
+    pub fn collisions(&self, scripts: &ScriptMap, config: &Config) -> Vec<Collision> {
+        self.collisions_in(scripts, config, &ScriptMap::user_scripts_dir())
+    }
+
+    pub(crate) fn collisions_in(
+        &self,
+        scripts: &ScriptMap,
+        config: &Config,
+        dir: &Path,
+    ) -> Vec<Collision> {
+        // installing a pack again replaces everything it installed before
+        let previous = self
+            .pack_name()
+            .and_then(|name| config.installed_packs.iter().find(|pack| pack.name == name));
+        let replaced_script =
+            |name: &str| previous.map_or(false, |pack| pack.scripts.iter().any(|s| s == name));
+        let replaced_file =
+            |path: &Path| previous.map_or(false, |pack| pack.files.iter().any(|f| f == path));
+
+        let mut collisions = Vec::new();
+
+        for script in &self.scripts {
+            if replaced_script(&script.name) {
+                continue;
+            }
+
+            match scripts.scripts.get(&script.name) {
+                Some(existing) if existing.path.starts_with(dir) => {
+                    collisions.push(Collision::Script(script.name.clone()))
+                }
+                Some(existing) => {
+                    collisions.push(Collision::Overrides(script.name.clone(), existing.origin()))
+                }
+                None => {}
+            }
+        }
+
+        for path in self.files() {
+            if !replaced_file(&path) && dir.join(&path).exists() {
+                collisions.push(Collision::File(path));
+            }
+        }
+
+        collisions
+    }
+
+    // paths of every file the package writes, relative to the user scripts directory
+    fn files(&self) -> Vec<PathBuf> {
+        self.scripts
+            .iter()
+            .map(|script| script.path.clone())
+            .chain(self.lib.keys().cloned())
+            .collect()
+    }
+
+    // writes the package to the user scripts directory and loads its scripts, installed packs are
+    // recorded in config so they can be uninstalled, returns the names of the installed scripts
+    pub fn install(&self, scripts: &mut ScriptMap, config: &mut Config) -> Result<Vec<String>> {
+        self.install_in(scripts, config, &ScriptMap::user_scripts_dir())
+    }
+
+    pub(crate) fn install_in(
+        &self,
+        scripts: &mut ScriptMap,
+        config: &mut Config,
+        dir: &Path,
+    ) -> Result<Vec<String>> {
+        let blocking: Vec<String> = self
+            .collisions_in(scripts, config, dir)
+            .into_iter()
+            .filter(Collision::is_blocking)
+            .map(|collision| collision.to_string())
+            .collect();
+        if !blocking.is_empty() {
+            return Err(eyre!("Can't install over:\n{}", blocking.join("\n")));
+        }
+
+        if let Some(name) = self.pack_name() {
+            if config.installed_packs.iter().any(|pack| pack.name == name) {
+                uninstall_in(name, scripts, config, dir)?;
+            }
+        }
+
+        let files = self.files();
+        for (path, contents) in self
+            .scripts
+            .iter()
+            .map(|script| (&script.path, script.source.as_bytes()))
+            .chain(
+                self.lib
+                    .iter()
+                    .map(|(path, contents)| (path, contents.as_slice())),
+            )
+        {
+            let path = dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+            }
+            fs::write(&path, contents)
+                .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+            info!("installed {}", path.display());
+        }
+
+        // don't wait for the watcher, the scripts can be used straight away
+        let mut names = Vec::new();
+        for script in &self.scripts {
+            let path = dir.join(&script.path);
+            let loaded = Script::from_file(path.clone())
+                .map_err(|err| eyre!("Failed to load {}: {}", path.display(), err))?;
+            scripts.scripts.insert(script.name.clone(), loaded);
+            scripts.load_errors.remove(&path);
+            names.push(script.name.clone());
+        }
+
+        if let Some(manifest) = &self.manifest {
+            config.installed_packs.push(InstalledPack {
+                name: manifest.name.clone(),
+                version: manifest.version.clone(),
+                scripts: names.clone(),
+                files,
+            });
+        }
+
+        Ok(names)
+    }
+}
+
+impl PackageScript {
+    // path is where the script is installed, relative to the user scripts directory
+    fn validate(source: String, path: &Path) -> Result<PackageScript> {
+        if path.extension().map_or(true, |extension| extension != "js") {
+            return Err(eyre!("{} isn't a .js file", path.display()));
+        }
+
+        let name = Script::from_source(source.clone(), PathBuf::new())
+            .map_err(|err| eyre!("{} isn't a valid script: {}", path.display(), err))?
+            .metadata
+            .name
+            .clone();
+
+        Ok(PackageScript {
+            name,
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+// deletes the files of an installed pack, built-in scripts it replaced are restored
+pub fn uninstall(pack: &str, scripts: &mut ScriptMap, config: &mut Config) -> Result<()> {
+    uninstall_in(pack, scripts, config, &ScriptMap::user_scripts_dir())
+}
+
+fn uninstall_in(
+    pack: &str,
+    scripts: &mut ScriptMap,
+    config: &mut Config,
+    dir: &Path,
+) -> Result<()> {
+    let index = config
+        .installed_packs
+        .iter()
+        .position(|installed| installed.name == pack)
+        .ok_or_else(|| eyre!("No pack named \"{}\" is installed", pack))?;
+    let installed = config.installed_packs.remove(index);
+
+    remove_installed(&installed.scripts, &installed.files, scripts, dir)
+}
+
+// deletes installed files and unloads the scripts they contained, built-in scripts they replaced
+// are restored
+pub(crate) fn remove_installed(
+    names: &[String],
+    files: &[PathBuf],
+    scripts: &mut ScriptMap,
+    dir: &Path,
+) -> Result<()> {
+    for file in files {
+        let path = dir.join(file);
+        match fs::remove_file(&path) {
+            Ok(()) => info!("uninstalled {}", path.display()),
+            // already deleted by hand
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Failed to delete {}", path.display()))
+            }
+        }
+    }
+
+    for name in names {
+        let installed_here = scripts
+            .scripts
+            .get(name)
+            .map_or(false, |script| script.path.starts_with(dir));
+        if !installed_here {
+            continue;
+        }
+
+        // global scripts it replaced come back on the next reload
+        match scripts.load_builtin(name) {
+            Ok(builtin) => scripts.scripts.insert(name.clone(), builtin),
+            Err(_) => scripts.scripts.remove(name),
+        };
+    }
+
+    Ok(())
+}
+
+// path inside a pack with "." removed, None if it isn't relative or leaves the pack
+fn relative_path(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if relative.as_os_str().is_empty() {
+        None
+    } else {
+        Some(relative)
+    }
+}
+
+fn read_zip(path: &Path) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let file = File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let mut archive = zip::ZipArchive::new(file).wrap_err("Failed to read zip archive")?;
+
+    let mut files = BTreeMap::new();
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .wrap_err("Failed to read zip archive")?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        let entry_path = entry
+            .enclosed_name()
+            .and_then(relative_path)
+            .ok_or_else(|| eyre!("{} is outside the pack", entry.name()))?;
+
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .wrap_err_with(|| format!("Failed to extract {}", entry_path.display()))?;
+        files.insert(entry_path, contents);
+    }
+
+    Ok(files)
+}
+
+fn read_tar(path: &Path) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let file = File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let mut archive = tar::Archive::new(file);
+
+    let mut files = BTreeMap::new();
+    for entry in archive.entries().wrap_err("Failed to read tar archive")? {
+        let mut entry = entry.wrap_err("Failed to read tar archive")?;
+        // links could point anywhere, only regular files are installed
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let raw_path = entry
+            .path()
+            .wrap_err("Failed to read tar archive")?
+            .into_owned();
+        let entry_path = relative_path(&raw_path)
+            .ok_or_else(|| eyre!("{} is outside the pack", raw_path.display()))?;
+
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .wrap_err_with(|| format!("Failed to extract {}", entry_path.display()))?;
+        files.insert(entry_path, contents);
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn source(name: &str) -> String {
+        format!(
+            r#"/**
+                {{
+                    "api": 1,
+                    "name": "{}",
+                    "description": "Test script",
+                    "icon": "type"
+                }}
+            **/
+
+            const lib = require("lib/shared");
+
+            function main(state) {{
+                state.text = lib.shout(state.text);
+            }}"#,
+            name
+        )
+    }
+
+    const MANIFEST: &str = r#"{
+        "name": "Test Pack",
+        "version": "1.0.0",
+        "scripts": ["scripts/Shout.js", "scripts/Whisper.js"],
+        "lib": ["lib/shared.js"]
+    }"#;
+
+    fn pack_files(manifest: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("pack/manifest.json", manifest.to_string()),
+            ("pack/scripts/Shout.js", source("Shout")),
+            ("pack/scripts/Whisper.js", source("Whisper")),
+            (
+                "pack/lib/shared.js",
+                "module.exports.shout = (text) => text.toUpperCase();".to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_open_archives() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let zip_path = dir.path().join("pack.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for (path, contents) in pack_files(MANIFEST) {
+            zip.start_file(path, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let tar_path = dir.path().join("pack.tar");
+        let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+        for (path, contents) in pack_files(MANIFEST) {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        tar.finish().unwrap();
+
+        for path in &[zip_path, tar_path] {
+            let package = Package::open(path).unwrap();
+            assert_eq!(package.pack_name(), Some("Test Pack"));
+            assert_eq!(
+                package.script_names().collect::<Vec<_>>(),
+                vec!["Shout", "Whisper"]
+            );
+            assert_eq!(
+                package.files(),
+                vec![
+                    PathBuf::from("Shout.js"),
+                    PathBuf::from("Whisper.js"),
+                    PathBuf::from("lib/shared.js")
+                ]
+            );
+        }
+
+        let script_path = dir.path().join("Shout.js");
+        fs::write(&script_path, source("Shout")).unwrap();
+        let package = Package::open(&script_path).unwrap();
+        assert_eq!(package.pack_name(), None);
+        assert_eq!(package.files(), vec![PathBuf::from("Shout.js")]);
+
+        assert!(Package::open(&dir.path().join("pack.rar")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_packs() {
+        let pack = |files: Vec<(&str, String)>| {
+            Package::pack(
+                files
+                    .into_iter()
+                    .map(|(path, contents)| (PathBuf::from(path), contents.into_bytes()))
+                    .collect(),
+            )
+        };
+
+        let mut files = pack_files(MANIFEST);
+        files.remove(0);
+        assert!(pack(files).is_err(), "missing manifest");
+
+        let mut files = pack_files(MANIFEST);
+        files.remove(3);
+        assert!(pack(files).is_err(), "missing lib file");
+
+        let mut files = pack_files(MANIFEST);
+        files[2].1 = "function main(state) {}".to_string();
+        assert!(pack(files).is_err(), "invalid script");
+
+        let files = pack_files(&MANIFEST.replace("scripts/Whisper.js", "../Whisper.js"));
+        assert!(pack(files).is_err(), "path outside the pack");
+
+        let files = pack_files(&MANIFEST.replace("lib/shared.js", "scripts/Shout.js"));
+        assert!(pack(files).is_err(), "lib file outside lib/");
+
+        let mut files = pack_files(MANIFEST);
+        files[2].1 = source("Shout");
+        assert!(pack(files).is_err(), "duplicate name");
+
+        assert!(pack(pack_files(MANIFEST)).is_ok());
+    }
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        scripts.load_internal();
+        let mut config = Config::default();
+
+        let package = Package::pack(
+            pack_files(MANIFEST)
+                .into_iter()
+                .map(|(path, contents)| {
+                    let contents = contents.replace("\"Whisper\"", "\"Format JSON\"");
+                    (PathBuf::from(path), contents.into_bytes())
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            package.collisions_in(&scripts, &config, dir.path()),
+            vec![Collision::Overrides(
+                "Format JSON".to_string(),
+                ScriptOrigin::Builtin
+            )]
+        );
+
+        let installed = package
+            .install_in(&mut scripts, &mut config, dir.path())
+            .unwrap();
+        assert_eq!(installed, vec!["Shout", "Format JSON"]);
+        assert!(dir.path().join("lib/shared.js").is_file());
+        assert_eq!(scripts.scripts["Shout"].path, dir.path().join("Shout.js"));
+        assert_eq!(
+            scripts.scripts["Format JSON"].path,
+            dir.path().join("Whisper.js")
+        );
+        assert_eq!(config.pack_of("Shout").unwrap().version, "1.0.0");
+
+        // installing the same pack again replaces it
+        assert!(package
+            .collisions_in(&scripts, &config, dir.path())
+            .is_empty());
+        package
+            .install_in(&mut scripts, &mut config, dir.path())
+            .unwrap();
+        assert_eq!(config.installed_packs.len(), 1);
+
+        // a single script can't replace a script from the pack
+        let script = Package::script(source("Shout"), Path::new("Other.js")).unwrap();
+        assert!(script
+            .collisions_in(&scripts, &config, dir.path())
+            .iter()
+            .all(Collision::is_blocking));
+        assert!(script
+            .install_in(&mut scripts, &mut config, dir.path())
+            .is_err());
+
+        uninstall_in("Test Pack", &mut scripts, &mut config, dir.path()).unwrap();
+        assert!(config.installed_packs.is_empty());
+        assert!(!dir.path().join("Shout.js").exists());
+        assert!(!dir.path().join("lib/shared.js").exists());
+        assert!(!scripts.scripts.contains_key("Shout"));
+        assert_eq!(
+            scripts.scripts["Format JSON"].origin(),
+            ScriptOrigin::Builtin
+        );
+
+        assert!(uninstall_in("Test Pack", &mut scripts, &mut config, dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("./lib/shared.js")),
+            Some(PathBuf::from("lib/shared.js"))
+        );
+        assert_eq!(relative_path(Path::new("lib/../../shared.js")), None);
+        assert_eq!(relative_path(Path::new("/etc/passwd")), None);
+        assert_eq!(relative_path(Path::new(".")), None);
+    }
+}
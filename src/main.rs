@@ -10,10 +10,20 @@ extern crate log;
 extern crate eyre;
 extern crate fs_extra;
 
+mod catalog;
+mod cli;
 mod config;
+mod dbus;
 mod executor;
+mod host;
+mod install;
+mod modules;
+mod native;
+mod permission;
 mod script;
 mod scriptmap;
+mod search_provider;
+mod testing;
 mod ui;
 mod util;
 
@@ -24,16 +34,25 @@ use ui::{
     shortcuts_window::ShortcutsWindow,
 };
 
-use crate::config::Config;
+use crate::{
+    cli::{CommandLine, Selection},
+    config::Config,
+    dbus::Request,
+};
 use eyre::{Context, Result};
 use fs::File;
 use gio::prelude::*;
 use gtk::{prelude::*, Application, Window};
 
 use std::{
+    cell::RefCell,
+    env,
+    ffi::OsString,
     fs,
     io::prelude::*,
     path::PathBuf,
+    process,
+    rc::Rc,
     sync::{Arc, RwLock},
     thread,
 };
@@ -66,6 +85,34 @@ fn main() -> Result<()> {
     color_eyre::install()?;
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
+    // options are checked before starting so help and errors are printed by this process, the
+    // running instance receives them again through the command-line signal
+    let args: Vec<OsString> = env::args_os().collect();
+    let service = match CommandLine::parse(&args) {
+        Ok(command_line) if command_line.help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(CommandLine {
+            test: Some(paths), ..
+        }) => process::exit(testing::run_cli(&paths)),
+        Ok(CommandLine {
+            list: true, all, ..
+        }) => {
+            let (config, _) = Config::load()?;
+            let (scripts, _) = ScriptMap::new();
+            for line in cli::list_scripts(&scripts, &config, all) {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        Ok(command_line) => command_line.service,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
     let (config, config_file_created) = Config::load()?;
     let config = Arc::new(RwLock::new(config));
 
@@ -84,10 +131,12 @@ fn main() -> Result<()> {
     let scripts = Arc::new(RwLock::new(scripts_map));
 
     // watch scripts folder for changes
+    let (script_change_sender, script_change_receiver) =
+        glib::MainContext::channel(glib::PRIORITY_DEFAULT);
     {
         let scripts = scripts.clone();
         thread::spawn(move || {
-            ScriptMap::watch(scripts);
+            ScriptMap::watch(scripts, script_change_sender);
         });
     }
 
@@ -96,69 +145,227 @@ fn main() -> Result<()> {
 
     glib::set_application_name("Boop-GTK");
 
-    let application = Application::new(Some("fyi.zoey.Boop-GTK"), Default::default())
-        .wrap_err("Failed to initialize GTK application")?;
-
-    application.connect_activate(move |application| {
-        // resources.gresources is created by build.rs
-        // it includes all the files in the resources directory
-        let resource_bytes =
-            include_bytes!(concat!(env!("OUT_DIR"), "/resources/resources.gresource"));
-        let resource_data = glib::Bytes::from(&resource_bytes[..]);
-        gio::resources_register(&gio::Resource::from_data(&resource_data).unwrap());
-
-        // add embedeed icons to theme
-        let icon_theme = gtk::IconTheme::get_default().expect("Failed to get default icon theme");
-        icon_theme.add_resource_path("/fyi/zoey/Boop-GTK/icons");
-
-        Window::set_default_icon_name("fyi.zoey.Boop-GTK");
-
-        // must be fetched _before_ widgets are proccessed since the language managers search path must
-        // be set immediantly after creation:
-        // https://developer.gnome.org/gtksourceview/stable/GtkSourceLanguageManager.html#gtk-source-language-manager-set-search-path
-        let boop_language = || -> Result<Language> {
-            let language_manager = sourceview::LanguageManager::get_default()
-                .ok_or_else(|| eyre!("Failed to get language manager"))?;
-
-            // add config_dir to language manager's search path
-            let dirs = language_manager.get_search_path();
-            let mut dirs: Vec<&str> = dirs.iter().map(|s| s.as_ref()).collect();
-            let config_dir_path = XDG_DIRS.get_config_home().to_string_lossy().to_string();
-            dirs.push(&config_dir_path);
-            language_manager.set_search_path(&dirs);
-
-            info!("language manager search directorys: {}", dirs.join(":"));
-
-            language_manager
-                .get_language("boop")
-                .ok_or_else(|| eyre!("'boop' language not found in language manager"))
-        }()
-        .expect("Failed to load boop language");
-
-        let app = App::new(boop_language, scripts.clone(), config.clone())
-            .expect("Failed to construct App");
-        app.set_application(Some(application));
-        app.show_all();
-
-        register_actions(&application, &app);
-
-        if config_file_created
-            || config
-                .read()
-                .expect("Config lock is poisoned")
-                .show_shortcuts_on_open
-        {
-            let shortcuts_window = ShortcutsWindow::new();
-            shortcuts_window.set_transient_for(Some(&app.window));
-            shortcuts_window.show_all();
+    let application = Application::new(
+        Some("fyi.zoey.Boop-GTK"),
+        gio::ApplicationFlags::HANDLES_COMMAND_LINE,
+    )
+    .wrap_err("Failed to initialize GTK application")?;
+
+    // GApplication only waits for its own activation before exiting, the scripts interface and the
+    // search provider are served on another connection so the service is kept running
+    if service {
+        application.connect_startup(|application| application.hold());
+    }
+
+    let app_cell: Rc<RefCell<Option<App>>> = Rc::new(RefCell::new(None));
+
+    // script changes are dropped until the window exists
+    {
+        let app_cell = app_cell.clone();
+        script_change_receiver.attach(None, move |changes| {
+            let app = app_cell.borrow().clone();
+            if let Some(app) = app {
+                app.scripts_changed(&changes);
+            }
+
+            Continue(true)
+        });
+    }
+
+    // the window is built by the first activation or command line and reused afterwards
+    let get_app = Rc::new({
+        let scripts = scripts.clone();
+        let config = config.clone();
+        move |application: &Application| -> App {
+            if let Some(app) = app_cell.borrow().as_ref() {
+                return app.clone();
+            }
+
+            // resources.gresources is created by build.rs
+            // it includes all the files in the resources directory
+            let resource_bytes =
+                include_bytes!(concat!(env!("OUT_DIR"), "/resources/resources.gresource"));
+            let resource_data = glib::Bytes::from(&resource_bytes[..]);
+            gio::resources_register(&gio::Resource::from_data(&resource_data).unwrap());
+
+            // add embedeed icons to theme
+            let icon_theme =
+                gtk::IconTheme::get_default().expect("Failed to get default icon theme");
+            icon_theme.add_resource_path("/fyi/zoey/Boop-GTK/icons");
+
+            Window::set_default_icon_name("fyi.zoey.Boop-GTK");
+
+            // must be fetched _before_ widgets are proccessed since the language managers search path must
+            // be set immediantly after creation:
+            // https://developer.gnome.org/gtksourceview/stable/GtkSourceLanguageManager.html#gtk-source-language-manager-set-search-path
+            let boop_language = || -> Result<Language> {
+                let language_manager = sourceview::LanguageManager::get_default()
+                    .ok_or_else(|| eyre!("Failed to get language manager"))?;
+
+                // add config_dir to language manager's search path
+                let dirs = language_manager.get_search_path();
+                let mut dirs: Vec<&str> = dirs.iter().map(|s| s.as_ref()).collect();
+                let config_dir_path = XDG_DIRS.get_config_home().to_string_lossy().to_string();
+                dirs.push(&config_dir_path);
+                language_manager.set_search_path(&dirs);
+
+                info!("language manager search directorys: {}", dirs.join(":"));
+
+                language_manager
+                    .get_language("boop")
+                    .ok_or_else(|| eyre!("'boop' language not found in language manager"))
+            }()
+            .expect("Failed to load boop language");
+
+            let app = App::new(boop_language, scripts.clone(), config.clone())
+                .expect("Failed to construct App");
+            app.set_application(Some(application));
+
+            register_actions(&application, &app);
+
+            if let Some(error) = &load_script_error {
+                app.post_notification_error(
+                    &glib::markup_escape_text(&error.to_string()),
+                    NOTIFICATION_LONG_DELAY,
+                );
+            }
+
+            app_cell.borrow_mut().replace(app.clone());
+            app
         }
+    });
 
-        if let Some(error) = &load_script_error {
-            app.post_notification_error(&error.to_string(), NOTIFICATION_LONG_DELAY);
+    // shows the window, the shortcuts are shown the first time if enabled
+    let present = {
+        let config = config.clone();
+        move |app: &App| {
+            if !app.window.get_visible() {
+                app.show_all();
+
+                if config_file_created
+                    || config
+                        .read()
+                        .expect("Config lock is poisoned")
+                        .show_shortcuts_on_open
+                {
+                    let shortcuts_window = ShortcutsWindow::new();
+                    shortcuts_window.set_transient_for(Some(&app.window));
+                    shortcuts_window.show_all();
+                }
+            }
+
+            app.window.present();
         }
+    };
+
+    {
+        let get_app = get_app.clone();
+        let present = present.clone();
+        application.connect_activate(move |application| present(&get_app(application)));
+    }
+
+    // serve the D-Bus interfaces from the primary instance, requests that need the window are
+    // handled by the main thread
+    {
+        let (request_sender, request_receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+
+        let scripts = scripts.clone();
+        let config = config.clone();
+        application.connect_startup(move |_| {
+            let scripts = scripts.clone();
+            let config = config.clone();
+            let request_sender = request_sender.clone();
+            thread::spawn(move || {
+                let result = zbus::Connection::new_session()
+                    .wrap_err("Failed to connect to the session bus")
+                    .and_then(|connection| {
+                        dbus::serve(&connection, scripts, config, request_sender)
+                    });
+
+                if let Err(err) = result {
+                    error!("D-Bus interface is unavailable: {:?}", err);
+                }
+            });
+        });
+
+        let application = application.clone();
+        let get_app = get_app.clone();
+        let present = present.clone();
+        request_receiver.attach(None, move |request| {
+            let app = get_app(&application);
+            present(&app);
+
+            let result = match request {
+                Request::OpenText(text) => app.open_text(&text),
+                Request::RunScript(script_key) => app.run_script_on_clipboard(&script_key),
+                Request::ShowCommandPalette => app.run_command_palette(),
+            };
+            if let Err(err) = result {
+                error!("failed to handle D-Bus request: {:?}", err);
+            }
+
+            Continue(true)
+        });
+    }
+
+    application.connect_command_line(move |application, command_line| {
+        let command_line = match CommandLine::parse(&command_line.get_arguments()) {
+            Ok(command_line) => command_line,
+            Err(err) => {
+                error!("invalid command line: {}", err);
+                return 2;
+            }
+        };
+
+        // D-Bus activation doesn't show the window
+        if command_line.service {
+            return 0;
+        }
+
+        let selection = match command_line.selection {
+            Some(selection) => selection,
+            None => {
+                application.activate();
+                return 0;
+            }
+        };
+
+        let app = get_app(application);
+        let was_open = app.window.get_visible();
+
+        // a script can run without the window, the palette needs it
+        if command_line.script.is_none() || !command_line.quit {
+            present(&app);
+        }
+
+        let copied = app
+            .transform_selection(selection, command_line.script.as_deref())
+            .unwrap_or_else(|err| {
+                error!("failed to transform {:?}: {:?}", selection, err);
+                app.post_notification_error(
+                    &glib::markup_escape_text(&err.to_string()),
+                    NOTIFICATION_LONG_DELAY,
+                );
+                false
+            });
+
+        // quitting is skipped when Boop-GTK was already open or the user needs to see an error
+        if command_line.quit && copied && !was_open {
+            application.quit();
+        } else {
+            present(&app);
+        }
+
+        0
     });
 
-    application.run(&[]);
+    // arguments that aren't valid unicode are passed on lossily, GTK only takes strings
+    application.run(
+        &args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<String>>(),
+    );
     Ok(())
 }
 
@@ -186,6 +393,35 @@ fn register_actions(application: &Application, app: &App) {
             .connect_activate(move |_, _| app.re_execute().expect("Failed to re-execute script"));
     }
 
+    // clipboard transform actions, the parameter is the name of the script to run or an empty
+    // string to choose one from the command palette, also available over D-Bus:
+    // gdbus call --session --dest fyi.zoey.Boop-GTK --object-path /fyi/zoey/Boop_GTK \
+    //   --method org.gtk.Actions.Activate transform_clipboard "[<'Base64 Encode'>]" {}
+    for selection in &[Selection::Clipboard, Selection::Primary] {
+        let app = app.clone();
+        let selection = *selection;
+        let transform_action = gio::SimpleAction::new(
+            selection.action_name(),
+            Some(glib::VariantTy::new("s").unwrap()),
+        );
+        application.add_action(&transform_action);
+        transform_action.connect_activate(move |_, parameter| {
+            let script_key = parameter
+                .and_then(|parameter| parameter.get_str())
+                .filter(|script_key| !script_key.is_empty());
+
+            app.show_all();
+            app.window.present();
+            if let Err(err) = app.transform_selection(selection, script_key) {
+                error!("failed to transform {:?}: {:?}", selection, err);
+                app.post_notification_error(
+                    &glib::markup_escape_text(&err.to_string()),
+                    NOTIFICATION_LONG_DELAY,
+                );
+            }
+        });
+    }
+
     // quit action
     {
         let quit_action = gio::SimpleAction::new("quit", None);
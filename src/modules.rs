@@ -0,0 +1,830 @@
+use crate::{native, scriptmap::Scripts, XDG_DIRS};
+use eyre::{Context, Result};
+use rusty_v8 as v8;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::Read,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+use v8_callback_scope::with_callback_scope;
+
+static BOOP_WRAPPER_START: &str = "
+/***********************************
+*     Start of Boop's wrapper      *
+***********************************/
+            
+(function(module, require) {
+    // module is created by Boop-GTK and cached before this runs,
+    // requiring this file again while it is still loading returns
+    // the exports defined so far
+            
+    const moduleWrapper = (function (exports, module, require) {
+
+/***********************************
+*      End of Boop's wrapper      *
+***********************************/
+
+";
+
+static BOOP_WRAPPER_END: &str = "
+            
+/***********************************
+*     Start of Boop's wrapper      *
+***********************************/
+            
+    }).apply(module.exports, [module.exports, module, require]);
+
+    return module.exports;
+})
+            
+/***********************************
+*      End of Boop's wrapper      *
+***********************************/
+";
+
+// CommonJS libraries (like the ones bundled with Boop) are wrapped so they can be imported, their
+// exports become the default export
+static COMMONJS_MODULE_START: &str = "const module = { exports: {} };
+(function (exports, module) {
+";
+
+static COMMONJS_MODULE_END: &str = "
+}).apply(module.exports, [module.exports, module]);
+export default module.exports;
+";
+
+// where the source of a script dependency lives
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ModulePath {
+    // path inside the embedded scripts folder, e.g. lib/base64.js
+    Internal(String),
+    External(PathBuf),
+}
+
+impl Display for ModulePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModulePath::Internal(path) => write!(f, "#BINARY#/{}", path),
+            ModulePath::External(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl ModulePath {
+    // resolves an import or require specifier:
+    // - "@boop/name" is the embedded lib/name.js
+    // - "./name" and "../name" are relative to the importing file
+    // - anything else is looked up in the script directories, the first one containing it wins
+    pub fn resolve(specifier: &str, referrer: Option<&ModulePath>) -> ModulePath {
+        let mut specifier = specifier.to_owned();
+        if !specifier.ends_with(".js") && !specifier.ends_with(".cjs") {
+            specifier.push_str(".js");
+        }
+
+        if let Some(name) = specifier.strip_prefix("@boop/") {
+            return ModulePath::Internal(format!("lib/{}", name));
+        }
+
+        let is_relative = specifier.starts_with("./") || specifier.starts_with("../");
+
+        match referrer {
+            Some(ModulePath::Internal(referrer)) if is_relative => {
+                let directory = Path::new(referrer)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""));
+                ModulePath::Internal(
+                    normalize(&directory.join(&specifier))
+                        .to_string_lossy()
+                        .replace("\\", "/"),
+                )
+            }
+            Some(ModulePath::External(referrer)) if is_relative => {
+                let directory = referrer.parent().unwrap_or_else(|| Path::new(""));
+                ModulePath::External(normalize(&directory.join(&specifier)))
+            }
+            _ => {
+                let candidates: Vec<PathBuf> = script_dirs()
+                    .iter()
+                    .map(|dir| normalize(&dir.join(&specifier)))
+                    .collect();
+
+                // fall back to the user scripts directory so errors mention the expected location
+                let path = candidates
+                    .iter()
+                    .find(|path| path.is_file())
+                    .or_else(|| candidates.first())
+                    .cloned()
+                    .unwrap_or_else(|| PathBuf::from(&specifier));
+
+                ModulePath::External(path)
+            }
+        }
+    }
+
+    // imports are ES modules like the script importing them, except for CommonJS files which are
+    // wrapped: Boop's libraries and files with a .cjs extension
+    pub fn is_commonjs(&self) -> bool {
+        match self {
+            ModulePath::Internal(_) => true,
+            ModulePath::External(path) => path.extension().map_or(false, |ext| ext == "cjs"),
+        }
+    }
+
+    // canonical path of the module if it may be loaded, only files inside the script directories
+    // are allowed, this also catches symlinks pointing outside of them
+    pub fn confine(self) -> Option<ModulePath> {
+        match self {
+            ModulePath::Internal(_) => Some(self),
+            ModulePath::External(path) => {
+                // missing files fail later with a normal "Could not open" error
+                let path = path.canonicalize().unwrap_or_else(|_| normalize(&path));
+
+                let allowed = script_dirs()
+                    .into_iter()
+                    .flat_map(|dir| {
+                        let canonical = dir.canonicalize().ok();
+                        std::iter::once(dir).chain(canonical)
+                    })
+                    .any(|dir| path.starts_with(dir));
+
+                if allowed {
+                    Some(ModulePath::External(path))
+                } else {
+                    warn!("denied access to {}", path.display());
+                    None
+                }
+            }
+        }
+    }
+
+    // read the source, specifier is what the script asked for and is used in error messages
+    pub fn load(&self, specifier: &str) -> Result<String> {
+        match self {
+            ModulePath::Internal(path) => {
+                info!("found internal script, real path: {}", self);
+
+                String::from_utf8(
+                    Scripts::get(path)
+                        .ok_or_else(|| eyre!("No internal script with path \"{}\"", specifier))?
+                        .to_vec(),
+                )
+                .wrap_err("Problem with file encoding")
+            }
+            ModulePath::External(path) => {
+                info!("found external script, real path: {}", self);
+
+                let mut raw_source = String::new();
+                File::open(path)
+                    .wrap_err_with(|| format!("Could not open \"{}\"", specifier))?
+                    .read_to_string(&mut raw_source)
+                    .wrap_err("Problem reading file")?;
+
+                Ok(raw_source)
+            }
+        }
+    }
+}
+
+// directories bare specifiers are resolved against, in order of precedence
+pub fn script_dirs() -> Vec<PathBuf> {
+    if cfg!(test) {
+        return vec![env::temp_dir()];
+    }
+
+    let mut dirs = vec![XDG_DIRS.get_config_home().join("scripts")];
+    dirs.extend(
+        XDG_DIRS
+            .get_config_dirs()
+            .into_iter()
+            .map(|dir| dir.join("scripts")),
+    );
+    dirs
+}
+
+// removes "." and ".." without touching the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// specifiers of the static imports and the require calls with a string literal in a source, found
+// without parsing it so a specifier mentioned in a comment or string is found as well
+pub fn scan_specifiers(source: &str) -> Vec<String> {
+    let mut specifiers = Vec::new();
+
+    for keyword in &["require", "import", "from"] {
+        for (index, _) in source.match_indices(keyword) {
+            // foo.require(...) or imported
+            let attached = source[..index].chars().next_back().map_or(false, |c| {
+                c.is_alphanumeric() || c == '_' || c == '$' || c == '.'
+            });
+            if attached {
+                continue;
+            }
+
+            // require("x"), import "x", import("x") and ... from "x"
+            let mut rest = source[index + keyword.len()..].trim_start();
+            match (rest.strip_prefix('('), *keyword) {
+                (Some(after), "require") | (Some(after), "import") => rest = after.trim_start(),
+                (None, "require") | (Some(_), _) => continue,
+                (None, _) => {}
+            }
+
+            let quote = match rest.chars().next() {
+                Some(quote @ '"') | Some(quote @ '\'') | Some(quote @ '`') => quote,
+                _ => continue,
+            };
+            let specifier = match rest[1..].find(quote) {
+                Some(end) => &rest[1..1 + end],
+                None => continue,
+            };
+            if !specifier.is_empty() && !specifier.contains('\n') && !specifier.contains("${") {
+                specifiers.push(specifier.to_string());
+            }
+        }
+    }
+
+    specifiers.sort();
+    specifiers.dedup();
+    specifiers
+}
+
+// files on disk a script imports or requires, directly or through other files, found by scanning
+// the sources so they are known before the script runs. the executor reports the ones built at
+// runtime once they are loaded
+pub fn scan_dependencies(path: &Path, source: &str) -> Vec<PathBuf> {
+    let script_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let mut dependencies: Vec<PathBuf> = Vec::new();
+    let mut pending = vec![(ModulePath::External(path.to_path_buf()), source.to_string())];
+
+    while let Some((referrer, source)) = pending.pop() {
+        for specifier in scan_specifiers(&source) {
+            let dependency = match ModulePath::resolve(&specifier, Some(&referrer)).confine() {
+                Some(ModulePath::External(dependency)) => dependency,
+                _ => continue,
+            };
+            if dependency == script_path || dependencies.contains(&dependency) {
+                continue;
+            }
+
+            // missing files are kept, the script is restarted once they appear
+            if let Ok(source) = fs::read_to_string(&dependency) {
+                pending.push((ModulePath::External(dependency.clone()), source));
+            }
+            dependencies.push(dependency);
+        }
+    }
+
+    dependencies.sort();
+    dependencies
+}
+
+// modules loaded by an isolate, stored in a slot so callbacks can find them
+#[derive(Default)]
+pub struct ModuleMap {
+    // compiled ES modules
+    modules: HashMap<ModulePath, v8::Global<v8::Module>>,
+    // module identity hash to path, used to resolve relative imports
+    paths: HashMap<i32, ModulePath>,
+    // `module` objects of required files, inserted before the file runs so circular requires work
+    required: HashMap<ModulePath, v8::Global<v8::Object>>,
+    // files that own a require function, the index is stored as the function's data
+    referrers: Vec<ModulePath>,
+    // last specifier that was denied by `ModulePath::confine`, with the error thrown for it
+    denied: Option<(String, v8::Global<v8::Value>)>,
+    // exports of the @boop/ libraries in the startup snapshot, keyed by internal path
+    preloaded: Option<v8::Global<v8::Object>>,
+}
+
+impl ModuleMap {
+    // files on disk that were imported or required, sorted and without duplicates
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        let mut dependencies: Vec<PathBuf> = self
+            .modules
+            .keys()
+            .chain(self.required.keys())
+            .filter_map(|path| match path {
+                ModulePath::Internal(_) => None,
+                ModulePath::External(path) => Some(path.clone()),
+            })
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+        dependencies
+    }
+}
+
+// moves the libraries evaluated by build.rs off the global object and into the module map
+pub fn take_preloaded(
+    scope: &mut v8::HandleScope<'_>,
+    global: v8::Local<'_, v8::Object>,
+) -> Option<()> {
+    let libraries_key = v8::String::new(scope, "__boopLibraries")?;
+    let libraries = global.get(scope, libraries_key.into())?;
+
+    if let Ok(libraries) = v8::Local::<v8::Object>::try_from(libraries) {
+        global.delete(scope, libraries_key.into())?;
+
+        let libraries = v8::Global::new(scope, libraries);
+        scope
+            .get_slot::<Rc<RefCell<ModuleMap>>>()
+            .expect("failed to get module map slot")
+            .borrow_mut()
+            .preloaded
+            .replace(libraries);
+    } else {
+        info!("no preloaded libraries, they are loaded from source");
+    }
+
+    Some(())
+}
+
+// exports of a library from the startup snapshot
+fn preloaded_exports<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    module_map: &RefCell<ModuleMap>,
+    path: &ModulePath,
+) -> Option<v8::Local<'s, v8::Value>> {
+    let path = match path {
+        ModulePath::Internal(path) => path,
+        ModulePath::External(_) => return None,
+    };
+
+    let libraries = module_map
+        .borrow()
+        .preloaded
+        .as_ref()
+        .map(|libraries| v8::Local::new(scope, libraries))?;
+
+    let key = v8::String::new(scope, path)?;
+    libraries
+        .get(scope, key.into())
+        .filter(|exports| !exports.is_undefined())
+}
+
+// clears the last denied specifier, it is returned if exception is the error thrown for it
+pub fn take_denied(
+    scope: &mut v8::HandleScope<'_>,
+    exception: Option<v8::Local<'_, v8::Value>>,
+) -> Option<String> {
+    let (specifier, error) = scope
+        .get_slot::<Rc<RefCell<ModuleMap>>>()
+        .expect("failed to get module map slot")
+        .borrow_mut()
+        .denied
+        .take()?;
+    let error = v8::Local::new(scope, error);
+
+    exception
+        .filter(|exception| exception.strict_equals(error))
+        .map(|_| specifier)
+}
+
+// forgets the last denied specifier, the script caught the error thrown for it
+pub fn clear_denied(isolate: &mut v8::Isolate) {
+    isolate
+        .get_slot::<Rc<RefCell<ModuleMap>>>()
+        .expect("failed to get module map slot")
+        .borrow_mut()
+        .denied
+        .take();
+}
+
+// records the denied specifier and throws an exception
+fn deny(scope: &mut v8::HandleScope<'_>, module_map: &RefCell<ModuleMap>, specifier: &str) {
+    let message = format!(
+        "Access to \"{}\" is denied, only files in the scripts directory can be loaded",
+        specifier
+    );
+    let exception_str =
+        v8::String::new(scope, &message).expect("failed to create string for exception");
+    let exception = v8::Exception::error(scope, exception_str);
+
+    module_map.borrow_mut().denied =
+        Some((specifier.to_string(), v8::Global::new(scope, exception)));
+    scope.throw_exception(exception);
+}
+
+pub fn compile_module<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    path: Option<&ModulePath>,
+    source: &str,
+) -> Option<v8::Local<'s, v8::Module>> {
+    let code = v8::String::new(scope, source)?;
+    let resource_name: v8::Local<v8::Value> = match path {
+        Some(path) => v8::String::new(scope, &path.to_string())?.into(),
+        None => v8::undefined(scope).into(),
+    };
+    let source_map_url: v8::Local<v8::Value> = v8::undefined(scope).into();
+    let origin = v8::ScriptOrigin::new(
+        scope,
+        resource_name,
+        0,
+        0,
+        false,
+        0,
+        source_map_url,
+        false,
+        false,
+        true,
+    );
+    let source = v8::script_compiler::Source::new(code, Some(&origin));
+
+    let module = v8::script_compiler::compile_module(scope, source)?;
+
+    if let Some(path) = path {
+        register_module(scope, path, module);
+    }
+
+    Some(module)
+}
+
+// ES module exporting a library from the startup snapshot as its default export, like a
+// wrapped CommonJS library
+fn preloaded_module<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    path: &ModulePath,
+) -> Option<v8::Local<'s, v8::Module>> {
+    let name = v8::String::new(scope, &path.to_string())?;
+    let default_key = v8::String::new(scope, "default")?;
+    let module =
+        v8::Module::create_synthetic_module(scope, name, &[default_key], evaluate_preloaded_module);
+
+    register_module(scope, path, module);
+
+    Some(module)
+}
+
+// called by V8 when a module created by `preloaded_module` is evaluated
+fn evaluate_preloaded_module<'a>(
+    context: v8::Local<'a, v8::Context>,
+    module: v8::Local<'a, v8::Module>,
+) -> Option<v8::Local<'a, v8::Value>> {
+    with_callback_scope(context, |scope| {
+        let module_map = scope
+            .get_slot::<Rc<RefCell<ModuleMap>>>()
+            .expect("failed to get module map slot")
+            .clone();
+        let path = module_map
+            .borrow()
+            .paths
+            .get(&module.get_identity_hash())
+            .cloned()?;
+
+        let exports = preloaded_exports(scope, &module_map, &path)?;
+        let default_key = v8::String::new(scope, "default")?;
+        module.set_synthetic_module_export(scope, default_key, exports)?;
+
+        Some(v8::undefined(scope).into())
+    })
+}
+
+// remembers a module so later imports of path reuse it and relative imports can be resolved
+fn register_module(
+    scope: &mut v8::HandleScope<'_>,
+    path: &ModulePath,
+    module: v8::Local<'_, v8::Module>,
+) {
+    let global = v8::Global::new(scope, module);
+    let module_map = scope
+        .get_slot::<Rc<RefCell<ModuleMap>>>()
+        .expect("failed to get module map slot")
+        .clone();
+    let mut module_map = module_map.borrow_mut();
+    module_map
+        .paths
+        .insert(module.get_identity_hash(), path.clone());
+    module_map.modules.insert(path.clone(), global);
+}
+
+// called by V8 for every import while instantiating a module
+pub fn resolve_module<'a>(
+    context: v8::Local<'a, v8::Context>,
+    specifier: v8::Local<'a, v8::String>,
+    _import_assertions: v8::Local<'a, v8::FixedArray>,
+    referrer: v8::Local<'a, v8::Module>,
+) -> Option<v8::Local<'a, v8::Module>> {
+    with_callback_scope(context, |scope| import_module(scope, specifier, referrer))
+}
+
+// compiles the module specifier resolves to, or returns it if it was compiled before
+fn import_module<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    specifier: v8::Local<'s, v8::String>,
+    referrer: v8::Local<'s, v8::Module>,
+) -> Option<v8::Local<'s, v8::Module>> {
+    let specifier = specifier.to_rust_string_lossy(scope);
+    let module_map = scope
+        .get_slot::<Rc<RefCell<ModuleMap>>>()
+        .expect("failed to get module map slot")
+        .clone();
+
+    let path = {
+        let module_map = module_map.borrow();
+        let referrer = module_map.paths.get(&referrer.get_identity_hash());
+        ModulePath::resolve(&specifier, referrer).confine()
+    };
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            deny(scope, &module_map, &specifier);
+            return None;
+        }
+    };
+
+    if let Some(module) = module_map.borrow().modules.get(&path) {
+        debug!("{} already compiled", path);
+        return Some(v8::Local::new(scope, module));
+    }
+
+    info!("importing {}", specifier);
+
+    if path == ModulePath::Internal(native::NATIVE_MODULE.to_string()) {
+        return compile_module(scope, Some(&path), native::NATIVE_MODULE_SOURCE);
+    }
+
+    if preloaded_exports(scope, &module_map, &path).is_some() {
+        debug!("{} is preloaded", path);
+        return preloaded_module(scope, &path);
+    }
+
+    match path.load(&specifier) {
+        Ok(source) if path.is_commonjs() => compile_module(
+            scope,
+            Some(&path),
+            &[COMMONJS_MODULE_START, &source, COMMONJS_MODULE_END].concat(),
+        ),
+        Ok(source) => compile_module(scope, Some(&path), &source),
+        Err(err) => {
+            let exception_str = v8::String::new(scope, &err.to_string())
+                .expect("failed to create string for exception");
+            let exception = v8::Exception::error(scope, exception_str);
+            scope.throw_exception(exception);
+            None
+        }
+    }
+}
+
+// throws a JS Error with message
+pub fn throw_error(scope: &mut v8::HandleScope<'_>, message: &str) {
+    let exception_str =
+        v8::String::new(scope, message).expect("failed to create string for exception");
+    let exception = v8::Exception::error(scope, exception_str);
+    scope.throw_exception(exception);
+}
+
+// creates a require function, relative paths are resolved against referrer
+pub fn require_function<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    referrer: Option<ModulePath>,
+) -> Option<v8::Local<'s, v8::Function>> {
+    let data: v8::Local<v8::Value> = match referrer {
+        Some(referrer) => {
+            let module_map = scope
+                .get_slot::<Rc<RefCell<ModuleMap>>>()
+                .expect("failed to get module map slot")
+                .clone();
+            let mut module_map = module_map.borrow_mut();
+            module_map.referrers.push(referrer);
+
+            v8::Integer::new(scope, (module_map.referrers.len() - 1) as i32).into()
+        }
+        None => v8::undefined(scope).into(),
+    };
+
+    v8::Function::builder(require).data(data).build(scope)
+}
+
+fn require(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    mut rv: v8::ReturnValue<'_>,
+) {
+    let specifier = match args.get(0).to_string(scope) {
+        Some(specifier) => specifier.to_rust_string_lossy(scope),
+        None => {
+            throw_error(scope, "argument to require is not a string");
+            return;
+        }
+    };
+
+    info!("loading {}", specifier);
+
+    let module_map = scope
+        .get_slot::<Rc<RefCell<ModuleMap>>>()
+        .expect("failed to get module map slot")
+        .clone();
+
+    let path = {
+        let referrer = args
+            .data()
+            .filter(|data| data.is_int32())
+            .and_then(|data| data.int32_value(scope));
+        let module_map = module_map.borrow();
+        let referrer = referrer.and_then(|index| module_map.referrers.get(index as usize));
+        ModulePath::resolve(&specifier, referrer).confine()
+    };
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            deny(scope, &module_map, &specifier);
+            return;
+        }
+    };
+
+    let exports_key = v8::String::new(scope, "exports").expect("failed to create JS string");
+
+    // already loaded, or still loading if this is a circular require
+    let cached = module_map
+        .borrow()
+        .required
+        .get(&path)
+        .map(|module| v8::Local::new(scope, module));
+    if let Some(module) = cached {
+        debug!("{} found in require cache", path);
+        if let Some(exports) = module.get(scope, exports_key.into()) {
+            rv.set(exports);
+        }
+        return;
+    }
+
+    let exports = if path == ModulePath::Internal(native::NATIVE_MODULE.to_string()) {
+        native::create_module(scope).map(v8::Local::<v8::Value>::from)
+    } else {
+        preloaded_exports(scope, &module_map, &path)
+    };
+
+    if let Some(exports) = exports {
+        debug!("{} is built in", path);
+
+        let module = v8::Object::new(scope);
+        module.set(scope, exports_key.into(), exports);
+        let module = v8::Global::new(scope, module);
+        module_map.borrow_mut().required.insert(path, module);
+
+        rv.set(exports);
+        return;
+    }
+
+    let code = path
+        .load(&specifier)
+        // add boop wrapper
+        .map(|raw_source| [BOOP_WRAPPER_START, &raw_source, BOOP_WRAPPER_END].concat())
+        // create JS string
+        .and_then(|source| {
+            v8::String::new(scope, &source)
+                .ok_or_else(|| eyre!("failed to create JS string from source"))
+        });
+
+    let code = match code {
+        Ok(code) => code,
+        Err(err) => {
+            throw_error(scope, &err.to_string());
+            return;
+        }
+    };
+
+    let module = v8::Object::new(scope);
+    let exports = v8::Object::new(scope);
+    module.set(scope, exports_key.into(), exports.into());
+
+    let module_global = v8::Global::new(scope, module);
+    module_map
+        .borrow_mut()
+        .required
+        .insert(path.clone(), module_global);
+
+    let module_require = match require_function(scope, Some(path.clone())) {
+        Some(module_require) => module_require,
+        None => {
+            module_map.borrow_mut().required.remove(&path);
+            throw_error(scope, "failed to create require function");
+            return;
+        }
+    };
+
+    // on failure the exception is left for the caller to catch
+    let undefined: v8::Local<v8::Value> = v8::undefined(scope).into();
+    let result = v8::Script::compile(scope, code, None)
+        .and_then(|script| script.run(scope))
+        .and_then(|wrapper| v8::Local::<v8::Function>::try_from(wrapper).ok())
+        .and_then(|wrapper| {
+            wrapper.call(scope, undefined, &[module.into(), module_require.into()])
+        });
+
+    match result {
+        Some(exports) => rv.set(exports),
+        None => {
+            error!("failed to require {}", path);
+            module_map.borrow_mut().required.remove(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let script = ModulePath::External(PathBuf::from("/scripts/foo/script.js"));
+        let library = ModulePath::Internal("lib/foo/bar.js".to_string());
+
+        assert_eq!(
+            ModulePath::resolve("@boop/base64", Some(&script)),
+            ModulePath::Internal("lib/base64.js".to_string())
+        );
+        assert_eq!(
+            ModulePath::resolve("./sibling", Some(&script)),
+            ModulePath::External(PathBuf::from("/scripts/foo/sibling.js"))
+        );
+        assert_eq!(
+            ModulePath::resolve("../parent.js", Some(&script)),
+            ModulePath::External(PathBuf::from("/scripts/parent.js"))
+        );
+        assert_eq!(
+            ModulePath::resolve("./baz", Some(&library)),
+            ModulePath::Internal("lib/foo/baz.js".to_string())
+        );
+        assert_eq!(
+            ModulePath::resolve("bare", Some(&script)),
+            ModulePath::External(normalize(&script_dirs()[0].join("bare.js")))
+        );
+    }
+
+    #[test]
+    fn test_scan_specifiers() {
+        assert_eq!(
+            scan_specifiers(
+                r#"import foo from "./foo";
+                import { bar } from './bar.js';
+                import "./side-effect";
+                export * from "./reexport";
+                const baz = require("baz");
+                const qux = require ( `../qux` );
+                const lazy = import("./lazy");
+                const name = require(prefix + "x");
+                const other = foo.require("not");
+                const array = Array.from("not");
+                const template = require(`./${name}`);"#
+            ),
+            vec![
+                "../qux",
+                "./bar.js",
+                "./foo",
+                "./lazy",
+                "./reexport",
+                "./side-effect",
+                "baz"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        fs::write(dir_path.join("a.js"), "const b = require(\"./b\");").unwrap();
+        fs::write(dir_path.join("b.js"), "export default 1;").unwrap();
+
+        assert_eq!(
+            scan_dependencies(
+                &dir_path.join("Script.js"),
+                "const a = require(\"./a\"); const c = require(\"./c\");"
+            ),
+            vec![
+                dir_path.join("a.js"),
+                dir_path.join("b.js"),
+                dir_path.join("c.js")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_commonjs() {
+        assert!(ModulePath::Internal("lib/base64.js".to_string()).is_commonjs());
+        assert!(ModulePath::External(PathBuf::from("/scripts/lib.cjs")).is_commonjs());
+        assert!(!ModulePath::External(PathBuf::from("/scripts/lib.js")).is_commonjs());
+        assert_eq!(
+            ModulePath::resolve("./lib.cjs", None),
+            ModulePath::External(normalize(&script_dirs()[0].join("lib.cjs")))
+        );
+    }
+}
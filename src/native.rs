@@ -0,0 +1,544 @@
+use crate::{host::set_function, modules::throw_error};
+use md5::Md5;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rand::RngCore;
+use rusty_v8 as v8;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// specifier scripts use to require or import the native helpers
+pub const NATIVE_MODULE: &str = "lib/native.js";
+
+// ES modules get the exports of `require("@boop/native")` as named and default exports
+pub const NATIVE_MODULE_SOURCE: &str = "const native = require(\"@boop/native\");
+export default native;
+export const {
+    sha1, sha256, md5,
+    base64Encode, base64Decode, base64DecodeBytes,
+    base64UrlEncode, base64UrlDecode, base64UrlDecodeBytes,
+    hexEncode, hexDecode, hexDecodeBytes,
+    percentEncode, percentDecode,
+    uuidV4, uuidV7,
+    randomBytes,
+} = native;
+";
+
+// RFC 3986 unreserved characters are left as is
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+// largest buffer randomBytes will create
+const MAX_RANDOM_BYTES: usize = 1024 * 1024;
+
+pub fn sha1(bytes: &[u8]) -> String {
+    hex::encode(Sha1::digest(bytes))
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+pub fn md5(bytes: &[u8]) -> String {
+    hex::encode(Md5::digest(bytes))
+}
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    base64::encode(bytes)
+}
+
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    base64::decode(text.trim()).map_err(|err| err.to_string())
+}
+
+pub fn base64url_encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+// padding is optional
+pub fn base64url_decode(text: &str) -> Result<Vec<u8>, String> {
+    base64::decode_config(text.trim().trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(|err| err.to_string())
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    hex::encode(bytes)
+}
+
+pub fn hex_decode(text: &str) -> Result<Vec<u8>, String> {
+    hex::decode(text.trim()).map_err(|err| err.to_string())
+}
+
+pub fn percent_encode(text: &str) -> String {
+    utf8_percent_encode(text, UNRESERVED).to_string()
+}
+
+pub fn percent_decode(text: &str) -> Result<String, String> {
+    percent_decode_str(text)
+        .decode_utf8()
+        .map(|text| text.into_owned())
+        .map_err(|err| err.to_string())
+}
+
+pub fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// random UUID
+pub fn uuid_v4() -> String {
+    let mut bytes = [0; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    format_uuid(&bytes)
+}
+
+// time ordered UUID, the first 48 bits are the unix timestamp in milliseconds
+pub fn uuid_v7() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut bytes = [0; 16];
+    rand::thread_rng().fill_bytes(&mut bytes[6..]);
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x70;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    format_uuid(&bytes)
+}
+
+// creates the object returned by `require("@boop/native")`
+pub fn create_module<'s>(scope: &mut v8::HandleScope<'s>) -> Option<v8::Local<'s, v8::Object>> {
+    let native = v8::Object::new(scope);
+
+    match set_functions(scope, native) {
+        Ok(()) => Some(native),
+        Err(err) => {
+            error!("failed to create native module: {}", err);
+            None
+        }
+    }
+}
+
+fn set_functions(
+    scope: &mut v8::HandleScope<'_>,
+    native: v8::Local<'_, v8::Object>,
+) -> eyre::Result<()> {
+    set_function(scope, native, "sha1", native_sha1)?;
+    set_function(scope, native, "sha256", native_sha256)?;
+    set_function(scope, native, "md5", native_md5)?;
+    set_function(scope, native, "base64Encode", native_base64_encode)?;
+    set_function(scope, native, "base64Decode", native_base64_decode)?;
+    set_function(
+        scope,
+        native,
+        "base64DecodeBytes",
+        native_base64_decode_bytes,
+    )?;
+    set_function(scope, native, "base64UrlEncode", native_base64url_encode)?;
+    set_function(scope, native, "base64UrlDecode", native_base64url_decode)?;
+    set_function(
+        scope,
+        native,
+        "base64UrlDecodeBytes",
+        native_base64url_decode_bytes,
+    )?;
+    set_function(scope, native, "hexEncode", native_hex_encode)?;
+    set_function(scope, native, "hexDecode", native_hex_decode)?;
+    set_function(scope, native, "hexDecodeBytes", native_hex_decode_bytes)?;
+    set_function(scope, native, "percentEncode", native_percent_encode)?;
+    set_function(scope, native, "percentDecode", native_percent_decode)?;
+    set_function(scope, native, "uuidV4", native_uuid_v4)?;
+    set_function(scope, native, "uuidV7", native_uuid_v7)?;
+    set_function(scope, native, "randomBytes", native_random_bytes)?;
+    Ok(())
+}
+
+// bytes of a typed array, array buffer or array of numbers
+pub fn value_to_bytes(
+    scope: &mut v8::HandleScope<'_>,
+    value: v8::Local<'_, v8::Value>,
+) -> Option<Vec<u8>> {
+    if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(value) {
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Some(bytes);
+    }
+
+    if let Ok(buffer) = v8::Local::<v8::ArrayBuffer>::try_from(value) {
+        return Some(buffer.get_backing_store().iter().map(Cell::get).collect());
+    }
+
+    if let Ok(array) = v8::Local::<v8::Array>::try_from(value) {
+        return Some(
+            (0..array.length())
+                .map(|index| {
+                    array
+                        .get_index(scope, index)
+                        .and_then(|byte| byte.uint32_value(scope))
+                        .unwrap_or(0) as u8
+                })
+                .collect(),
+        );
+    }
+
+    None
+}
+
+pub fn uint8_array<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    bytes: Vec<u8>,
+) -> Option<v8::Local<'s, v8::Uint8Array>> {
+    let length = bytes.len();
+    let backing_store =
+        v8::ArrayBuffer::new_backing_store_from_boxed_slice(bytes.into_boxed_slice()).make_shared();
+    let buffer = v8::ArrayBuffer::with_backing_store(scope, &backing_store);
+
+    v8::Uint8Array::new(scope, buffer, 0, length)
+}
+
+// strings are UTF-8 encoded
+fn bytes_arg(
+    scope: &mut v8::HandleScope<'_>,
+    args: &v8::FunctionCallbackArguments<'_>,
+    index: i32,
+) -> Vec<u8> {
+    value_to_bytes(scope, args.get(index))
+        .unwrap_or_else(|| string_arg(scope, args, index).into_bytes())
+}
+
+fn string_arg(
+    scope: &mut v8::HandleScope<'_>,
+    args: &v8::FunctionCallbackArguments<'_>,
+    index: i32,
+) -> String {
+    let value = args.get(index);
+    if value.is_null_or_undefined() {
+        return String::new();
+    }
+
+    value
+        .to_string(scope)
+        .map(|value| value.to_rust_string_lossy(scope))
+        .unwrap_or_default()
+}
+
+fn return_string(scope: &mut v8::HandleScope<'_>, mut rv: v8::ReturnValue<'_>, value: &str) {
+    match v8::String::new(scope, value) {
+        Some(value) => rv.set(value.into()),
+        None => throw_error(scope, "failed to create JS string, value is too long"),
+    }
+}
+
+// decoded text has to be valid UTF-8, the Bytes variants of the decoders return anything else
+fn decoded_text(decoded: Result<Vec<u8>, String>) -> Result<String, String> {
+    String::from_utf8(decoded?).map_err(|_| {
+        "decoded bytes aren't valid UTF-8, use the Bytes variant to get a Uint8Array".to_string()
+    })
+}
+
+fn return_decoded(
+    scope: &mut v8::HandleScope<'_>,
+    rv: v8::ReturnValue<'_>,
+    decoded: Result<Vec<u8>, String>,
+) {
+    match decoded_text(decoded) {
+        Ok(text) => return_string(scope, rv, &text),
+        Err(err) => throw_error(scope, &err),
+    }
+}
+
+// decoded bytes are returned as a Uint8Array
+fn return_decoded_bytes(
+    scope: &mut v8::HandleScope<'_>,
+    mut rv: v8::ReturnValue<'_>,
+    decoded: Result<Vec<u8>, String>,
+) {
+    let bytes = match decoded {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            throw_error(scope, &err);
+            return;
+        }
+    };
+
+    match uint8_array(scope, bytes) {
+        Some(array) => rv.set(array.into()),
+        None => throw_error(scope, "failed to create Uint8Array"),
+    }
+}
+
+fn native_sha1(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let bytes = bytes_arg(scope, &args, 0);
+    return_string(scope, rv, &sha1(&bytes));
+}
+
+fn native_sha256(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let bytes = bytes_arg(scope, &args, 0);
+    return_string(scope, rv, &sha256(&bytes));
+}
+
+fn native_md5(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let bytes = bytes_arg(scope, &args, 0);
+    return_string(scope, rv, &md5(&bytes));
+}
+
+fn native_base64_encode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let bytes = bytes_arg(scope, &args, 0);
+    return_string(scope, rv, &base64_encode(&bytes));
+}
+
+fn native_base64_decode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_decoded(scope, rv, base64_decode(&text));
+}
+
+fn native_base64_decode_bytes(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_decoded_bytes(scope, rv, base64_decode(&text));
+}
+
+fn native_base64url_encode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let bytes = bytes_arg(scope, &args, 0);
+    return_string(scope, rv, &base64url_encode(&bytes));
+}
+
+fn native_base64url_decode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_decoded(scope, rv, base64url_decode(&text));
+}
+
+fn native_base64url_decode_bytes(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_decoded_bytes(scope, rv, base64url_decode(&text));
+}
+
+fn native_hex_encode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let bytes = bytes_arg(scope, &args, 0);
+    return_string(scope, rv, &hex_encode(&bytes));
+}
+
+fn native_hex_decode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_decoded(scope, rv, hex_decode(&text));
+}
+
+fn native_hex_decode_bytes(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_decoded_bytes(scope, rv, hex_decode(&text));
+}
+
+fn native_percent_encode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    return_string(scope, rv, &percent_encode(&text));
+}
+
+fn native_percent_decode(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    let text = string_arg(scope, &args, 0);
+    match percent_decode(&text) {
+        Ok(text) => return_string(scope, rv, &text),
+        Err(err) => throw_error(scope, &err),
+    }
+}
+
+fn native_uuid_v4(
+    scope: &mut v8::HandleScope<'_>,
+    _args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    return_string(scope, rv, &uuid_v4());
+}
+
+fn native_uuid_v7(
+    scope: &mut v8::HandleScope<'_>,
+    _args: v8::FunctionCallbackArguments<'_>,
+    rv: v8::ReturnValue<'_>,
+) {
+    return_string(scope, rv, &uuid_v7());
+}
+
+// randomBytes(length) returns a Uint8Array
+fn native_random_bytes(
+    scope: &mut v8::HandleScope<'_>,
+    args: v8::FunctionCallbackArguments<'_>,
+    mut rv: v8::ReturnValue<'_>,
+) {
+    let length = args
+        .get(0)
+        .integer_value(scope)
+        .filter(|length| *length >= 0)
+        .map(|length| length as usize);
+
+    let length = match length {
+        Some(length) if length <= MAX_RANDOM_BYTES => length,
+        _ => {
+            throw_error(
+                scope,
+                &format!(
+                    "randomBytes length must be between 0 and {}",
+                    MAX_RANDOM_BYTES
+                ),
+            );
+            return;
+        }
+    };
+
+    match uint8_array(scope, random_bytes(length)) {
+        Some(array) => rv.set(array.into()),
+        None => throw_error(scope, "failed to create Uint8Array"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashes() {
+        assert_eq!(sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(md5(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "+/8=");
+        assert_eq!(base64_decode("+/8=").unwrap(), vec![0xfb, 0xff]);
+        assert_eq!(base64url_encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(base64url_decode("-_8=").unwrap(), vec![0xfb, 0xff]);
+        assert!(base64_decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode(b"abc"), "616263");
+        assert_eq!(hex_decode("616263").unwrap(), b"abc".to_vec());
+        assert!(hex_decode("xyz").is_err());
+    }
+
+    #[test]
+    fn test_decoded_text() {
+        assert_eq!(decoded_text(hex_decode("616263")).unwrap(), "abc");
+        assert!(decoded_text(hex_decode("ff")).is_err());
+        // decoding errors are passed on
+        assert_eq!(
+            decoded_text(hex_decode("xyz")),
+            Err(hex_decode("xyz").unwrap_err())
+        );
+    }
+
+    #[test]
+    fn test_percent_encoding() {
+        assert_eq!(percent_encode("a b/ü~"), "a%20b%2F%C3%BC~");
+        assert_eq!(percent_decode("a%20b%2F%C3%BC~").unwrap(), "a b/ü~");
+        assert!(percent_decode("%FF").is_err());
+    }
+
+    #[test]
+    fn test_uuid() {
+        for (uuid, version) in &[(uuid_v4(), '4'), (uuid_v7(), '7')] {
+            assert_eq!(uuid.len(), 36);
+            assert_eq!(uuid.chars().nth(14), Some(*version));
+            assert!("89ab".contains(uuid.chars().nth(19).unwrap()));
+            assert_eq!(
+                uuid.char_indices()
+                    .filter(|(_, c)| *c == '-')
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>(),
+                vec![8, 13, 18, 23]
+            );
+        }
+
+        // v7 is ordered by time
+        let first = uuid_v7();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(first[..13] < uuid_v7()[..13]);
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        assert_eq!(random_bytes(16).len(), 16);
+        assert_ne!(random_bytes(32), random_bytes(32));
+    }
+}
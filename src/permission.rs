@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+// capabilities a script can request in its metadata, scripts without any can only transform text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Permission {
+    // state.clipboard, readClipboard() and writeClipboard(text)
+    #[serde(rename = "clipboard")]
+    Clipboard,
+    // storage.get(key), storage.set(key, value) and storage.remove(key), persisted between runs
+    #[serde(rename = "storage")]
+    Storage,
+    // readFile(path)
+    #[serde(rename = "files:read")]
+    FilesRead,
+    // exec(program, args, input)
+    #[serde(rename = "exec")]
+    Exec,
+}
+
+impl Permission {
+    // shown in the consent dialog
+    pub fn description(&self) -> &'static str {
+        match self {
+            Permission::Clipboard => "Read and change the clipboard",
+            Permission::Storage => "Store data between runs",
+            Permission::FilesRead => "Read any file you can read",
+            Permission::Exec => "Run programs on your computer",
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Clipboard => write!(f, "clipboard"),
+            Permission::Storage => write!(f, "storage"),
+            Permission::FilesRead => write!(f, "files:read"),
+            Permission::Exec => write!(f, "exec"),
+        }
+    }
+}
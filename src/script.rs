@@ -1,26 +1,46 @@
-use crate::executor::{ExecutionStatus, Executor, ExecutorError};
+use crate::{
+    executor::{
+        ExecutionRequest, ExecutionStatus, Executor, ExecutorError, ExecutorOptions, TextRange,
+        API_VERSION, DEFAULT_HEAP_LIMIT,
+    },
+    modules, native,
+    permission::Permission,
+    scriptmap::ScriptMap,
+    XDG_DIRS,
+};
 use crossbeam::channel::{bounded, Receiver, Sender};
 use eyre::{Context, Result};
 use fuse_rust::{FuseProperty, Fuseable};
 use serde::Deserialize;
-use std::{fmt, fs, path::PathBuf, thread};
+use std::{fmt, fs, path::PathBuf, thread, time::Instant};
 
 pub struct Script {
     pub metadata: Metadata,
     pub path: PathBuf,
     source: String,
     channel: Option<ExecutorChannel>,
+    // permissions the user granted, a subset of the ones requested in the metadata
+    granted_permissions: Vec<Permission>,
+    // maximum V8 heap size in bytes
+    heap_limit: usize,
+    // when the script was last executed, used to evict idle executors
+    last_used: Option<Instant>,
+    // heap size of the executor after the last execution
+    heap_size: usize,
+    // files the script imports or requires, found in its source when it's loaded and reported by
+    // the executor, it is restarted when one of them changes
+    dependencies: Vec<PathBuf>,
 }
-#[derive(Debug)]
-enum ExecutorJob {
-    Request((String, Option<String>)),
-    Responce(Result<ExecutionStatus, ExecutorError>),
-    Kill,
-}
-
-struct ExecutorChannel {
-    sender: Sender<ExecutorJob>,
-    receiver: Receiver<ExecutorJob>,
+// result of a request and the files the executor has loaded so far
+pub type ExecutorResponse = (Result<ExecutionStatus, ExecutorError>, Vec<PathBuf>);
+
+// the executor thread exits once every requests sender is dropped, so dropping the channel never
+// has to wait for the thread. clones can be used from several threads, each caller only receives
+// after the executor took its request, so responses can't be mixed up
+#[derive(Clone)]
+pub struct ExecutorChannel {
+    requests: Sender<ExecutionRequest>,
+    responses: Receiver<ExecutorResponse>,
 }
 
 #[derive(Debug)]
@@ -28,6 +48,7 @@ pub enum ParseScriptError {
     NoMetadata,
     InvalidMetadata(serde_jsonrc::error::Error),
     FailedToRead(std::io::Error),
+    UnsupportedApi(String, u32),
 }
 
 impl fmt::Display for ParseScriptError {
@@ -36,18 +57,52 @@ impl fmt::Display for ParseScriptError {
             ParseScriptError::NoMetadata => write!(f, "no metadata"),
             ParseScriptError::InvalidMetadata(e) => write!(f, "invalid metadata: {}", e),
             ParseScriptError::FailedToRead(e) => write!(f, "failed to read script: {}", e),
+            ParseScriptError::UnsupportedApi(name, api) => write!(
+                f,
+                "\"{}\" requires script API version {}, but only {} is supported",
+                name, api, API_VERSION
+            ),
+        }
+    }
+}
+
+// where a script was loaded from, user scripts override global scripts which override built-ins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptOrigin {
+    Builtin,
+    Global,
+    User,
+}
+
+impl fmt::Display for ScriptOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptOrigin::Builtin => write!(f, "built-in"),
+            ScriptOrigin::Global => write!(f, "global"),
+            ScriptOrigin::User => write!(f, "user"),
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Metadata {
+    // script API version, see `executor::API_VERSION`
     pub api: u32,
     pub name: String,
     pub description: String,
     pub author: Option<String>,
     pub icon: String,
     pub tags: Option<String>,
+    // keeps the executor alive instead of letting the pool evict it, for scripts that rely on
+    // global state being retained between runs
+    #[serde(default)]
+    pub persistent: bool,
+    // host functions the script needs, anything beyond text transforms requires consent
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    // the script is an ES module, it can `import` and `export function main`
+    #[serde(default)]
+    pub module: bool,
 }
 
 impl Fuseable for Metadata {
@@ -103,129 +158,311 @@ impl Script {
         let mut metadata: Metadata = serde_jsonrc::from_str(&source[start + 3..end])
             .map_err(ParseScriptError::InvalidMetadata)?;
 
+        if metadata.api > API_VERSION {
+            return Err(ParseScriptError::UnsupportedApi(
+                metadata.name,
+                metadata.api,
+            ));
+        }
+
         metadata.icon = metadata.icon.to_lowercase();
 
-        Ok(Script {
+        let mut script = Script {
             metadata,
             source,
             channel: None,
             path,
-        })
+            granted_permissions: Vec::new(),
+            heap_limit: DEFAULT_HEAP_LIMIT,
+            last_used: None,
+            heap_size: 0,
+            dependencies: Vec::new(),
+        };
+        script.scan_dependencies();
+
+        Ok(script)
+    }
+
+    // finds the files the source imports or requires, built-in scripts have no file to resolve
+    // them against
+    pub fn scan_dependencies(&mut self) {
+        self.dependencies = if self.path.as_os_str().is_empty() {
+            Vec::new()
+        } else {
+            modules::scan_dependencies(&self.path, &self.source)
+        };
     }
 
     fn init_executor_thread(&mut self) {
         assert!(self.channel.is_none());
 
-        let (sender, receiver) = bounded(0);
+        let (requests, t_requests) = bounded::<ExecutionRequest>(0);
+        let (t_responses, responses) = bounded::<ExecutorResponse>(0);
 
         {
             let t_name = self.metadata.name.clone();
             let t_source = self.source.clone();
-            let (t_sender, t_receiver) = (sender.clone(), receiver.clone());
+            let t_options = ExecutorOptions {
+                api: self.metadata.api,
+                path: Some(self.path.clone()).filter(|path| path.is_file()),
+                module: self.metadata.module,
+                permissions: self.granted_permissions.clone(),
+                storage_path: Some(self.storage_path()),
+                heap_limit: Some(self.heap_limit),
+            };
 
             thread::spawn(move || {
                 info!("thread spawned for {}", t_name);
 
                 let mut executor = None;
 
-                debug!("executor created");
-
-                loop {
-                    match t_receiver.recv().unwrap() // blocks until receive 
-                    {
-                        ExecutorJob::Request((full_text, selection)) => {
-                            if executor.is_none() {
-                                executor = match Executor::new(&t_source) {
-                                    Ok(executor) => Some(executor),
-                                    Err(err) => {
-                                        warn!("failed to create executor");
-                                        let executor_err = err.downcast::<ExecutorError>().unwrap(); // anything else is unrecoverable
-                                        t_sender.send(ExecutorJob::Responce(Err(executor_err)))
-                                            .wrap_err("Failed to send error responce")
-                                            .unwrap();
-                                        None
-                                    }
+                // blocks until a request is received, fails once the script dropped the channel
+                while let Ok(request) = t_requests.recv() {
+                    if executor.is_none() {
+                        executor = match Executor::with_options(&t_source, t_options.clone()) {
+                            Ok(executor) => Some(executor),
+                            Err(err) => {
+                                warn!("failed to create executor");
+                                let executor_err = err.downcast::<ExecutorError>().unwrap(); // anything else is unrecoverable
+                                let out_of_memory = executor_err == ExecutorError::OutOfMemory;
+                                if t_responses.send((Err(executor_err), Vec::new())).is_err()
+                                    || out_of_memory
+                                {
+                                    info!("exiting thread for {}", t_name);
+                                    return;
                                 }
+                                None
                             }
-
-                            if let Some(executor) = executor.as_mut() {
-                                info!(
-                                    "request received, full_text: {} bytes, selection: {} bytes",
-                                    full_text.len(),
-                                    selection.as_ref().map(|s| s.len()).unwrap_or(0),
-                                );
-                                let result = executor
-                                    .execute(&full_text, selection.as_deref())
-                                    .map_err(|err| err.downcast::<ExecutorError>().unwrap());
-                                t_sender.send(ExecutorJob::Responce(result)).unwrap(); // blocks until send
-                            }
-                        }
-                        ExecutorJob::Responce(_) => {
-                            warn!("executor thread received a responce on channel");
                         }
-                        ExecutorJob::Kill => {
-                            info!("killing thread for {}", t_name);
+                    }
+
+                    if let Some(executor) = executor.as_mut() {
+                        info!(
+                            "request received, full_text: {} bytes, selection: {:?}",
+                            request.full_text.len(),
+                            request.selection,
+                        );
+                        let result = executor
+                            .execute_request(&request)
+                            .map_err(|err| err.downcast::<ExecutorError>().unwrap());
+                        let dependencies = executor.dependencies();
+                        // the isolate is unusable after being terminated
+                        let out_of_memory = matches!(result, Err(ExecutorError::OutOfMemory));
+                        // blocks until the script receives it
+                        if t_responses.send((result, dependencies)).is_err() || out_of_memory {
+                            info!("exiting thread for {}", t_name);
                             return;
                         }
                     }
                 }
+
+                info!("killing thread for {}", t_name);
             });
         };
 
-        self.channel = Some(ExecutorChannel { sender, receiver });
+        self.channel = Some(ExecutorChannel {
+            requests,
+            responses,
+        });
     }
 
-    // kills the thread associated with this script, it will be recreated when `execute` is called
-    pub fn kill_thread(&mut self) {
-        if let Some(channel) = &self.channel {
-            channel.sender.send(ExecutorJob::Kill).unwrap(); // blocks until send
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // sha256 of the source, permissions are granted to one version of a script
+    pub fn checksum(&self) -> String {
+        native::sha256(self.source.as_bytes())
+    }
+
+    // built-in scripts are embedded and have no path
+    pub fn origin(&self) -> ScriptOrigin {
+        if self.path.as_os_str().is_empty() {
+            ScriptOrigin::Builtin
+        } else if self.path.starts_with(ScriptMap::user_scripts_dir()) {
+            ScriptOrigin::User
+        } else {
+            ScriptOrigin::Global
         }
+    }
 
+    // subfolder of the scripts directory the script is in, "Text/Case" for Text/Case/Upper.js
+    pub fn category(&self) -> Option<String> {
+        ScriptMap::roots()
+            .iter()
+            .find_map(|root| self.path.strip_prefix(root).ok())
+            .and_then(|relative| relative.parent())
+            .filter(|folder| !folder.as_os_str().is_empty())
+            .map(|folder| folder.to_string_lossy().to_string())
+    }
+
+    // file the storage host functions persist to
+    fn storage_path(&self) -> PathBuf {
+        XDG_DIRS
+            .get_data_home()
+            .join("storage")
+            .join(format!("{}.json", self.metadata.name.replace('/', "_")))
+    }
+
+    // requested permissions that haven't been granted
+    pub fn missing_permissions(&self, granted: &[Permission]) -> Vec<Permission> {
+        self.metadata
+            .permissions
+            .iter()
+            .filter(|permission| !granted.contains(permission))
+            .copied()
+            .collect()
+    }
+
+    // the executor is recreated when the granted permissions change
+    pub fn set_granted_permissions(&mut self, granted: &[Permission]) {
+        let mut granted_permissions: Vec<Permission> = self
+            .metadata
+            .permissions
+            .iter()
+            .filter(|permission| granted.contains(permission))
+            .copied()
+            .collect();
+        granted_permissions.sort();
+        granted_permissions.dedup();
+
+        if granted_permissions != self.granted_permissions {
+            info!(
+                "permissions of {} changed to {:?}",
+                self.metadata.name, granted_permissions
+            );
+            self.kill_thread();
+            self.granted_permissions = granted_permissions;
+        }
+    }
+
+    // the executor is recreated when the heap limit changes
+    pub fn set_heap_limit(&mut self, heap_limit: usize) {
+        if heap_limit != self.heap_limit {
+            self.kill_thread();
+            self.heap_limit = heap_limit;
+        }
+    }
+
+    // kills the thread associated with this script, it will be recreated when `execute` is called
+    pub fn kill_thread(&mut self) {
+        // dropping the channel disconnects it, the thread exits without being waited on
         self.channel = None;
+        self.heap_size = 0;
+    }
+
+    // true if the script has a thread and isolate
+    pub fn is_running(&self) -> bool {
+        self.channel.is_some()
     }
 
-    pub fn execute(&mut self, full_text: &str, selection: Option<&str>) -> Result<ExecutionStatus> {
+    pub fn last_used(&self) -> Option<Instant> {
+        self.last_used
+    }
+
+    // heap size of the executor in bytes, 0 if it isn't running
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    // kept after the executor stops so libraries stay known
+    pub fn dependencies(&self) -> &[PathBuf] {
+        &self.dependencies
+    }
+
+    pub fn execute(
+        &mut self,
+        full_text: &str,
+        selection: Option<TextRange>,
+    ) -> Result<ExecutionStatus> {
+        self.execute_request(ExecutionRequest::new(full_text, selection))
+    }
+
+    pub fn execute_request(&mut self, request: ExecutionRequest) -> Result<ExecutionStatus> {
+        let channel = self.executor_channel();
+        let response = channel.execute(request);
+        self.finish_execution(&channel, response)
+    }
+
+    // starts the executor if needed, the channel can be used after the scripts lock is released
+    // so other threads aren't blocked while the script runs, pass the response to
+    // `finish_execution` afterwards
+    pub fn executor_channel(&mut self) -> ExecutorChannel {
         if self.channel.is_none() {
             self.init_executor_thread();
         }
-        assert!(self.channel.is_some());
 
-        let channel = self
+        self.last_used = Some(Instant::now());
+
+        self.channel
+            .clone()
+            .expect("executor thread wasn't started")
+    }
+
+    // records what the executor reported, unless it was replaced while the request ran
+    pub fn finish_execution(
+        &mut self,
+        channel: &ExecutorChannel,
+        response: Result<ExecutorResponse>,
+    ) -> Result<ExecutionStatus> {
+        let current = self
             .channel
             .as_ref()
-            .ok_or_else(|| eyre!("Channel is none"))?;
-
-        // send request
-        channel
-            .sender
-            .send(ExecutorJob::Request((
-                full_text.to_owned(),
-                selection.map(|s| s.to_owned()),
-            )))
-            .wrap_err("Channel is disconnected")?;
+            .map_or(false, |own| own.requests.same_channel(&channel.requests));
+
+        // a thread that panicked leaves a disconnected channel, the next execution starts a new one
+        let (status, dependencies) = match response {
+            Ok(response) => response,
+            Err(err) => {
+                if current {
+                    self.kill_thread();
+                }
+                return Err(err);
+            }
+        };
 
-        // receive result
-        let result = channel
-            .receiver
-            .recv()
-            .wrap_err("Receive channel is empty and disconnected")?;
+        if current {
+            self.dependencies.extend(dependencies);
+            self.dependencies.sort();
+            self.dependencies.dedup();
 
-        if let ExecutorJob::Responce(status) = result {
-            return status.map_err(eyre::Report::from);
+            // thread has exited, a new one is spawned on the next execution
+            match &status {
+                Ok(status) => self.heap_size = status.heap_size(),
+                Err(ExecutorError::OutOfMemory) => self.kill_thread(),
+                Err(_) => {}
+            }
         }
 
-        Err(eyre!(
-            "Expected a responce on channel, but got a request: {:?}",
-            result
-        ))
+        status.map_err(eyre::Report::from)
+    }
+}
+
+impl ExecutorChannel {
+    // blocks until the executor answers
+    pub fn execute(&self, request: ExecutionRequest) -> Result<ExecutorResponse> {
+        self.requests
+            .send(request)
+            .wrap_err("Channel is disconnected")?;
+
+        self.responses
+            .recv()
+            .wrap_err("Receive channel is empty and disconnected")
+    }
+}
+
+// executor threads wait for requests until their channel is dropped, this must not block
+impl Drop for Script {
+    fn drop(&mut self) {
+        self.kill_thread();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{executor::TextReplacement, script::ParseScriptError};
-    use std::borrow::Cow;
+    use crate::{executor::TextReplacement, script::ParseScriptError, testing::Fixture};
+    use std::{borrow::Cow, path::Path};
 
     #[test]
     fn test_retain_execution_context() {
@@ -295,7 +532,7 @@ mod tests {
             status.unwrap().into_replacement()
         );
 
-        let status = script.execute("foo", Some("fo"));
+        let status = script.execute("foo", Some(TextRange::new(0, 2)));
         assert!(status.is_ok());
         assert_eq!(
             TextReplacement::Full("true".to_string()),
@@ -303,6 +540,174 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_error_unsupported_api() {
+        let result = Script::from_source(
+            r#"
+            /**
+                {
+                    "api": 9999,
+                    "name": "From the future",
+                    "description": "Test script",
+                    "icon": "html"
+                }
+            **/
+
+            function main(state) {}"#
+                .to_string(),
+            PathBuf::new(),
+        );
+
+        match result {
+            Err(ParseScriptError::UnsupportedApi(name, api)) => {
+                assert_eq!(name, "From the future");
+                assert_eq!(api, 9999);
+            }
+            _ => panic!("expected UnsupportedApi error"),
+        }
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut script = Script::from_source(
+            r#"
+            /**
+                {
+                    "api": 2,
+                    "name": "Paste",
+                    "description": "Test script",
+                    "icon": "html",
+                    "permissions": ["clipboard", "files:read"]
+                }
+            **/
+
+            function main(state) {
+                state.text = typeof readClipboard;
+            }"#
+            .to_string(),
+            PathBuf::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.metadata.permissions,
+            vec![Permission::Clipboard, Permission::FilesRead]
+        );
+        assert_eq!(
+            script.missing_permissions(&[Permission::FilesRead, Permission::Exec]),
+            vec![Permission::Clipboard]
+        );
+
+        assert_eq!(
+            script.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("undefined".to_string())
+        );
+
+        // only requested permissions are granted
+        script.set_granted_permissions(&[Permission::Clipboard, Permission::Exec]);
+        assert_eq!(
+            script.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("function".to_string())
+        );
+        assert_eq!(script.granted_permissions, vec![Permission::Clipboard]);
+    }
+
+    #[test]
+    fn test_out_of_memory_restarts_executor() {
+        let mut script = Script::from_source(
+            r#"
+            /**
+                {
+                    "api": 2,
+                    "name": "Hungry",
+                    "description": "Test script",
+                    "icon": "html"
+                }
+            **/
+
+            let number = 0;
+
+            function main(state) {
+                if (state.text === "eat") {
+                    const food = [];
+                    while (true) {
+                        food.push(new Array(1000).fill(food.length));
+                    }
+                }
+
+                number += 1;
+                state.text = number;
+            }"#
+            .to_string(),
+            PathBuf::new(),
+        )
+        .unwrap();
+        script.set_heap_limit(32 * 1024 * 1024);
+
+        assert_eq!(
+            script.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("1".to_string())
+        );
+        assert_eq!(
+            script
+                .execute("eat", None)
+                .unwrap_err()
+                .downcast::<ExecutorError>()
+                .unwrap(),
+            ExecutorError::OutOfMemory
+        );
+        // state is lost with the old executor
+        assert_eq!(
+            script.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_kill_thread() {
+        let mut scripts: Vec<Script> = (0..2)
+            .map(|_| {
+                Script::from_source(
+                    r#"
+                    /**
+                        {
+                            "api": 2,
+                            "name": "Counter",
+                            "description": "Test script",
+                            "icon": "html"
+                        }
+                    **/
+
+                    let number = 0;
+
+                    function main(state) {
+                        number += 1;
+                        state.text = number;
+                    }"#
+                    .to_string(),
+                    PathBuf::new(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        for script in scripts.iter_mut() {
+            script.execute("", None).unwrap();
+            assert!(script.is_running());
+        }
+
+        let script = &mut scripts[0];
+        script.kill_thread();
+        assert!(!script.is_running());
+        assert_eq!(
+            script.execute("", None).unwrap().into_replacement(),
+            TextReplacement::Full("1".to_string())
+        );
+
+        // dropping running scripts doesn't wait for their threads
+        drop(scripts);
+    }
+
     #[test]
     fn test_builtin_scripts() {
         use rust_embed::RustEmbed;
@@ -317,15 +722,20 @@ mod tests {
             let source: Cow<'static, [u8]> = Scripts::get(&file).unwrap();
             let script_source = String::from_utf8(source.to_vec()).unwrap();
 
-            let full_text = match file.as_ref() {
-                "MinifyJSON.js" => "{\n\n\"foo\":\n\"bar\"}",
-                "SumAll.js" => "100\n9.00\n230\n2.09",
-                _ => "foobar ♈ ♉ ♊ ♋ ♌ ♍ ♎ ♏ ♐ ♑ ♒ ♓ 😁 😝 😋 😄",
-            };
+            // scripts with a fixture are tested by `testing::tests::test_builtin_fixtures`
+            let fixture = Path::new("fixtures").join(&*file);
+            if Fixture::path_for(&fixture).is_file() {
+                continue;
+            }
 
             match Script::from_source(script_source, PathBuf::new()) {
                 Ok(mut script) => {
-                    script.execute(full_text, None).unwrap();
+                    script
+                        .execute(
+                            "foobar ♈ ♉ ♊ ♋ ♌ ♍ ♎ ♏ ♐ ♑ ♒ ♓ 😁 😝 😋 😄",
+                            None,
+                        )
+                        .unwrap();
                 }
                 Err(e) => match e {
                     ParseScriptError::NoMetadata => {
@@ -1,159 +1,1204 @@
+use crossbeam::channel::unbounded;
 use eyre::{Context, Report, Result};
+use fuse_rust::Fuse;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use rust_embed::RustEmbed;
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs,
     path::{Path, PathBuf},
     sync::{Arc, RwLock},
-    thread,
     time::Duration,
 };
 
-use crate::{script::Script, XDG_DIRS};
+use crate::{
+    script::{ParseScriptError, Script, ScriptOrigin},
+    XDG_DIRS,
+};
+
+pub(crate) struct ScriptMap {
+    // scripts by name
+    pub scripts: BTreeMap<String, Script>,
+    // the reason each script file that failed to load was skipped
+    pub load_errors: BTreeMap<PathBuf, String>,
+    // embedded file of each built-in script by script name, filled by load_internal
+    pub builtin_files: HashMap<String, String>,
+    // script name of every file that loaded, including files hidden by a later one with the same
+    // name, so a rebuild knows which file takes over
+    files: BTreeMap<PathBuf, String>,
+}
+
+// a change the watcher picked up, sent to the main loop so the UI can show it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ScriptChange {
+    Added(String),
+    Reloaded(String),
+    Removed(String),
+    // a library the script requires changed, its executor was stopped
+    Restarted(String),
+    // file and the reason it couldn't be loaded
+    Failed(PathBuf, String),
+}
+
+impl ScriptChange {
+    pub(crate) fn is_error(&self) -> bool {
+        matches!(self, ScriptChange::Failed(..))
+    }
+
+    // describes the changes that went through, the change itself if there is only one, otherwise
+    // how many scripts changed in each way, errors are left out
+    pub(crate) fn summary(changes: &[ScriptChange]) -> Option<String> {
+        let changes: Vec<&ScriptChange> =
+            changes.iter().filter(|change| !change.is_error()).collect();
+        if let [change] = changes.as_slice() {
+            return Some(change.to_string());
+        }
+
+        let count = |kind: fn(&ScriptChange) -> bool| changes.iter().filter(|c| kind(c)).count();
+        let counts = [
+            ("Added", count(|c| matches!(c, ScriptChange::Added(_)))),
+            (
+                "Reloaded",
+                count(|c| matches!(c, ScriptChange::Reloaded(_))),
+            ),
+            ("Removed", count(|c| matches!(c, ScriptChange::Removed(_)))),
+            (
+                "Restarted",
+                count(|c| matches!(c, ScriptChange::Restarted(_))),
+            ),
+        ];
+        let parts: Vec<String> = counts
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .enumerate()
+            .map(|(i, (kind, count))| {
+                format!(
+                    "{} {} script{}",
+                    if i == 0 {
+                        kind.to_string()
+                    } else {
+                        kind.to_lowercase()
+                    },
+                    count,
+                    if *count == 1 { "" } else { "s" }
+                )
+            })
+            .collect();
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for ScriptChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptChange::Added(name) => write!(f, "Added {}", name),
+            ScriptChange::Reloaded(name) => write!(f, "Reloaded {}", name),
+            ScriptChange::Removed(name) => write!(f, "Removed {}", name),
+            ScriptChange::Restarted(name) => {
+                write!(f, "Restarted {}, a library it uses changed", name)
+            }
+            ScriptChange::Failed(path, err) => write!(
+                f,
+                "{} failed to parse: {}",
+                path.file_stem().unwrap_or_default().to_string_lossy(),
+                err
+            ),
+        }
+    }
+}
 
-pub(crate) struct ScriptMap(pub BTreeMap<String, Script>);
+impl From<BTreeMap<String, Script>> for ScriptMap {
+    fn from(scripts: BTreeMap<String, Script>) -> Self {
+        ScriptMap {
+            scripts,
+            load_errors: BTreeMap::new(),
+            builtin_files: HashMap::new(),
+            files: BTreeMap::new(),
+        }
+    }
+}
 
 #[derive(RustEmbed)]
 #[folder = "submodules/Boop/Boop/Boop/scripts/"]
 pub(crate) struct Scripts;
 
+// how long the watcher waits for more changes before rebuilding the scripts
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
 impl ScriptMap {
     pub(crate) fn new() -> (Self, Option<Report>) {
-        let mut scripts = ScriptMap(BTreeMap::new());
+        let mut scripts = ScriptMap::from(BTreeMap::new());
 
         scripts.load_internal();
 
-        for mut dir in XDG_DIRS.get_config_dirs() {
-            dir.push("scripts");
-
-            if std::fs::read_dir(&dir).is_ok() {
-                // load scripts (overrides any internal scripts)
+        // each directory overrides scripts with the same name in the ones before it
+        let user_scripts_dir = ScriptMap::user_scripts_dir();
+        let mut load_result = Ok(());
+        for dir in ScriptMap::roots() {
+            if dir == user_scripts_dir {
+                load_result = scripts.load_path(&dir);
+            } else if dir.is_dir() {
                 scripts.load_path(&dir).ok();
             }
         }
-
-        // load user scripts overriding internal and global scripts
-        let load_result = scripts.load_path(&ScriptMap::user_scripts_dir());
+        scripts.forget_library_errors();
 
         (scripts, load_result.err())
     }
 
-    fn user_scripts_dir() -> PathBuf {
+    // directories scripts are loaded from, later ones take precedence: the global directories, the
+    // first in $XDG_CONFIG_DIRS last, then the user scripts directory
+    pub(crate) fn roots() -> Vec<PathBuf> {
+        let mut roots: Vec<PathBuf> = XDG_DIRS
+            .get_config_dirs()
+            .into_iter()
+            .rev()
+            .map(|dir| dir.join("scripts"))
+            .collect();
+        roots.push(ScriptMap::user_scripts_dir());
+        roots
+    }
+
+    pub(crate) fn user_scripts_dir() -> PathBuf {
         let mut dir = XDG_DIRS.get_config_home();
         dir.push("scripts");
         dir
     }
 
+    // loads every script again, scripts that are running are stopped
+    pub(crate) fn reload(&mut self) -> Option<Report> {
+        info!("reloading scripts");
+
+        let (scripts, load_error) = ScriptMap::new();
+        *self = scripts;
+
+        load_error
+    }
+
+    // loads the changed files and folders again, the other scripts and the ones whose file didn't
+    // change keep running
+    pub(crate) fn rebuild(&mut self, changed: &[PathBuf]) -> Vec<ScriptChange> {
+        self.rebuild_in(changed, &ScriptMap::roots())
+    }
+
+    fn rebuild_in(&mut self, changed: &[PathBuf], roots: &[PathBuf]) -> Vec<ScriptChange> {
+        let mut changes = Vec::new();
+        let previous_errors = std::mem::take(&mut self.load_errors);
+        let is_changed = |path: &Path| changed.iter().any(|changed| path.starts_with(changed));
+
+        // names that may now belong to another file, or to no file at all
+        let mut names = BTreeSet::new();
+        self.files.retain(|path, name| {
+            if is_changed(path) {
+                names.insert(name.clone());
+            }
+            !is_changed(path)
+        });
+        self.load_errors = previous_errors
+            .iter()
+            .filter(|(path, _)| !is_changed(path))
+            .map(|(path, err)| (path.clone(), err.clone()))
+            .collect();
+
+        let mut loaded = BTreeMap::new();
+        for path in changed_files(changed, roots) {
+            match Script::from_file(path.clone()) {
+                Ok(script) => {
+                    names.insert(script.metadata.name.clone());
+                    self.files
+                        .insert(path.clone(), script.metadata.name.clone());
+                    loaded.insert(path, script);
+                }
+                Err(err) => {
+                    debug!("skipping {}: {}", path.display(), err);
+                    if path
+                        .extension()
+                        .map_or(false, |extension| extension == "js")
+                    {
+                        self.load_errors.insert(path, err.to_string());
+                    }
+                }
+            }
+        }
+
+        for name in names {
+            // like in ScriptMap::new, later directories and later files in a directory win
+            let winner = self
+                .files
+                .iter()
+                .filter(|(_, file_name)| **file_name == name)
+                .map(|(path, _)| path)
+                .max_by_key(|path| (roots.iter().position(|root| path.starts_with(root)), *path))
+                .cloned();
+            let next = match winner {
+                // an unchanged file that was hidden by a changed one is read again
+                Some(path) => loaded
+                    .remove(&path)
+                    .or_else(|| Script::from_file(path).ok()),
+                None => self.load_builtin(&name).ok(),
+            };
+
+            match (self.scripts.remove(&name), next) {
+                (Some(current), Some(next))
+                    if current.path == next.path && current.source() == next.source() =>
+                {
+                    self.scripts.insert(name, current);
+                }
+                (Some(_), Some(next)) => {
+                    info!("reloaded {}", name);
+                    changes.push(ScriptChange::Reloaded(name.clone()));
+                    // dropping the old script stops its executor
+                    self.scripts.insert(name, next);
+                }
+                (None, Some(next)) => {
+                    info!("added {}", name);
+                    changes.push(ScriptChange::Added(name.clone()));
+                    self.scripts.insert(name, next);
+                }
+                (Some(_), None) => {
+                    info!("unloaded {}", name);
+                    changes.push(ScriptChange::Removed(name));
+                }
+                (None, None) => {}
+            }
+        }
+
+        self.forget_library_errors();
+
+        // errors are only reported when they are new, not on every rebuild
+        for (path, err) in &self.load_errors {
+            if previous_errors.get(path) != Some(err) {
+                changes.push(ScriptChange::Failed(path.clone(), err.clone()));
+            }
+        }
+
+        changes
+    }
+
+    // libraries outside of lib folders have no metadata either, they aren't broken scripts
+    fn forget_library_errors(&mut self) {
+        let dependencies: BTreeSet<PathBuf> = self
+            .scripts
+            .values()
+            .flat_map(|script| script.dependencies().iter().cloned())
+            .collect();
+        self.load_errors
+            .retain(|path, _| !dependencies.contains(&canonical_path(path)));
+    }
+
+    // stops the executors of scripts that imported or required a changed file (or a file in a
+    // changed folder), they load the new version when they run next
+    pub(crate) fn restart_dependents(&mut self, changed: &[PathBuf]) -> Vec<ScriptChange> {
+        let changed: Vec<PathBuf> = changed.iter().map(|path| canonical_path(path)).collect();
+        let mut changes = Vec::new();
+
+        for script in self.scripts.values_mut() {
+            let depends_on_changed = script
+                .dependencies()
+                .iter()
+                .any(|dependency| changed.iter().any(|path| dependency.starts_with(path)));
+
+            if !depends_on_changed {
+                continue;
+            }
+
+            // the library may require other files now
+            script.scan_dependencies();
+
+            // scripts that aren't running load the new version anyway
+            if script.is_running() {
+                info!(
+                    "restarting {}, a file it depends on changed",
+                    script.metadata.name
+                );
+                script.kill_thread();
+                changes.push(ScriptChange::Restarted(script.metadata.name.clone()));
+            }
+        }
+
+        changes
+    }
+
+    // file name for a new user script, "Upper Case" is saved as UpperCase.js
+    pub(crate) fn file_name(name: &str) -> String {
+        let stem: String = name
+            .split_whitespace()
+            .flat_map(|word| {
+                let mut chars = word.chars().filter(|c| c.is_alphanumeric());
+                chars
+                    .next()
+                    .into_iter()
+                    .flat_map(char::to_uppercase)
+                    .chain(chars)
+                    .collect::<Vec<char>>()
+            })
+            .collect();
+
+        if stem.is_empty() {
+            "Script.js".to_string()
+        } else {
+            format!("{}.js", stem)
+        }
+    }
+
+    // copies a script to the user scripts directory under a new name, returns the new name
+    pub(crate) fn duplicate(&mut self, name: &str) -> Result<String> {
+        self.duplicate_in(name, &ScriptMap::user_scripts_dir())
+    }
+
+    fn duplicate_in(&mut self, name: &str, dir: &Path) -> Result<String> {
+        let source = self
+            .scripts
+            .get(name)
+            .map(|script| script.source().to_string())
+            .ok_or_else(|| eyre!("No script named \"{}\"", name))?;
+
+        // "Format JSON Copy", "Format JSON Copy 2", ...
+        let (copy_name, path) = (1..)
+            .map(|n| match n {
+                1 => format!("{} Copy", name),
+                n => format!("{} Copy {}", name, n),
+            })
+            .map(|copy_name| {
+                let path = dir.join(ScriptMap::file_name(&copy_name));
+                (copy_name, path)
+            })
+            .find(|(copy_name, path)| !self.scripts.contains_key(copy_name) && !path.exists())
+            .expect("Ran out of names");
+
+        let source = rename_source(&source, name, &copy_name)
+            .ok_or_else(|| eyre!("Failed to find the name of \"{}\" in its metadata", name))?;
+
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        fs::write(&path, source).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+        info!("duplicated {} to {}", name, path.display());
+
+        let script = Script::from_file(path.clone())
+            .map_err(|err| eyre!("Failed to load {}: {}", path.display(), err))?;
+        self.scripts.insert(copy_name.clone(), script);
+
+        Ok(copy_name)
+    }
+
+    // deletes a user script, the built-in script it overrides takes its place
+    pub(crate) fn delete(&mut self, name: &str) -> Result<()> {
+        self.delete_in(name, &ScriptMap::user_scripts_dir())
+    }
+
+    fn delete_in(&mut self, name: &str, dir: &Path) -> Result<()> {
+        if self.builtin_files.contains_key(name) {
+            return self.revert_to_builtin_in(name, dir);
+        }
+
+        let path = match self.scripts.get(name) {
+            Some(script) if script.path.starts_with(dir) => script.path.clone(),
+            _ => return Err(eyre!("\"{}\" is not a user script", name)),
+        };
+
+        fs::remove_file(&path).wrap_err_with(|| format!("Failed to delete {}", path.display()))?;
+        info!("deleted {}", path.display());
+
+        self.scripts.remove(name);
+
+        Ok(())
+    }
+
     // load scripts included in the binary
-    fn load_internal(&mut self) {
+    pub(crate) fn load_internal(&mut self) {
         for file in Scripts::iter() {
             // scripts are internal, so we can unwrap "safely"
             let script_source = String::from_utf8(Scripts::get(&file).unwrap().to_vec()).unwrap();
             if let Ok(script) = Script::from_source(script_source, PathBuf::new()) {
-                self.0.insert(script.metadata.name.clone(), script);
+                self.builtin_files
+                    .insert(script.metadata.name.clone(), file.to_string());
+                self.scripts.insert(script.metadata.name.clone(), script);
             }
         }
 
         info!("loaded {} internal scripts", Scripts::iter().count());
     }
 
-    // load scripts from a path
+    // true if a global or user script replaces the built-in script with the same name
+    pub(crate) fn is_overridden(&self, name: &str) -> bool {
+        self.builtin_files.contains_key(name)
+            && self
+                .scripts
+                .get(name)
+                .map_or(false, |script| script.origin() != ScriptOrigin::Builtin)
+    }
+
+    // copies a built-in script to the user scripts directory so it can be edited, returns the path
+    // of the copy
+    pub(crate) fn override_builtin(&mut self, name: &str) -> Result<PathBuf> {
+        self.override_builtin_in(name, &ScriptMap::user_scripts_dir())
+    }
+
+    fn override_builtin_in(&mut self, name: &str, dir: &Path) -> Result<PathBuf> {
+        if self.scripts.get(name).map(Script::origin) != Some(ScriptOrigin::Builtin) {
+            return Err(eyre!("\"{}\" is not a built-in script", name));
+        }
+
+        let file = self
+            .builtin_files
+            .get(name)
+            .cloned()
+            .ok_or_else(|| eyre!("No built-in script named \"{}\"", name))?;
+        let source = Scripts::get(&file).ok_or_else(|| eyre!("{} is not embedded", file))?;
+
+        let path = dir.join(
+            Path::new(&file)
+                .file_name()
+                .ok_or_else(|| eyre!("{} has no file name", file))?,
+        );
+        if path.exists() {
+            return Err(eyre!("{} already exists", path.display()));
+        }
+
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        fs::write(&path, source).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+        info!("copied {} to {}", name, path.display());
+
+        // don't wait for the watcher, the copy replaces the built-in straight away
+        let script = Script::from_file(path.clone())
+            .map_err(|err| eyre!("Failed to load {}: {}", path.display(), err))?;
+        self.scripts.insert(name.to_string(), script);
+
+        Ok(path)
+    }
+
+    // deletes the user script overriding a built-in script and restores the built-in
+    pub(crate) fn revert_to_builtin(&mut self, name: &str) -> Result<()> {
+        self.revert_to_builtin_in(name, &ScriptMap::user_scripts_dir())
+    }
+
+    fn revert_to_builtin_in(&mut self, name: &str, dir: &Path) -> Result<()> {
+        let path = match self.scripts.get(name) {
+            Some(script) if script.path.starts_with(dir) => script.path.clone(),
+            _ => return Err(eyre!("\"{}\" is not a user script", name)),
+        };
+
+        let builtin = self.load_builtin(name)?;
+
+        fs::remove_file(&path).wrap_err_with(|| format!("Failed to delete {}", path.display()))?;
+        info!("deleted {}, reverted to built-in {}", path.display(), name);
+
+        // dropping the old script stops its executor
+        self.scripts.insert(name.to_string(), builtin);
+
+        Ok(())
+    }
+
+    // a fresh copy of the built-in script with the given name
+    pub(crate) fn load_builtin(&self, name: &str) -> Result<Script> {
+        let file = self
+            .builtin_files
+            .get(name)
+            .ok_or_else(|| eyre!("No built-in script named \"{}\"", name))?;
+        let source = String::from_utf8(
+            Scripts::get(file)
+                .ok_or_else(|| eyre!("{} is not embedded", file))?
+                .to_vec(),
+        )?;
+
+        Script::from_source(source, PathBuf::new())
+            .map_err(|err| eyre!("Failed to load built-in {}: {}", name, err))
+    }
+
+    // load scripts from a path and its subfolders
     fn load_path(&mut self, dir: &Path) -> Result<()> {
-        let paths = fs::read_dir(dir)
+        let mut paths = Vec::new();
+        let entries = fs::read_dir(dir)
             .wrap_err_with(|| format!("Failed to read scripts directory: {}", dir.display()))?;
+        collect_files(entries, &mut paths);
+        // when two files use the same name the last one wins, sorting makes that predictable
+        paths.sort();
+
+        let mut unsupported = Vec::new();
+        let mut load_errors = Vec::new();
+
+        let scripts: Vec<(String, Script)> = paths
+            .into_iter()
+            .filter_map(|path| match Script::from_file(path.clone()) {
+                Ok(script) => {
+                    self.files.insert(path, script.metadata.name.clone());
+                    Some(script)
+                }
+                Err(e) => {
+                    if let ParseScriptError::UnsupportedApi(name, api) = &e {
+                        warn!("{} requires script API version {}", path.display(), api);
+                        unsupported.push(name.clone());
+                    } else {
+                        debug!("skipping {}: {}", path.display(), e);
+                    }
 
-        let scripts: HashMap<String, Script> = paths
-            .filter_map(Result::ok)
-            .map(|f| f.path())
-            .filter(|path| path.is_file())
-            .map(Script::from_file)
-            .filter_map(Result::ok)
+                    // other files (like fixtures) can live next to scripts
+                    if path
+                        .extension()
+                        .map_or(false, |extension| extension == "js")
+                    {
+                        load_errors.push((path, e.to_string()));
+                    }
+                    None
+                }
+            })
             .map(|script| (script.metadata.name.clone(), script))
             .collect();
 
         info!("loaded {} scripts from {}", scripts.len(), dir.display());
 
-        self.0.extend(scripts);
+        self.scripts.extend(scripts);
+        self.load_errors.extend(load_errors);
+
+        if !unsupported.is_empty() {
+            return Err(eyre!(
+                "{} require a newer version of Boop-GTK",
+                unsupported.join(", ")
+            ));
+        }
 
         Ok(())
     }
 
-    pub(crate) fn watch(scripts: Arc<RwLock<Self>>) {
+    // fuzzy search of script names, returns the score of each match by name, lower is better
+    pub(crate) fn search(&self, query: &str) -> HashMap<String, f64> {
+        let scripts = self.scripts.values().collect::<Vec<&Script>>();
+
+        Fuse::default()
+            .search_text_in_fuse_list(query, &*scripts)
+            .into_iter()
+            .map(|result| (scripts[result.index].metadata.name.clone(), result.score))
+            .collect()
+    }
+
+    // stops the least recently used executors until at most pool_size are running, persistent
+    // scripts are never stopped and don't count towards the pool
+    pub(crate) fn enforce_pool_size(&mut self, pool_size: usize) {
+        let mut running: Vec<&mut Script> = self
+            .scripts
+            .values_mut()
+            .filter(|script| script.is_running() && !script.metadata.persistent)
+            .collect();
+
+        if running.len() <= pool_size {
+            return;
+        }
+
+        running.sort_by_key(|script| script.last_used());
+
+        let evict = running.len() - pool_size;
+        for script in running.into_iter().take(evict) {
+            info!(
+                "evicting {} ({} bytes heap)",
+                script.metadata.name,
+                script.heap_size()
+            );
+            script.kill_thread();
+        }
+
+        self.log_pool_metrics();
+    }
+
+    // heap size of each running executor
+    pub(crate) fn pool_metrics(&self) -> Vec<(&str, usize)> {
+        self.scripts
+            .values()
+            .filter(|script| script.is_running())
+            .map(|script| (script.metadata.name.as_str(), script.heap_size()))
+            .collect()
+    }
+
+    pub(crate) fn log_pool_metrics(&self) {
+        let metrics = self.pool_metrics();
+        let total: usize = metrics.iter().map(|(_, heap_size)| heap_size).sum();
+
+        info!(
+            "{} executors running, {} bytes heap in total",
+            metrics.len(),
+            total
+        );
+        for (name, heap_size) in metrics {
+            debug!("{}: {} bytes heap", name, heap_size);
+        }
+    }
+
+    // rebuilds the scripts when their files change, each batch of changes is sent to the main loop
+    pub(crate) fn watch(scripts: Arc<RwLock<Self>>, changes: glib::Sender<Vec<ScriptChange>>) {
         trace!("watch_scripts_folder");
 
-        // watch for changes to script folder
-        let watcher: notify::Result<RecommendedWatcher> = Watcher::new_immediate(move |res| {
-            debug!("res: {:?}", res);
-            match res {
+        let (sender, receiver) = unbounded();
+
+        let watcher: notify::Result<RecommendedWatcher> =
+            Watcher::new_immediate(move |res: notify::Result<notify::Event>| match res {
                 Ok(event) => {
-                    let event: notify::Event = event;
-
-                    for file in event.paths {
-                        debug!("file: {}", file.display());
-
-                        if file.extension().filter(|&s| s == "js").is_none() {
-                            break;
-                        }
-
-                        info!("{} changed, reloading", file.display());
-
-                        let mut scripts = scripts.write().expect("script lock is poisoned");
-
-                        // remove script
-                        // TODO: replace with drain_filter when stabalized
-                        let mut matched = None;
-                        for (name, script) in scripts.0.iter() {
-                            if script.path == file {
-                                matched = Some(name.clone());
-                            }
-                        }
-                        if let Some(name) = matched {
-                            scripts.0.remove(&name);
-                        }
-                        // scripts.0.drain_filter(|_, script| script.path == file);
-
-                        if !file.exists() {
-                            // file was deleted
-                            break;
-                        }
-
-                        match Script::from_file(file.clone()) {
-                            Ok(script) => {
-                                // file added or changed
-                                scripts.0.insert(script.metadata.name.clone(), script);
-                            }
-                            Err(e) => {
-                                warn!("error parsing {}: {}", file.display(), e);
-                            }
-                        }
-                    }
+                    debug!("event: {:?}", event);
+                    sender.send(event).ok();
                 }
                 Err(e) => error!("watch error: {:?}", e),
-            }
-        });
+            });
 
         // configure and start watcher
-        match watcher {
-            Ok(mut watcher) => {
-                let script_dir = &ScriptMap::user_scripts_dir();
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(watcher_error) => {
+                error!("couldn't create watcher: {}", watcher_error);
+                return;
+            }
+        };
 
-                info!("watching {}", script_dir.display());
+        let user_scripts_dir = ScriptMap::user_scripts_dir();
+        if let Err(err) = fs::create_dir_all(&user_scripts_dir) {
+            warn!("failed to create {}: {}", user_scripts_dir.display(), err);
+        }
 
-                if let Err(watch_error) = watcher.watch(&script_dir, RecursiveMode::Recursive) {
-                    error!("watch start error: {}", watch_error);
-                    return;
-                }
+        for dir in ScriptMap::roots() {
+            if !dir.is_dir() {
+                debug!("not watching {}, it doesn't exist", dir.display());
+                continue;
+            }
 
-                // keep the thread alive
-                loop {
-                    trace!("watching!");
-                    thread::sleep(Duration::from_millis(1000));
-                }
+            info!("watching {}", dir.display());
+
+            if let Err(watch_error) = watcher.watch(&dir, RecursiveMode::Recursive) {
+                error!("watch start error: {}", watch_error);
             }
-            Err(watcher_error) => {
-                error!("couldn't create watcher: {}", watcher_error);
+        }
+
+        // editors tend to save with several events (write a temporary file, rename it over the
+        // script), so the scripts are rebuilt once the events stop
+        while let Ok(event) = receiver.recv() {
+            let mut changed = changed_paths(event);
+            while let Ok(event) = receiver.recv_timeout(WATCH_DEBOUNCE) {
+                changed.extend(changed_paths(event));
             }
+
+            if changed.is_empty() {
+                continue;
+            }
+
+            info!("scripts changed, rebuilding");
+
+            let script_changes = {
+                let mut scripts = scripts.write().expect("script lock is poisoned");
+                let mut script_changes = scripts.rebuild(&changed);
+                script_changes.extend(scripts.restart_dependents(&changed));
+                script_changes
+            };
+
+            if !script_changes.is_empty() && changes.send(script_changes).is_err() {
+                debug!("main loop is gone, no longer sending script changes");
+            }
+        }
+    }
+}
+
+// changed scripts, libraries and folders, fixtures and other files don't need a rebuild
+fn changed_paths(event: notify::Event) -> Vec<PathBuf> {
+    if let notify::EventKind::Access(_) = event.kind {
+        return Vec::new();
+    }
+
+    event
+        .paths
+        .into_iter()
+        .filter(|path| path.extension().map_or(true, |extension| extension == "js"))
+        .collect()
+}
+
+// files under the changed paths that are loaded as scripts, lib and hidden folders are skipped like
+// in collect_files, paths outside the script directories are ignored
+fn changed_files(changed: &[PathBuf], roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for path in changed {
+        let relative = match roots.iter().find_map(|root| path.strip_prefix(root).ok()) {
+            Some(relative) => relative,
+            None => continue,
+        };
+
+        let folders = if path.is_dir() {
+            relative
+        } else {
+            relative.parent().unwrap_or_else(|| Path::new(""))
+        };
+        let skipped = folders.components().any(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            name == "lib" || name.starts_with('.')
+        });
+        if skipped {
+            continue;
+        }
+
+        if path.is_dir() {
+            match fs::read_dir(path) {
+                Ok(entries) => collect_files(entries, &mut files),
+                Err(err) => warn!("failed to read {}: {}", path.display(), err),
+            }
+        } else if path.is_file() {
+            files.push(path.clone());
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    files
+}
+
+// dependencies are stored canonicalized, removed files are resolved through their folder
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .ok()
+        .or_else(|| {
+            let parent = path.parent()?.canonicalize().ok()?;
+            Some(parent.join(path.file_name()?))
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// files in a scripts directory, including its subfolders, lib folders hold the libraries scripts
+// require rather than scripts
+fn collect_files(entries: fs::ReadDir, files: &mut Vec<PathBuf>) {
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+
+        // symlinked folders aren't followed so they can't loop
+        let is_dir = entry
+            .file_type()
+            .map_or(false, |file_type| file_type.is_dir());
+        if !is_dir {
+            if path.is_file() {
+                files.push(path);
+            }
+            continue;
+        }
+
+        let name = entry.file_name();
+        if name == "lib" || name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        match fs::read_dir(&path) {
+            Ok(entries) => collect_files(entries, files),
+            Err(err) => warn!("failed to read {}: {}", path.display(), err),
+        }
+    }
+}
+
+// replaces the name in the metadata of a script, the rest of the source is kept as it is
+fn rename_source(source: &str, name: &str, new_name: &str) -> Option<String> {
+    let end = source.find("**/")?;
+    let key = source[..end].find("\"name\"")?;
+
+    let quoted_name = serde_jsonrc::to_string(name).ok()?;
+    let start = key + source[key..end].find(&quoted_name)?;
+
+    Some(format!(
+        "{}{}{}",
+        &source[..start],
+        serde_jsonrc::to_string(new_name).ok()?,
+        &source[start + quoted_name.len()..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::TextReplacement;
+
+    fn script(name: &str, persistent: bool) -> Script {
+        Script::from_source(
+            format!(
+                "/**
+                    {{
+                        \"api\": 2,
+                        \"name\": \"{}\",
+                        \"description\": \"Test script\",
+                        \"icon\": \"html\",
+                        \"persistent\": {}
+                    }}
+                **/
+
+                function main(state) {{}}",
+                name, persistent
+            ),
+            PathBuf::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_enforce_pool_size() {
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        for (name, persistent) in &[("A", true), ("B", false), ("C", false), ("D", false)] {
+            scripts
+                .scripts
+                .insert(name.to_string(), script(name, *persistent));
+        }
+
+        for name in &["A", "B", "C", "D", "B"] {
+            scripts
+                .scripts
+                .get_mut(*name)
+                .unwrap()
+                .execute("", None)
+                .unwrap();
+        }
+
+        scripts.enforce_pool_size(1);
+
+        let running: Vec<&str> = scripts
+            .pool_metrics()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(running, vec!["A", "B"]);
+        assert!(scripts
+            .pool_metrics()
+            .iter()
+            .all(|(_, heap_size)| *heap_size > 0));
+
+        scripts.enforce_pool_size(0);
+        assert_eq!(scripts.pool_metrics().len(), 1);
+    }
+
+    #[test]
+    fn test_change_summary() {
+        assert_eq!(ScriptChange::summary(&[]), None);
+        assert_eq!(
+            ScriptChange::summary(&[
+                ScriptChange::Removed("Foo".to_string()),
+                ScriptChange::Failed(PathBuf::from("Bar.js"), "oops".to_string()),
+            ]),
+            Some("Removed Foo".to_string())
+        );
+        assert_eq!(
+            ScriptChange::summary(&[
+                ScriptChange::Reloaded("Foo".to_string()),
+                ScriptChange::Added("Bar".to_string()),
+                ScriptChange::Reloaded("Baz".to_string()),
+            ]),
+            Some("Added 1 script, reloaded 2 scripts".to_string())
+        );
+        assert_eq!(
+            ScriptChange::summary(&[ScriptChange::Failed(
+                PathBuf::from("Bar.js"),
+                "oops".to_string()
+            )]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = vec![dir.path().to_path_buf()];
+        let write = |file: &str, name: &str, persistent: bool| {
+            fs::write(dir.path().join(file), script(name, persistent).source()).unwrap();
+        };
+        for name in &["Kept", "Changed", "Removed"] {
+            write(&format!("{}.js", name), name, false);
         }
+
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        scripts.load_path(dir.path()).unwrap();
+        for name in &["Kept", "Changed", "Removed"] {
+            scripts
+                .scripts
+                .get_mut(*name)
+                .unwrap()
+                .execute("", None)
+                .unwrap();
+        }
+
+        write("Changed.js", "Changed", true);
+        write("Added.js", "Added", false);
+        fs::remove_file(dir.path().join("Removed.js")).unwrap();
+        fs::write(dir.path().join("Broken.js"), "function main() {}").unwrap();
+
+        let changed: Vec<PathBuf> = ["Changed.js", "Added.js", "Removed.js", "Broken.js"]
+            .iter()
+            .map(|file| dir.path().join(file))
+            .collect();
+        assert_eq!(
+            scripts.rebuild_in(&changed, &roots),
+            vec![
+                ScriptChange::Added("Added".to_string()),
+                ScriptChange::Reloaded("Changed".to_string()),
+                ScriptChange::Removed("Removed".to_string()),
+                ScriptChange::Failed(
+                    dir.path().join("Broken.js"),
+                    ParseScriptError::NoMetadata.to_string()
+                ),
+            ]
+        );
+
+        assert_eq!(
+            scripts.scripts.keys().collect::<Vec<_>>(),
+            vec!["Added", "Changed", "Kept"]
+        );
+        // only the unchanged script keeps its executor
+        assert!(scripts.scripts["Kept"].is_running());
+        assert!(!scripts.scripts["Changed"].is_running());
+        assert!(scripts.scripts["Changed"].metadata.persistent);
+        assert_eq!(scripts.load_errors.len(), 1);
+
+        // the same error isn't reported twice, files that didn't change aren't touched
+        assert!(scripts
+            .rebuild_in(&[dir.path().join("Broken.js")], &roots)
+            .is_empty());
+        assert!(scripts.scripts["Kept"].is_running());
+
+        // a file taking the name of another wins if it comes later, the other takes over again
+        // once it's gone
+        write("Later.js", "Kept", false);
+        assert_eq!(
+            scripts.rebuild_in(&[dir.path().join("Later.js")], &roots),
+            vec![ScriptChange::Reloaded("Kept".to_string())]
+        );
+        assert_eq!(scripts.scripts["Kept"].path, dir.path().join("Later.js"));
+        fs::remove_file(dir.path().join("Later.js")).unwrap();
+        assert_eq!(
+            scripts.rebuild_in(&[dir.path().join("Later.js")], &roots),
+            vec![ScriptChange::Reloaded("Kept".to_string())]
+        );
+        assert_eq!(scripts.scripts["Kept"].path, dir.path().join("Kept.js"));
+
+        // removing a folder removes the scripts in it
+        fs::create_dir(dir.path().join("Folder")).unwrap();
+        write("Folder/Nested.js", "Nested", false);
+        assert_eq!(
+            scripts.rebuild_in(&[dir.path().join("Folder")], &roots),
+            vec![ScriptChange::Added("Nested".to_string())]
+        );
+        fs::remove_dir_all(dir.path().join("Folder")).unwrap();
+        assert_eq!(
+            scripts.rebuild_in(&[dir.path().join("Folder")], &roots),
+            vec![ScriptChange::Removed("Nested".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_load_nested() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, name: &str| {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, script(name, false).source()).unwrap();
+        };
+        write("Top.js", "Top");
+        write("Text/Case/Upper.js", "Upper");
+        write("lib/helper.js", "Library");
+        write(".hidden/Hidden.js", "Hidden");
+        fs::write(dir.path().join("Text/broken.js"), "function main() {}").unwrap();
+
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        scripts.load_path(dir.path()).unwrap();
+
+        assert_eq!(
+            scripts.scripts.keys().collect::<Vec<_>>(),
+            vec!["Top", "Upper"]
+        );
+        assert_eq!(
+            scripts.scripts["Upper"].path,
+            dir.path().join("Text/Case/Upper.js")
+        );
+        assert_eq!(
+            scripts.load_errors.keys().collect::<Vec<_>>(),
+            vec![&dir.path().join("Text/broken.js")]
+        );
+    }
+
+    #[test]
+    fn test_restart_dependents() {
+        let dir = tempfile::tempdir().unwrap();
+        let helper = dir.path().join("helper.js");
+        fs::write(&helper, "module.exports = { value: 1 };").unwrap();
+        fs::write(
+            dir.path().join("Uses.js"),
+            "/**
+                {
+                    \"api\": 2,
+                    \"name\": \"Uses Helper\",
+                    \"description\": \"Test script\",
+                    \"icon\": \"html\"
+                }
+            **/
+
+            const helper = require(\"./helper\");
+
+            function main(state) {
+                state.text = String(helper.value);
+            }",
+        )
+        .unwrap();
+
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        scripts.load_path(dir.path()).unwrap();
+
+        // the require is found without running the script
+        assert_eq!(
+            scripts.scripts["Uses Helper"].dependencies(),
+            &[helper.canonicalize().unwrap()]
+        );
+
+        // the library has no metadata, but isn't reported since a script requires it
+        assert!(scripts.load_errors.contains_key(&helper));
+        assert!(scripts
+            .rebuild_in(&[helper.clone()], &[dir.path().to_path_buf()])
+            .is_empty());
+        assert!(scripts.load_errors.is_empty());
+
+        // scripts that aren't running don't need a restart
+        assert!(scripts.restart_dependents(&[helper.clone()]).is_empty());
+
+        let status = scripts
+            .scripts
+            .get_mut("Uses Helper")
+            .unwrap()
+            .execute("", None)
+            .unwrap();
+        assert_eq!(status.into_replacement(), TextReplacement::Full("1".into()));
+        assert_eq!(
+            scripts.scripts["Uses Helper"].dependencies(),
+            &[helper.canonicalize().unwrap()]
+        );
+
+        scripts
+            .scripts
+            .insert("Other".to_string(), script("Other", false));
+        scripts
+            .scripts
+            .get_mut("Other")
+            .unwrap()
+            .execute("", None)
+            .unwrap();
+
+        fs::write(&helper, "module.exports = { value: 2 };").unwrap();
+        assert_eq!(
+            scripts.restart_dependents(&[helper]),
+            vec![ScriptChange::Restarted("Uses Helper".to_string())]
+        );
+
+        assert!(!scripts.scripts["Uses Helper"].is_running());
+        assert!(scripts.scripts["Other"].is_running());
+
+        let status = scripts
+            .scripts
+            .get_mut("Uses Helper")
+            .unwrap()
+            .execute("", None)
+            .unwrap();
+        assert_eq!(status.into_replacement(), TextReplacement::Full("2".into()));
+    }
+
+    #[test]
+    fn test_override_builtin() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        scripts.load_internal();
+
+        assert!(scripts.builtin_files.contains_key("Format JSON"));
+        assert!(!scripts.is_overridden("Format JSON"));
+
+        let path = scripts
+            .override_builtin_in("Format JSON", dir.path())
+            .unwrap();
+        assert_eq!(path, dir.path().join("FormatJSON.js"));
+        assert!(path.is_file());
+        assert_eq!(scripts.scripts["Format JSON"].path, path);
+        assert!(scripts.is_overridden("Format JSON"));
+
+        // the copy can't be overridden again
+        assert!(scripts
+            .override_builtin_in("Format JSON", dir.path())
+            .is_err());
+
+        scripts
+            .revert_to_builtin_in("Format JSON", dir.path())
+            .unwrap();
+        assert!(!path.exists());
+        assert_eq!(
+            scripts.scripts["Format JSON"].origin(),
+            ScriptOrigin::Builtin
+        );
+
+        // built-ins can't be reverted
+        assert!(scripts
+            .revert_to_builtin_in("Format JSON", dir.path())
+            .is_err());
+    }
+
+    #[test]
+    fn test_duplicate_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        scripts
+            .scripts
+            .insert("Test".to_string(), script("Test", false));
+
+        assert_eq!(
+            scripts.duplicate_in("Test", dir.path()).unwrap(),
+            "Test Copy"
+        );
+        assert_eq!(
+            scripts.duplicate_in("Test", dir.path()).unwrap(),
+            "Test Copy 2"
+        );
+        assert!(dir.path().join("TestCopy.js").is_file());
+        assert_eq!(scripts.scripts["Test Copy 2"].metadata.name, "Test Copy 2");
+        assert_eq!(
+            scripts.scripts["Test Copy 2"].path,
+            dir.path().join("TestCopy2.js")
+        );
+
+        scripts.delete_in("Test Copy", dir.path()).unwrap();
+        assert!(!scripts.scripts.contains_key("Test Copy"));
+        assert!(!dir.path().join("TestCopy.js").exists());
+
+        // scripts outside the user scripts directory can't be deleted
+        assert!(scripts.delete_in("Test", dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_rename_source() {
+        assert_eq!(
+            rename_source(
+                r#"/** { "api": 1, "name": "Test", "description": "Test script" } **/ "Test""#,
+                "Test",
+                "Test \"Copy\""
+            )
+            .unwrap(),
+            r#"/** { "api": 1, "name": "Test \"Copy\"", "description": "Test script" } **/ "Test""#
+        );
+        assert!(rename_source("/** {} **/", "Test", "Test Copy").is_none());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(ScriptMap::file_name("Upper Case"), "UpperCase.js");
+        assert_eq!(
+            ScriptMap::file_name("base64 encode (url)"),
+            "Base64EncodeUrl.js"
+        );
+        assert_eq!(ScriptMap::file_name("  "), "Script.js");
+    }
+
+    #[test]
+    fn test_search() {
+        let mut scripts = ScriptMap::from(BTreeMap::new());
+        for name in &["Format JSON", "Minify JSON", "Base64 Encode"] {
+            scripts
+                .scripts
+                .insert(name.to_string(), script(name, false));
+        }
+
+        let results = scripts.search("json");
+        assert!(results.contains_key("Format JSON"));
+        assert!(results.contains_key("Minify JSON"));
+        assert!(!results.contains_key("Base64 Encode"));
     }
 }
@@ -0,0 +1,222 @@
+use crate::{config::Config, dbus::Request, scriptmap::ScriptMap};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use zbus::{dbus_interface, fdo};
+use zvariant::Value;
+
+pub const OBJECT_PATH: &str = "/fyi/zoey/BoopGTK/SearchProvider";
+
+// shown next to every result, the script icons are only available inside the app
+const RESULT_ICON: &str = "fyi.zoey.Boop-GTK";
+
+// org.gnome.Shell.SearchProvider2, lists scripts in the desktop overview, activating one runs it
+// on the clipboard, see data/fyi.zoey.Boop-GTK.search-provider.ini
+pub struct SearchProvider {
+    scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    requests: glib::Sender<Request>,
+}
+
+impl SearchProvider {
+    pub fn new(
+        scripts: Arc<RwLock<ScriptMap>>,
+        config: Arc<RwLock<Config>>,
+        requests: glib::Sender<Request>,
+    ) -> Self {
+        SearchProvider {
+            scripts,
+            config,
+            requests,
+        }
+    }
+
+    // names of the matching scripts, best match first, "boop" only narrows the search to scripts,
+    // disabled scripts aren't shown
+    fn search(&self, terms: &[String]) -> Vec<String> {
+        let query = terms
+            .iter()
+            .filter(|term| !term.eq_ignore_ascii_case("boop"))
+            .map(String::as_str)
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+        let config = self.config.read().expect("Config lock poisoned");
+
+        if query.is_empty() {
+            // nothing but "boop" was typed
+            return if terms.is_empty() {
+                Vec::new()
+            } else {
+                scripts
+                    .scripts
+                    .keys()
+                    .filter(|name| !config.is_disabled(name))
+                    .cloned()
+                    .collect()
+            };
+        }
+
+        let mut results: Vec<(String, f64)> = scripts
+            .search(&query)
+            .into_iter()
+            .filter(|(name, _)| !config.is_disabled(name))
+            .collect();
+        results.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        results.into_iter().map(|(name, _)| name).collect()
+    }
+
+    fn send(&self, request: Request) -> fdo::Result<()> {
+        self.requests
+            .send(request)
+            .map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+}
+
+#[dbus_interface(name = "org.gnome.Shell.SearchProvider2")]
+impl SearchProvider {
+    fn get_initial_result_set(&self, terms: Vec<String>) -> Vec<String> {
+        self.search(&terms)
+    }
+
+    fn get_subsearch_result_set(
+        &self,
+        _previous_results: Vec<String>,
+        terms: Vec<String>,
+    ) -> Vec<String> {
+        self.search(&terms)
+    }
+
+    fn get_result_metas(&self, identifiers: Vec<String>) -> Vec<HashMap<String, Value<'static>>> {
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+
+        identifiers
+            .into_iter()
+            .filter_map(|identifier| {
+                let script = scripts.scripts.get(&identifier)?;
+
+                let mut meta = HashMap::new();
+                meta.insert("id".to_string(), Value::from(identifier.clone()));
+                meta.insert(
+                    "name".to_string(),
+                    Value::from(script.metadata.name.clone()),
+                );
+                meta.insert(
+                    "description".to_string(),
+                    Value::from(script.metadata.description.clone()),
+                );
+                meta.insert("gicon".to_string(), Value::from(RESULT_ICON));
+                Some(meta)
+            })
+            .collect()
+    }
+
+    fn activate_result(
+        &self,
+        identifier: String,
+        _terms: Vec<String>,
+        _timestamp: u32,
+    ) -> fdo::Result<()> {
+        info!("activating {} from search", identifier);
+        self.send(Request::RunScript(identifier))
+    }
+
+    fn launch_search(&self, _terms: Vec<String>, _timestamp: u32) -> fdo::Result<()> {
+        self.send(Request::ShowCommandPalette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    fn search_provider(names: &[&str], disabled: &[&str]) -> SearchProvider {
+        let mut scripts = BTreeMap::new();
+        for name in names {
+            let script = Script::from_source(
+                format!(
+                    "/**
+                        {{
+                            \"api\": 2,
+                            \"name\": \"{}\",
+                            \"description\": \"Test script\",
+                            \"icon\": \"html\"
+                        }}
+                    **/
+
+                    function main(state) {{}}",
+                    name
+                ),
+                PathBuf::new(),
+            )
+            .unwrap();
+            scripts.insert(name.to_string(), script);
+        }
+
+        let mut config = Config::default();
+        for name in disabled {
+            config.set_disabled(name, true);
+        }
+
+        let (sender, _) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        SearchProvider::new(
+            Arc::new(RwLock::new(ScriptMap::from(scripts))),
+            Arc::new(RwLock::new(config)),
+            sender,
+        )
+    }
+
+    fn terms(terms: &[&str]) -> Vec<String> {
+        terms.iter().map(|term| term.to_string()).collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let search_provider = search_provider(&["Format JSON", "Base64 Encode"], &[]);
+
+        assert_eq!(
+            search_provider.get_initial_result_set(terms(&["boop", "json"])),
+            vec!["Format JSON".to_string()]
+        );
+        assert_eq!(
+            search_provider.get_initial_result_set(terms(&["Boop"])),
+            vec!["Base64 Encode".to_string(), "Format JSON".to_string()]
+        );
+        assert!(search_provider
+            .get_initial_result_set(terms(&[]))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_search_disabled() {
+        let search_provider = search_provider(
+            &["Format JSON", "Minify JSON", "Base64 Encode"],
+            &["Minify JSON"],
+        );
+
+        assert_eq!(
+            search_provider.get_initial_result_set(terms(&["json"])),
+            vec!["Format JSON".to_string()]
+        );
+        assert_eq!(
+            search_provider.get_initial_result_set(terms(&["boop"])),
+            vec!["Base64 Encode".to_string(), "Format JSON".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_result_metas() {
+        let search_provider = search_provider(&["Format JSON"], &[]);
+
+        let metas = search_provider.get_result_metas(terms(&["Format JSON", "Missing"]));
+        assert_eq!(metas.len(), 1);
+        assert_eq!(
+            metas[0].get("name"),
+            Some(&Value::from("Format JSON".to_string()))
+        );
+    }
+}
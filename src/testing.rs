@@ -0,0 +1,405 @@
+use crate::{
+    executor::{ExecutionRequest, ExecutorError, TextRange},
+    permission::Permission,
+    script::Script,
+    scriptmap::ScriptMap,
+};
+use eyre::{Context, Result};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// fixtures are stored next to the script they test, MyScript.js is tested by MyScript.test.toml
+pub const FIXTURE_EXTENSION: &str = "test.toml";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Fixture {
+    // permissions granted to the script while it's tested, nothing is granted by default
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    #[serde(rename = "test", default)]
+    pub tests: Vec<TestCase>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TestCase {
+    pub name: Option<String>,
+    pub input: String,
+    // start and end of the selection in UTF-16 code units, like selectionStart and selectionEnd
+    pub selection: Option<(usize, usize)>,
+    // full text after the script ran
+    pub output: Option<String>,
+    // message posted with postInfo
+    pub info: Option<String>,
+    // text the error posted with postError, or thrown, must contain
+    pub error: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TestOutcome {
+    pub name: String,
+    pub failures: Vec<String>,
+}
+
+impl TestOutcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Fixture {
+    pub fn path_for(script_path: &Path) -> PathBuf {
+        script_path.with_extension(FIXTURE_EXTENSION)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        Fixture::parse(&toml).wrap_err_with(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn run(&self, script: &mut Script) -> Vec<TestOutcome> {
+        script.set_granted_permissions(&self.permissions);
+
+        self.tests
+            .iter()
+            .enumerate()
+            .map(|(index, test)| TestOutcome {
+                name: test
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("test {}", index + 1)),
+                failures: test.run(script),
+            })
+            .collect()
+    }
+}
+
+impl TestCase {
+    // returns a description of each expectation that wasn't met
+    pub fn run(&self, script: &mut Script) -> Vec<String> {
+        let request = ExecutionRequest::new(
+            &self.input,
+            self.selection
+                .map(|(start, end)| TextRange::new(start, end)),
+        );
+
+        let (output, info, error) = match script.execute_request(request.clone()) {
+            Ok(status) => {
+                let info = status.info().cloned();
+                let error = status.error().cloned();
+                let output = String::from_utf8_lossy(&status.into_replacement().apply(&request))
+                    .into_owned();
+                (output, info, error)
+            }
+            Err(err) => {
+                let error = match err.downcast::<ExecutorError>() {
+                    Ok(ExecutorError::Compile(exception))
+                    | Ok(ExecutorError::Execute(exception)) => exception.exception_str,
+                    Ok(err) => err.to_string(),
+                    Err(err) => err.to_string(),
+                };
+                (self.input.clone(), None, Some(error))
+            }
+        };
+
+        let mut failures = Vec::new();
+
+        if let Some(expected) = &self.output {
+            if &output != expected {
+                failures.push(format!("expected output {:?}, got {:?}", expected, output));
+            }
+        }
+
+        if let Some(expected) = &self.info {
+            if info.as_ref() != Some(expected) {
+                failures.push(format!("expected info {:?}, got {:?}", expected, info));
+            }
+        }
+
+        match (&self.error, &error) {
+            (Some(expected), Some(error)) if !error.contains(expected.as_str()) => failures.push(
+                format!("expected error containing {:?}, got {:?}", expected, error),
+            ),
+            (Some(expected), None) => failures.push(format!(
+                "expected error containing {:?}, got none",
+                expected
+            )),
+            (None, Some(error)) => failures.push(format!("unexpected error {:?}", error)),
+            _ => {}
+        }
+
+        failures
+    }
+}
+
+// scripts in paths that have a fixture, directories are searched for scripts
+fn scripts_with_fixtures(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut scripts = Vec::new();
+
+    for path in paths {
+        if path.is_dir() {
+            let mut found: Vec<PathBuf> = fs::read_dir(path)
+                .map(|dir| dir.filter_map(Result::ok).map(|f| f.path()).collect())
+                .unwrap_or_default();
+            found.retain(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "js")
+                    && Fixture::path_for(path).is_file()
+            });
+            found.sort();
+            scripts.extend(found);
+        } else {
+            scripts.push(path.clone());
+        }
+    }
+
+    scripts
+}
+
+// `boop-gtk test [SCRIPT|DIRECTORY...]`, runs the fixtures of scripts and returns the exit code,
+// the user scripts directory is tested if no paths are given
+pub fn run_cli(paths: &[PathBuf]) -> i32 {
+    let paths = if paths.is_empty() {
+        vec![ScriptMap::user_scripts_dir()]
+    } else {
+        paths.to_vec()
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+
+    for script_path in scripts_with_fixtures(&paths) {
+        let fixture_path = Fixture::path_for(&script_path);
+
+        let result = Script::from_file(script_path.clone())
+            .map_err(|err| eyre!("Failed to load {}: {}", script_path.display(), err))
+            .and_then(|script| Ok((script, Fixture::load(&fixture_path)?)));
+
+        let (mut script, fixture) = match result {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                println!("{:?}\n", err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        println!("{} ({})", script.metadata.name, fixture_path.display());
+
+        for outcome in fixture.run(&mut script) {
+            if outcome.passed() {
+                println!("  ok      {}", outcome.name);
+                passed += 1;
+            } else {
+                println!("  FAILED  {}", outcome.name);
+                for failure in outcome.failures {
+                    println!("          {}", failure);
+                }
+                failed += 1;
+            }
+        }
+
+        script.kill_thread();
+        println!();
+    }
+
+    if passed + failed == 0 {
+        println!("no scripts with fixtures found");
+    } else {
+        println!("{} passed, {} failed", passed, failed);
+    }
+
+    if failed > 0 || passed == 0 {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script() -> Script {
+        Script::from_source(
+            r#"/**
+                {
+                    "api": 2,
+                    "name": "Upper Case",
+                    "description": "Converts text to upper case",
+                    "icon": "type"
+                }
+            **/
+
+            function main(state) {
+                if (state.text === "") {
+                    state.postError("Nothing to convert");
+                    return;
+                }
+                if (state.text === "throw") {
+                    throw new Error("thrown");
+                }
+                state.text = state.text.toUpperCase();
+                state.postInfo("Converted " + state.text.length + " characters");
+            }"#
+            .to_string(),
+            PathBuf::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fixture() {
+        let fixture = Fixture::parse(
+            r#"
+            [[test]]
+            name = "converts text"
+            input = "foo"
+            output = "FOO"
+            info = "Converted 3 characters"
+
+            [[test]]
+            input = "foo bar"
+            selection = [4, 7]
+            output = "foo BAR"
+
+            [[test]]
+            name = "empty"
+            input = ""
+            error = "Nothing"
+
+            [[test]]
+            name = "throws"
+            input = "throw"
+            error = "thrown"
+            "#,
+        )
+        .unwrap();
+
+        let outcomes = fixture.run(&mut script());
+        assert_eq!(
+            outcomes.iter().map(|o| o.name.as_str()).collect::<Vec<_>>(),
+            vec!["converts text", "test 2", "empty", "throws"]
+        );
+        assert!(outcomes.iter().all(TestOutcome::passed), "{:?}", outcomes);
+    }
+
+    #[test]
+    fn test_fixture_failures() {
+        let fixture = Fixture::parse(
+            r#"
+            [[test]]
+            input = "foo"
+            output = "foo"
+            error = "Nothing"
+
+            [[test]]
+            input = ""
+            "#,
+        )
+        .unwrap();
+
+        let outcomes = fixture.run(&mut script());
+        assert_eq!(
+            outcomes[0].failures,
+            vec![
+                r#"expected output "foo", got "FOO""#.to_string(),
+                r#"expected error containing "Nothing", got none"#.to_string(),
+            ]
+        );
+        assert_eq!(
+            outcomes[1].failures,
+            vec![r#"unexpected error "Nothing to convert""#.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_fixture_permissions() {
+        let mut script = Script::from_source(
+            r#"/**
+                {
+                    "api": 2,
+                    "name": "Read File",
+                    "description": "Reads a file",
+                    "icon": "type",
+                    "permissions": ["files:read"]
+                }
+            **/
+
+            function main(state) {
+                state.text = typeof readFile;
+            }"#
+            .to_string(),
+            PathBuf::new(),
+        )
+        .unwrap();
+
+        let fixture = Fixture::parse(
+            r#"
+            [[test]]
+            input = ""
+            output = "undefined"
+            "#,
+        )
+        .unwrap();
+        assert!(fixture.run(&mut script).iter().all(TestOutcome::passed));
+
+        let fixture = Fixture::parse(
+            r#"
+            permissions = ["files:read"]
+
+            [[test]]
+            input = ""
+            output = "function"
+            "#,
+        )
+        .unwrap();
+        assert!(fixture.run(&mut script).iter().all(TestOutcome::passed));
+    }
+
+    #[test]
+    fn test_fixture_path() {
+        assert_eq!(
+            Fixture::path_for(Path::new("scripts/MyScript.js")),
+            PathBuf::from("scripts/MyScript.test.toml")
+        );
+    }
+
+    #[test]
+    fn test_builtin_fixtures() {
+        use rust_embed::RustEmbed;
+
+        #[derive(RustEmbed)]
+        #[folder = "submodules/Boop/Boop/Boop/scripts/"]
+        struct Scripts;
+
+        #[derive(RustEmbed)]
+        #[folder = "fixtures/"]
+        struct Fixtures;
+
+        for file in Fixtures::iter() {
+            println!("testing {}", file);
+
+            let script_file = file.replace(&format!(".{}", FIXTURE_EXTENSION), ".js");
+            let source = Scripts::get(&script_file)
+                .unwrap_or_else(|| panic!("no builtin script named {}", script_file));
+            let mut script =
+                Script::from_source(String::from_utf8(source.to_vec()).unwrap(), PathBuf::new())
+                    .unwrap();
+
+            let fixture =
+                Fixture::parse(std::str::from_utf8(&Fixtures::get(&file).unwrap()).unwrap())
+                    .unwrap();
+
+            for outcome in fixture.run(&mut script) {
+                assert!(outcome.passed(), "{}: {:?}", file, outcome);
+            }
+        }
+    }
+}
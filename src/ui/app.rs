@@ -1,12 +1,18 @@
 use crate::{
+    cli::Selection,
     config::Config,
     executor::{self},
+    permission::Permission,
     script::Script,
-    scriptmap::ScriptMap,
-    ui::command_palette::CommandPaletteDialog,
-    ui::{preferences_dialog::PreferencesDialog, shortcuts_window::ShortcutsWindow},
+    scriptmap::{ScriptChange, ScriptMap},
+    ui::command_palette::{CommandPaletteDialog, ScriptAction, ACTION_RESPONSE},
+    ui::{
+        catalog_dialog::CatalogDialog, preferences_dialog::PreferencesDialog,
+        script_editor::ScriptEditor, script_manager::ScriptManagerDialog,
+        shortcuts_window::ShortcutsWindow,
+    },
     util::SourceViewExt,
-    util::StringExt,
+    util::{BytesExt, Utf16Ext},
     XDG_DIRS,
 };
 use eyre::{Context, Result};
@@ -16,9 +22,14 @@ use glib::SourceId;
 use gtk::{prelude::*, Label, Revealer};
 use sourceview::{prelude::*, Language};
 
-use executor::{ExecutorError, TextReplacement};
+use executor::{ExecutionRequest, ExecutionStatus, ExecutorError, TextRange, TextReplacement};
 use gtk::{ApplicationWindow, Button, ModelButton};
-use std::sync::{Arc, RwLock};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, RwLock},
+};
 
 use super::about_dialog::AboutDialog;
 
@@ -41,6 +52,8 @@ pub struct AppWidgets {
     preferences_button: ModelButton,
     config_directory_button: ModelButton,
     more_scripts_button: ModelButton,
+    script_editor_button: ModelButton,
+    manage_scripts_button: ModelButton,
     shortcuts_button: ModelButton,
     about_button: ModelButton,
 }
@@ -56,6 +69,10 @@ pub struct App {
     notification_source_id: Arc<RwLock<Option<SourceId>>>,
     last_script_executed: Arc<RwLock<Option<String>>>,
     config: Arc<RwLock<Config>>,
+    // contents of the buffer while it shows a hex dump of binary data
+    binary: Arc<RwLock<Option<Vec<u8>>>>,
+    // the command palette while it is open, refreshed when scripts change
+    command_palette: Rc<RefCell<Option<CommandPaletteDialog>>>,
 }
 
 impl App {
@@ -73,6 +90,8 @@ impl App {
             notification_source_id: Arc::new(RwLock::new(None)),
             last_script_executed: Arc::new(RwLock::new(None)),
             config,
+            binary: Arc::new(RwLock::new(None)),
+            command_palette: Rc::new(RefCell::new(None)),
         };
 
         app.configure(boop_language)?;
@@ -102,7 +121,7 @@ impl App {
                 for (_, script) in scripts
                     .write()
                     .expect("Scripts lock is poisoned")
-                    .0
+                    .scripts
                     .iter_mut()
                 {
                     script.kill_thread();
@@ -149,14 +168,32 @@ impl App {
             });
         }
 
-        // launch more scripts page in default web browser
         {
             let app_ = app.clone();
             app.more_scripts_button.connect_clicked(move |_| {
-                if let Err(open_err) = open::that("https://boop.okat.best/scripts/") {
-                    error!("could not launch website: {}", open_err);
+                if let Err(err) = app_.run_catalog() {
+                    error!("could not open script catalog: {:?}", err);
                     app_.post_notification_error(
-                        "Failed to launch website",
+                        "Failed to open script catalog",
+                        NOTIFICATION_LONG_DELAY,
+                    );
+                }
+            });
+        }
+
+        {
+            let app_ = app.clone();
+            app.script_editor_button
+                .connect_clicked(move |_| app_.open_script_editor(None));
+        }
+
+        {
+            let app_ = app.clone();
+            app.manage_scripts_button.connect_clicked(move |_| {
+                if let Err(err) = app_.run_script_manager() {
+                    error!("could not open script manager: {:?}", err);
+                    app_.post_notification_error(
+                        "Failed to open script manager",
                         NOTIFICATION_LONG_DELAY,
                     );
                 }
@@ -265,19 +302,173 @@ impl App {
         );
     }
 
+    // shows what the script watcher picked up and updates an open command palette
+    pub(crate) fn scripts_changed(&self, changes: &[ScriptChange]) {
+        let command_palette = self.command_palette.borrow().clone();
+        if let Some(command_palette) = command_palette {
+            if let Err(err) = command_palette.refresh() {
+                error!("failed to refresh command palette: {:?}", err);
+            }
+        }
+
+        // errors come first, the changes that went through are summarized below them
+        let summary = ScriptChange::summary(changes)
+            .map(|summary| glib::markup_escape_text(&summary).to_string());
+        let errors: Vec<String> = changes
+            .iter()
+            .filter(|change| change.is_error())
+            .map(|change| glib::markup_escape_text(&change.to_string()).to_string())
+            .collect();
+
+        if !errors.is_empty() {
+            let mut text = errors.join("\n");
+            if let Some(summary) = summary {
+                text.push_str(&format!("\n{}", summary));
+            }
+            self.post_notification_error(&text, NOTIFICATION_LONG_DELAY);
+        } else if let Some(summary) = summary {
+            self.post_notification(&summary, NOTIFICATION_LONG_DELAY);
+        }
+    }
+
     pub fn run_command_palette(&self) -> Result<()> {
-        let dialog = CommandPaletteDialog::new(&self.window, self.scripts.clone())?;
+        if let Some(selected) = self.choose_script()? {
+            *self.last_script_executed.write().unwrap() = Some(selected.clone());
+            self.execute_script(&selected)?;
+        }
+
+        Ok(())
+    }
+
+    // shows the command palette, returns the script the user picked
+    fn choose_script(&self) -> Result<Option<String>> {
+        let dialog =
+            CommandPaletteDialog::new(&self.window, self.scripts.clone(), self.config.clone())?;
         dialog.show_all();
 
-        if let gtk::ResponseType::Accept = dialog.run() {
+        self.command_palette.replace(Some(dialog.clone()));
+        let responce = dialog.run();
+        self.command_palette.replace(None);
+        let selected = if let gtk::ResponseType::Accept = responce {
             let selected: &str = dialog
                 .get_selected()
                 .ok_or_else(|| eyre!("Command palette dialog didn't return a selection"))?;
+            Some(String::from(selected))
+        } else {
+            None
+        };
+        let action = if responce == ACTION_RESPONSE {
+            dialog.get_action().cloned()
+        } else {
+            None
+        };
+
+        dialog.close();
 
-            *self.last_script_executed.write().unwrap() = Some(String::from(selected));
-            self.execute_script(selected)?;
+        if let Some(action) = action {
+            self.run_script_action(action);
         }
 
+        Ok(selected)
+    }
+
+    // runs an action from the context menu of the command palette
+    fn run_script_action(&self, action: ScriptAction) {
+        info!("running script action {:?}", action);
+
+        let result = match &action {
+            ScriptAction::Edit(name) => self
+                .scripts
+                .read()
+                .expect("Scripts lock is poisoned")
+                .scripts
+                .get(name)
+                .map(|script| script.path.clone())
+                .ok_or_else(|| eyre!("No script named \"{}\"", name))
+                .map(|path| self.open_script_editor(Some(&path))),
+            ScriptAction::Override(name) => self
+                .scripts
+                .write()
+                .expect("Scripts lock is poisoned")
+                .override_builtin(name)
+                .map(|path| self.open_script_editor(Some(&path))),
+            ScriptAction::Revert(name) => self
+                .scripts
+                .write()
+                .expect("Scripts lock is poisoned")
+                .revert_to_builtin(name)
+                .map(|_| {
+                    self.post_notification(
+                        &format!(
+                            "Reverted \"{}\" to the built-in script",
+                            glib::markup_escape_text(name)
+                        ),
+                        NOTIFICATION_LONG_DELAY,
+                    )
+                }),
+        };
+
+        if let Err(err) = result {
+            error!("script action failed: {:?}", err);
+            self.post_notification_error(
+                &glib::markup_escape_text(&err.to_string()),
+                NOTIFICATION_LONG_DELAY,
+            );
+        }
+    }
+
+    // opens a script editor with a script file, or the template if path is none, scripts can be
+    // run on the buffer before they are saved
+    fn open_script_editor(&self, path: Option<&Path>) {
+        let script_editor =
+            ScriptEditor::new(&self.window, self.config.clone()).and_then(|script_editor| {
+                if let Some(path) = path {
+                    script_editor.open(path)?;
+                }
+                Ok(script_editor)
+            });
+
+        match script_editor {
+            Ok(script_editor) => {
+                let app = self.clone();
+                script_editor.connect_run(move |source| {
+                    if let Err(err) = app.execute_source(source) {
+                        error!("could not execute script: {:?}", err);
+                        app.post_notification_error(
+                            &glib::markup_escape_text(&format!(
+                                "Failed to execute script: {}",
+                                err
+                            )),
+                            NOTIFICATION_LONG_DELAY,
+                        );
+                    }
+                });
+                script_editor.show_all();
+            }
+            Err(err) => {
+                error!("could not open script editor: {:?}", err);
+                self.post_notification_error(
+                    "Failed to open script editor",
+                    NOTIFICATION_LONG_DELAY,
+                );
+            }
+        }
+    }
+
+    // lets the user disable scripts, changes are saved as they are made
+    pub fn run_script_manager(&self) -> Result<()> {
+        let dialog =
+            ScriptManagerDialog::new(&self.window, self.scripts.clone(), self.config.clone())?;
+        dialog.show_all();
+        dialog.run();
+        dialog.close();
+
+        Ok(())
+    }
+
+    pub fn run_catalog(&self) -> Result<()> {
+        let dialog = CatalogDialog::new(&self.window, self.scripts.clone(), self.config.clone())?;
+        dialog.run();
         dialog.close();
 
         Ok(())
@@ -286,6 +477,7 @@ impl App {
     pub fn re_execute(&self) -> Result<()> {
         if let Some(script_key) = &*self.last_script_executed.read().unwrap() {
             self.execute_script(&script_key)
+                .map(|_| ())
                 .wrap_err("Failed to execute script")
         } else {
             warn!("no last script");
@@ -293,33 +485,286 @@ impl App {
         }
     }
 
-    fn execute_script(&self, script_key: &str) -> Result<()> {
-        let mut script_map = self.scripts.write().expect("Scripts lock is poisoned");
-        let script: &mut Script = script_map
-            .0
-            .get_mut(script_key)
-            .ok_or_else(|| eyre!("Script not in map"))?;
+    // asks the user to allow a script to use permissions, returns true if they were granted
+    fn request_permissions(&self, script_name: &str, permissions: &[Permission]) -> bool {
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Warning,
+            gtk::ButtonsType::None,
+            &format!("Allow \"{}\" to access your system?", script_name),
+        );
 
-        info!("executing {}", script.metadata.name);
+        let descriptions = permissions
+            .iter()
+            .map(|permission| format!("• {}", permission.description()))
+            .collect::<Vec<String>>()
+            .join("\n");
+        dialog.set_property_secondary_text(Some(&format!(
+            "This script wants to:\n{}\n\nOnly allow scripts you trust.",
+            descriptions
+        )));
+
+        dialog.add_button("Deny", gtk::ResponseType::Reject);
+        dialog.add_button("Allow", gtk::ResponseType::Accept);
+        dialog.set_default_response(gtk::ResponseType::Reject);
+
+        let responce = dialog.run();
+        dialog.close();
+
+        responce == gtk::ResponseType::Accept
+    }
+
+    // permissions granted to a script, the user is asked about any that haven't been granted yet,
+    // returned with the path and checksum of the script they were granted for
+    fn granted_permissions(
+        &self,
+        script_key: &str,
+    ) -> Result<Option<(Vec<Permission>, PathBuf, String)>> {
+        let (missing, path, checksum) = {
+            let script_map = self.scripts.read().expect("Scripts lock is poisoned");
+            let script: &Script = script_map
+                .scripts
+                .get(script_key)
+                .ok_or_else(|| eyre!("Script not in map"))?;
+            let checksum = script.checksum();
+
+            let config = self.config.read().expect("Config lock poisoned");
+            let missing =
+                script.missing_permissions(&config.granted_permissions(&script.path, &checksum));
+            (missing, script.path.clone(), checksum)
+        };
+
+        // scripts lock is released while the dialog is open
+        if !missing.is_empty() {
+            if !self.request_permissions(script_key, &missing) {
+                info!("permissions for {} denied", script_key);
+                return Ok(None);
+            }
+
+            let mut config = self.config.write().expect("Config lock poisoned");
+            config.grant_permissions(script_key, &path, &checksum, &missing);
+            config.save().wrap_err("Failed to save config")?;
+        }
+
+        let granted = self
+            .config
+            .read()
+            .expect("Config lock poisoned")
+            .granted_permissions(&path, &checksum);
+        Ok(Some((granted, path, checksum)))
+    }
+
+    // clipboard transform mode: runs a script on the clipboard (or primary selection) and copies
+    // the result back, the command palette is shown if no script is given, returns true if the
+    // result was copied
+    pub fn transform_selection(
+        &self,
+        selection: Selection,
+        script_key: Option<&str>,
+    ) -> Result<bool> {
+        if !self.paste_selection(selection)? {
+            return Ok(false);
+        }
+
+        let script_key = match script_key {
+            Some(script_key) => script_key.to_string(),
+            None => match self.choose_script()? {
+                Some(script_key) => script_key,
+                None => return Ok(false),
+            },
+        };
+
+        if !self
+            .scripts
+            .read()
+            .expect("Scripts lock is poisoned")
+            .scripts
+            .contains_key(&script_key)
+        {
+            self.post_notification_error(
+                &format!(
+                    "No script named \"{}\"",
+                    glib::markup_escape_text(&script_key)
+                ),
+                NOTIFICATION_LONG_DELAY,
+            );
+            return Ok(false);
+        }
+
+        *self.last_script_executed.write().unwrap() = Some(script_key.clone());
+        if !self.execute_script(&script_key)? {
+            return Ok(false);
+        }
+
+        // the buffer holds a hex dump of binary results, that isn't what the user wants pasted
+        if self.binary.read().expect("Binary lock poisoned").is_some() {
+            self.post_notification_error(
+                &format!(
+                    "\"{}\" returned binary data, it can't be copied to the clipboard",
+                    glib::markup_escape_text(&script_key)
+                ),
+                NOTIFICATION_LONG_DELAY,
+            );
+            return Ok(false);
+        }
 
         let buffer = &self
             .source_view
             .get_buffer()
             .ok_or_else(|| eyre!("Failed to get buffer"))?;
-
-        let buffer_text = buffer
+        let result = buffer
             .get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), false)
             .ok_or_else(|| eyre!("Failed to get buffer text"))?;
 
-        let selection_text = buffer
-            .get_selection_bounds()
-            .map(|(start, end)| buffer.get_text(&start, &end, false))
-            .flatten()
-            .map(|s| s.to_string());
+        info!("copying {} bytes to {:?}", result.len(), selection);
+        let clipboard = gtk::Clipboard::get(&selection.atom());
+        clipboard.set_text(&result);
+        // hand the result to the clipboard manager so it is kept after quitting
+        clipboard.store();
+
+        Ok(true)
+    }
 
-        let status_result = script.execute(buffer_text.as_str(), selection_text.as_deref());
+    // runs a script on the clipboard without copying the result back, used by the search provider
+    pub fn run_script_on_clipboard(&self, script_key: &str) -> Result<()> {
+        if self.paste_selection(Selection::Clipboard)? {
+            *self.last_script_executed.write().unwrap() = Some(script_key.to_string());
+            self.execute_script(script_key)?;
+        }
+
+        Ok(())
+    }
 
-        match status_result {
+    // replaces the buffer with the contents of the clipboard (or primary selection), returns false
+    // if it is empty
+    fn paste_selection(&self, selection: Selection) -> Result<bool> {
+        match gtk::Clipboard::get(&selection.atom()).wait_for_text() {
+            Some(text) => {
+                self.set_bytes(text.as_bytes().to_vec())?;
+                Ok(true)
+            }
+            None => {
+                self.post_notification_error("Clipboard is empty", NOTIFICATION_LONG_DELAY);
+                Ok(false)
+            }
+        }
+    }
+
+    // replaces the buffer with text from another application
+    pub fn open_text(&self, text: &str) -> Result<()> {
+        info!("opening {} bytes", text.len());
+        self.set_bytes(text.as_bytes().to_vec())
+    }
+
+    // returns true if the script ran without errors
+    fn execute_script(&self, script_key: &str) -> Result<bool> {
+        let (granted, path, checksum) = match self.granted_permissions(script_key)? {
+            Some(granted) => granted,
+            None => {
+                self.post_notification_error(
+                    &format!(
+                        "\"{}\" was not allowed to run",
+                        glib::markup_escape_text(script_key)
+                    ),
+                    NOTIFICATION_LONG_DELAY,
+                );
+                return Ok(false);
+            }
+        };
+
+        let mut script_map = self.scripts.write().expect("Scripts lock is poisoned");
+        let script: &mut Script = script_map
+            .scripts
+            .get_mut(script_key)
+            .ok_or_else(|| eyre!("Script not in map"))?;
+
+        // the script can be reloaded while the user is asked, consent only covers what they saw
+        if script.path != path || script.checksum() != checksum {
+            info!("{} changed while asking for permissions", script_key);
+            drop(script_map);
+            return self.execute_script(script_key);
+        }
+
+        info!("executing {}", script.metadata.name);
+
+        let status_result = self.execute_with_permissions(script, &granted)?;
+
+        script_map.enforce_pool_size(
+            self.config
+                .read()
+                .expect("Config lock poisoned")
+                .executor
+                .pool_size,
+        );
+        drop(script_map);
+
+        self.show_status(status_result)
+    }
+
+    // runs source from the script editor on the buffer without adding it to the script map,
+    // returns true if the script ran without errors
+    pub fn execute_source(&self, source: &str) -> Result<bool> {
+        let mut script = match Script::from_source(source.to_string(), PathBuf::new()) {
+            Ok(script) => script,
+            Err(err) => {
+                self.post_notification_error(
+                    &glib::markup_escape_text(&err.to_string()),
+                    NOTIFICATION_LONG_DELAY,
+                );
+                return Ok(false);
+            }
+        };
+
+        info!("executing unsaved {}", script.metadata.name);
+
+        // unsaved scripts have no name to remember consent under, so the user is asked every run
+        let permissions = script.metadata.permissions.clone();
+        if !permissions.is_empty() && !self.request_permissions(&script.metadata.name, &permissions)
+        {
+            self.post_notification_error(
+                &format!(
+                    "\"{}\" was not allowed to run",
+                    glib::markup_escape_text(&script.metadata.name)
+                ),
+                NOTIFICATION_LONG_DELAY,
+            );
+            return Ok(false);
+        }
+
+        let status_result = self.execute_with_permissions(&mut script, &permissions)?;
+        script.kill_thread();
+
+        self.show_status(status_result)
+    }
+
+    fn execute_with_permissions(
+        &self,
+        script: &mut Script,
+        granted: &[Permission],
+    ) -> Result<Result<ExecutionStatus>> {
+        script.set_granted_permissions(granted);
+        script.set_heap_limit(
+            self.config
+                .read()
+                .expect("Config lock poisoned")
+                .executor
+                .heap_limit(),
+        );
+
+        let mut request = self.execution_request()?;
+        if granted.contains(&Permission::Clipboard) {
+            request.clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD)
+                .wait_for_text()
+                .map(|text| text.to_string());
+        }
+
+        Ok(script.execute_request(request))
+    }
+
+    // posts the messages of an execution and replaces the text, returns true if there was no error
+    fn show_status(&self, status_result: Result<ExecutionStatus>) -> Result<bool> {
+        let success = match status_result {
             Ok(status) => {
                 // TODO: how to handle multiple messages?
                 if let Some(error) = status.error() {
@@ -333,8 +778,14 @@ impl App {
                 } else if let Some(info) = status.info() {
                     self.post_notification(&info, NOTIFICATION_LONG_DELAY);
                 }
+                if let Some(text) = status.clipboard() {
+                    info!("writing {} bytes to clipboard", text.len());
+                    gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(text);
+                }
                 self.do_replacement(status.clone().into_replacement())
                     .wrap_err_with(|| format!("Failed to make replacement: {:?}", status))?;
+
+                status.error().is_none()
             }
             Err(err) => {
                 let executor_err = err.downcast::<ExecutorError>().unwrap(); // can't recover from other errors
@@ -344,8 +795,76 @@ impl App {
                     &executor_err.into_notification_string(),
                     NOTIFICATION_LONG_DELAY,
                 );
+
+                false
             }
-        }
+        };
+
+        Ok(success)
+    }
+
+    // capture the buffer text, selection and cursor, offsets are converted to UTF-16 for scripts
+    fn execution_request(&self) -> Result<ExecutionRequest> {
+        let buffer = &self
+            .source_view
+            .get_buffer()
+            .ok_or_else(|| eyre!("Failed to get buffer"))?;
+
+        let buffer_text = buffer
+            .get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), false)
+            .ok_or_else(|| eyre!("Failed to get buffer text"))?;
+
+        let utf16_offset = |iter: &gtk::TextIter| {
+            buffer
+                .get_text(&buffer.get_start_iter(), iter, false)
+                .map(|text| text.utf16_len())
+                .unwrap_or(0)
+        };
+
+        let selection = buffer
+            .get_selection_bounds()
+            .map(|(start, end)| TextRange::new(utf16_offset(&start), utf16_offset(&end)));
+
+        let cursor =
+            utf16_offset(&buffer.get_iter_at_offset(buffer.get_property_cursor_position()));
+
+        let bytes = self.binary_contents(&buffer_text);
+
+        Ok(ExecutionRequest {
+            full_text: buffer_text.to_string(),
+            selection,
+            cursor,
+            clipboard: None,
+            bytes,
+        })
+    }
+
+    // iter at a UTF-16 offset, offsets past the end of the buffer are clamped
+    fn get_iter_at_utf16_offset(&self, offset: usize) -> Result<gtk::TextIter> {
+        let buffer = &self
+            .source_view
+            .get_buffer()
+            .ok_or_else(|| eyre!("Failed to get buffer"))?;
+
+        let buffer_text = buffer
+            .get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), false)
+            .ok_or_else(|| eyre!("Failed to get buffer text"))?;
+        let buffer_text = buffer_text.as_str();
+
+        let byte_offset = buffer_text.utf16_to_byte_offset(offset);
+        Ok(buffer.get_iter_at_offset(buffer_text[..byte_offset].chars().count() as i32))
+    }
+
+    fn select_range(&self, range: TextRange) -> Result<()> {
+        let buffer = &self
+            .source_view
+            .get_buffer()
+            .ok_or_else(|| eyre!("Failed to get buffer"))?;
+
+        buffer.select_range(
+            &self.get_iter_at_utf16_offset(range.start)?,
+            &self.get_iter_at_utf16_offset(range.end)?,
+        );
 
         Ok(())
     }
@@ -359,50 +878,51 @@ impl App {
         match replacement {
             TextReplacement::Full(text) => {
                 info!("replacing full text");
-
-                let safe_text = text
-                    .remove_null_bytes()
-                    .wrap_err("Failed to remove null bytes from text")?;
-
-                buffer.set_text(&safe_text);
+                self.set_bytes(text.into_bytes())?;
+            }
+            TextReplacement::FullBytes(bytes) => {
+                info!("replacing full text with {} bytes", bytes.len());
+                self.set_bytes(bytes)?;
             }
             TextReplacement::Selection(text) => {
                 info!("replacing selection");
-
-                let safe_text = text
-                    .remove_null_bytes()
-                    .wrap_err("Failed to remove null bytes from text")?;
-
-                match &mut buffer.get_selection_bounds() {
-                    Some((start, end)) => {
-                        buffer.delete(start, end);
-                        buffer.insert(start, &safe_text);
-                    }
-                    None => {
-                        error!("tried to do a selection replacement, but no text is selected!");
-                    }
-                }
+                self.replace_selection(text.into_bytes())?;
+            }
+            TextReplacement::SelectionBytes(bytes) => {
+                info!("replacing selection with {} bytes", bytes.len());
+                self.replace_selection(bytes)?;
             }
             TextReplacement::Insert(insertions) => {
                 let insert_text = insertions.join("");
                 info!("inserting {} bytes", insert_text.len());
 
-                let safe_text = insert_text
-                    .remove_null_bytes()
-                    .wrap_err("Failed to remove null bytes from text")?;
-
-                match &mut buffer.get_selection_bounds() {
+                match buffer.get_selection_bounds() {
                     Some((start, end)) => {
-                        buffer.delete(start, end);
-                        buffer.insert(start, &safe_text);
+                        self.replace_range(start, end, insert_text.into_bytes())?
                     }
                     None => {
-                        let mut insert_point =
+                        let insert_point =
                             buffer.get_iter_at_offset(buffer.get_property_cursor_position());
-                        buffer.insert(&mut insert_point, &safe_text)
+                        self.replace_range(
+                            insert_point.clone(),
+                            insert_point,
+                            insert_text.into_bytes(),
+                        )?;
                     }
                 }
             }
+            TextReplacement::InsertAt(text, offset) => {
+                info!("inserting {} bytes at {}", text.len(), offset);
+
+                let insert_point = self.get_iter_at_utf16_offset(offset)?;
+                self.replace_range(insert_point.clone(), insert_point, text.into_bytes())?;
+            }
+            TextReplacement::WithSelection(replacement, range) => {
+                self.do_replacement(*replacement)?;
+
+                info!("selecting {}..{}", range.start, range.end);
+                self.select_range(range)?;
+            }
             TextReplacement::None => {
                 info!("no text to replace");
             }
@@ -412,4 +932,122 @@ impl App {
 
         Ok(())
     }
+
+    fn replace_selection(&self, bytes: Vec<u8>) -> Result<()> {
+        let buffer = &self
+            .source_view
+            .get_buffer()
+            .ok_or_else(|| eyre!("Failed to get buffer"))?;
+
+        match buffer.get_selection_bounds() {
+            Some((start, end)) => self.replace_range(start, end, bytes),
+            None => {
+                error!("tried to do a selection replacement, but no text is selected!");
+                Ok(())
+            }
+        }
+    }
+
+    // binary contents of the buffer, they are only used while the buffer still shows their hex
+    // dump and are forgotten once it was edited
+    fn binary_contents(&self, buffer_text: &str) -> Option<Vec<u8>> {
+        let mut binary = self.binary.write().expect("Binary lock poisoned");
+        match binary.as_ref() {
+            Some(bytes) if bytes.hex_dump() == buffer_text => Some(bytes.clone()),
+            _ => {
+                *binary = None;
+                None
+            }
+        }
+    }
+
+    // text that isn't valid UTF-8 or contains NUL bytes can't be edited in place, it is spliced
+    // into the rest of the buffer and everything is shown as binary
+    fn replace_range(
+        &self,
+        mut start: gtk::TextIter,
+        mut end: gtk::TextIter,
+        bytes: Vec<u8>,
+    ) -> Result<()> {
+        let buffer = &self
+            .source_view
+            .get_buffer()
+            .ok_or_else(|| eyre!("Failed to get buffer"))?;
+
+        if start == buffer.get_start_iter() && end == buffer.get_end_iter() {
+            return self.set_bytes(bytes);
+        }
+
+        // offsets into a hex dump don't match the bytes it shows, binary is only replaced as a whole
+        let buffer_text = buffer
+            .get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), false)
+            .ok_or_else(|| eyre!("Failed to get buffer text"))?;
+        if self.binary_contents(&buffer_text).is_some() {
+            self.post_notification_error(
+                "Part of binary data can't be replaced, the result was discarded",
+                NOTIFICATION_LONG_DELAY,
+            );
+            return Ok(());
+        }
+
+        match String::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => {
+                buffer.delete(&mut start, &mut end);
+                buffer.insert(&mut start, &text);
+                Ok(())
+            }
+            result => {
+                let bytes = match result {
+                    Ok(text) => text.into_bytes(),
+                    Err(err) => err.into_bytes(),
+                };
+
+                let before = buffer
+                    .get_text(&buffer.get_start_iter(), &start, false)
+                    .ok_or_else(|| eyre!("Failed to get buffer text"))?;
+                let after = buffer
+                    .get_text(&end, &buffer.get_end_iter(), false)
+                    .ok_or_else(|| eyre!("Failed to get buffer text"))?;
+
+                self.set_bytes([before.as_bytes(), &bytes, after.as_bytes()].concat())
+            }
+        }
+    }
+
+    // shows bytes as text if possible, otherwise as a hex dump, scripts run on the hex dump
+    // receive the original bytes
+    fn set_bytes(&self, bytes: Vec<u8>) -> Result<()> {
+        let buffer = &self
+            .source_view
+            .get_buffer()
+            .ok_or_else(|| eyre!("Failed to get buffer"))?;
+
+        match String::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => {
+                self.binary.write().expect("Binary lock poisoned").take();
+                buffer.set_text(&text);
+            }
+            result => {
+                let bytes = match result {
+                    Ok(text) => text.into_bytes(),
+                    Err(err) => err.into_bytes(),
+                };
+
+                info!("showing {} bytes as hex", bytes.len());
+
+                buffer.set_text(&bytes.hex_dump());
+                self.binary
+                    .write()
+                    .expect("Binary lock poisoned")
+                    .replace(bytes);
+
+                self.post_notification(
+                    "Result is binary and shown as hex, scripts run on it receive the original bytes",
+                    NOTIFICATION_LONG_DELAY,
+                );
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,348 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, RwLock},
+    thread,
+};
+
+use eyre::{Context, Result};
+use gladis::Gladis;
+use glib::Type;
+use gtk::{prelude::*, Button, Dialog, Entry, HeaderBar, Label, TreeView, Window};
+use shrinkwraprs::Shrinkwrap;
+
+use crate::{
+    catalog::{Catalog, Download, EntryStatus},
+    config::Config,
+    scriptmap::ScriptMap,
+};
+
+const TEXT_COLUMN: u32 = 0;
+const VERSION_COLUMN: u32 = 1;
+const STATUS_COLUMN: u32 = 2;
+const NAME_COLUMN: u32 = 3;
+
+const COLUMNS: [u32; 4] = [TEXT_COLUMN, VERSION_COLUMN, STATUS_COLUMN, NAME_COLUMN];
+const COLUMN_TYPES: [Type; 4] = [Type::String, Type::String, Type::String, Type::String];
+
+#[derive(Shrinkwrap, Gladis, Clone)]
+pub struct CatalogDialogWidgets {
+    #[shrinkwrap(main_field)]
+    catalog_dialog: Dialog,
+    catalog_header_bar: HeaderBar,
+    index_entry: Entry,
+    load_index_button: Button,
+    catalog_tree_view: TreeView,
+    catalog_status_label: Label,
+    catalog_install_button: Button,
+}
+
+// lists the scripts of a script index, scripts are installed to the user scripts directory
+#[derive(Shrinkwrap, Clone)]
+pub struct CatalogDialog {
+    #[shrinkwrap(main_field)]
+    widgets: CatalogDialogWidgets,
+
+    scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    store: gtk::ListStore,
+    catalog: Rc<RefCell<Option<Catalog>>>,
+}
+
+impl CatalogDialog {
+    pub(crate) fn new<P: IsA<Window>>(
+        window: &P,
+        scripts: Arc<RwLock<ScriptMap>>,
+        config: Arc<RwLock<Config>>,
+    ) -> Result<Self> {
+        let widgets = CatalogDialogWidgets::from_resource("/fyi/zoey/Boop-GTK/catalog.glade")
+            .wrap_err("Failed to load catalog.glade")?;
+
+        let dialog = CatalogDialog {
+            widgets,
+            scripts,
+            config,
+            store: gtk::ListStore::new(&COLUMN_TYPES),
+            catalog: Rc::new(RefCell::new(None)),
+        };
+        dialog.set_transient_for(Some(window));
+
+        for (title, column_id, markup) in &[
+            ("Script", TEXT_COLUMN, true),
+            ("Version", VERSION_COLUMN, false),
+            ("Status", STATUS_COLUMN, false),
+        ] {
+            let renderer = gtk::CellRendererText::new();
+            renderer.set_property_ellipsize(pango::EllipsizeMode::End);
+
+            let column = gtk::TreeViewColumn::new();
+            column.set_title(title);
+            column.set_expand(*column_id == TEXT_COLUMN);
+            column.pack_start(&renderer, true);
+            column.add_attribute(
+                &renderer,
+                if *markup { "markup" } else { "text" },
+                *column_id as i32,
+            );
+            dialog.catalog_tree_view.append_column(&column);
+        }
+
+        dialog
+            .catalog_tree_view
+            .set_search_column(NAME_COLUMN as i32);
+        dialog.catalog_tree_view.set_model(Some(&dialog.store));
+
+        let index = dialog
+            .config
+            .read()
+            .expect("Config lock poisoned")
+            .catalog
+            .index
+            .clone();
+        dialog.index_entry.set_text(&index);
+
+        dialog.register_handlers();
+
+        // the last index is loaded straight away
+        if !index.is_empty() {
+            dialog.load_index();
+        }
+
+        Ok(dialog)
+    }
+
+    fn register_handlers(&self) {
+        {
+            let dialog = self.clone();
+            self.load_index_button
+                .connect_clicked(move |_| dialog.load_index());
+        }
+
+        {
+            let dialog = self.clone();
+            self.index_entry
+                .connect_activate(move |_| dialog.load_index());
+        }
+
+        {
+            let dialog = self.clone();
+            self.catalog_tree_view
+                .get_selection()
+                .connect_changed(move |_| dialog.update_install_button());
+        }
+
+        {
+            let dialog = self.clone();
+            self.catalog_install_button
+                .connect_clicked(move |_| dialog.install_selected());
+        }
+    }
+
+    // loads the index in the entry and remembers it for next time, the index is fetched on another
+    // thread so the window stays responsive
+    fn load_index(&self) {
+        let location = self.index_entry.get_text().to_string();
+
+        {
+            let mut config = self.config.write().expect("Config lock poisoned");
+            if config.catalog.index != location {
+                config.catalog.index = location.clone();
+                if let Err(err) = config.save() {
+                    error!("failed to save config: {:?}", err);
+                }
+            }
+        }
+
+        self.set_busy(true);
+        self.post_status(&format!("Loading {}…", location));
+
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        thread::spawn(move || {
+            // the dialog may have been closed
+            sender.send(Catalog::load(&location)).ok();
+        });
+
+        let dialog = self.clone();
+        receiver.attach(None, move |result| {
+            dialog.set_busy(false);
+            dialog.index_loaded(result);
+            Continue(false)
+        });
+    }
+
+    fn index_loaded(&self, result: Result<Catalog>) {
+        match result {
+            Ok(catalog) => {
+                let subtitle = catalog.index.name.clone();
+                self.catalog.borrow_mut().replace(catalog);
+                self.catalog_header_bar.set_subtitle(Some(&subtitle));
+                self.refresh(None);
+                self.post_status("");
+            }
+            Err(err) => {
+                self.catalog.borrow_mut().take();
+                self.catalog_header_bar.set_subtitle(None);
+                self.refresh(None);
+                self.post_error(&err);
+            }
+        }
+    }
+
+    // rebuilds the list from the loaded index
+    fn refresh(&self, select: Option<String>) {
+        self.store.clear();
+
+        let catalog = self.catalog.borrow();
+        let catalog = match catalog.as_ref() {
+            Some(catalog) => catalog,
+            None => {
+                self.update_install_button();
+                return;
+            }
+        };
+
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+        let config = self.config.read().expect("Config lock poisoned");
+
+        let mut selected_iter = None;
+        for entry in &catalog.index.scripts {
+            let text = format!(
+                "<b>{}</b>\n<span size=\"smaller\">{}</span>",
+                glib::markup_escape_text(&entry.name),
+                glib::markup_escape_text(&entry.description)
+            );
+            let status = catalog.status(entry, &scripts, &config).to_string();
+
+            let values: [&dyn ToValue; 4] = [&text, &entry.version, &status, &entry.name];
+            let iter = self.store.append();
+            self.store.set(&iter, &COLUMNS, &values);
+
+            if select.as_ref() == Some(&entry.name) {
+                selected_iter = Some(iter);
+            }
+        }
+
+        drop(scripts);
+        drop(config);
+
+        let selection = self.catalog_tree_view.get_selection();
+        match selected_iter.or_else(|| self.store.get_iter_first()) {
+            Some(iter) => selection.select_iter(&iter),
+            None => selection.unselect_all(),
+        }
+
+        self.update_install_button();
+    }
+
+    fn selected_name(&self) -> Option<String> {
+        let (model, iter) = self.catalog_tree_view.get_selection().get_selected()?;
+
+        model
+            .get_value(&iter, NAME_COLUMN as i32)
+            .get::<String>()
+            .ok()
+            .flatten()
+    }
+
+    fn selected_status(&self) -> Option<EntryStatus> {
+        let name = self.selected_name()?;
+        let catalog = self.catalog.borrow();
+        let catalog = catalog.as_ref()?;
+
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+        let config = self.config.read().expect("Config lock poisoned");
+        Some(catalog.status(catalog.entry(&name)?, &scripts, &config))
+    }
+
+    fn update_install_button(&self) {
+        let (label, sensitive) = match self.selected_status() {
+            Some(EntryStatus::NotInstalled) => ("Install", true),
+            Some(EntryStatus::UpdateAvailable(_)) => ("Update", true),
+            Some(EntryStatus::Installed) => ("Installed", false),
+            // the user script has to be deleted in the script manager first
+            Some(EntryStatus::Conflict) => ("Install", false),
+            None => ("Install", false),
+        };
+
+        self.catalog_install_button.set_label(label);
+        self.catalog_install_button.set_sensitive(sensitive);
+    }
+
+    // downloads the selected script on another thread, it's installed once the download finished
+    fn install_selected(&self) {
+        let name = match self.selected_name() {
+            Some(name) => name,
+            None => return,
+        };
+        let catalog = match self.catalog.borrow().clone() {
+            Some(catalog) => catalog,
+            None => return,
+        };
+
+        self.set_busy(true);
+        self.post_status(&format!("Downloading \"{}\"…", name));
+
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        {
+            let name = name.clone();
+            let catalog = catalog.clone();
+            thread::spawn(move || {
+                let result = catalog
+                    .entry(&name)
+                    .ok_or_else(|| eyre!("\"{}\" isn't in the script index", name))
+                    .and_then(|entry| catalog.download_script(entry));
+                sender.send(result).ok();
+            });
+        }
+
+        let dialog = self.clone();
+        receiver.attach(None, move |download| {
+            dialog.set_busy(false);
+            match download.and_then(|download| dialog.install(&catalog, &name, download)) {
+                Ok(()) => dialog.refresh(Some(name.clone())),
+                Err(err) => {
+                    dialog.update_install_button();
+                    dialog.post_error(&err);
+                }
+            }
+            Continue(false)
+        });
+    }
+
+    // the locks are only held while the downloaded script is written and loaded
+    fn install(&self, catalog: &Catalog, name: &str, download: Download) -> Result<()> {
+        let entry = catalog
+            .entry(name)
+            .ok_or_else(|| eyre!("\"{}\" isn't in the script index", name))?;
+
+        let mut scripts = self.scripts.write().expect("Scripts lock is poisoned");
+        let mut config = self.config.write().expect("Config lock poisoned");
+        catalog.install(entry, download, &mut scripts, &mut config)?;
+        config.save().wrap_err("Failed to save config")?;
+
+        self.post_status(&format!("Installed \"{}\" {}", name, entry.version));
+
+        Ok(())
+    }
+
+    // loading and installing can't be started again until they finished
+    fn set_busy(&self, busy: bool) {
+        self.index_entry.set_sensitive(!busy);
+        self.load_index_button.set_sensitive(!busy);
+        if busy {
+            self.catalog_install_button.set_sensitive(false);
+        }
+    }
+
+    fn post_status(&self, text: &str) {
+        self.catalog_status_label.set_text(text);
+    }
+
+    fn post_error(&self, err: &eyre::Report) {
+        error!("script catalog: {:?}", err);
+        self.catalog_status_label.set_markup(&format!(
+            r#"<span foreground="red" weight="bold">ERROR:</span> {}"#,
+            glib::markup_escape_text(&err.to_string())
+        ));
+    }
+}
@@ -1,15 +1,14 @@
 use eyre::{Context, ContextCompat, Result};
-use fuse_rust::Fuse;
-use gdk::{keys, EventKey};
+use gdk::{keys, EventButton, EventKey};
 use gio::prelude::*;
 use gladis::Gladis;
 use glib::Type;
 use gtk::prelude::*;
-use gtk::{Dialog, Entry, TreePath, TreeView, Window};
+use gtk::{Dialog, Entry, Menu, MenuItem, TreePath, TreeView, Window};
 use once_cell::unsync::OnceCell;
 use shrinkwraprs::Shrinkwrap;
 
-use crate::{script::Script, scriptmap::ScriptMap};
+use crate::{config::Config, script::ScriptOrigin, scriptmap::ScriptMap};
 
 use std::{
     collections::HashMap,
@@ -43,7 +42,48 @@ const ICON_COLUMN_PADDING: i32 = 8;
 const ICON_COLUMN_WIDTH: i32 = ICON_COLUMN_PADDING + 32 + ICON_COLUMN_PADDING; // IconSize::Dnd = 32
 const TEXT_COLUMN_WIDTH: i32 = DIALOG_WIDTH - ICON_COLUMN_WIDTH;
 
-#[derive(Shrinkwrap, Gladis)]
+// dialog response when an action was chosen from the context menu of a script
+pub const ACTION_RESPONSE: gtk::ResponseType = gtk::ResponseType::Apply;
+
+// actions in the context menu of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptAction {
+    // open a user script in the script editor
+    Edit(String),
+    // copy a built-in script to the user scripts directory and edit the copy
+    Override(String),
+    // delete the user script overriding a built-in script
+    Revert(String),
+}
+
+impl ScriptAction {
+    pub fn available(scripts: &ScriptMap, name: &str) -> Vec<ScriptAction> {
+        let origin = match scripts.scripts.get(name) {
+            Some(script) => script.origin(),
+            None => return Vec::new(),
+        };
+
+        match origin {
+            ScriptOrigin::Builtin => vec![ScriptAction::Override(name.to_string())],
+            ScriptOrigin::User if scripts.builtin_files.contains_key(name) => vec![
+                ScriptAction::Edit(name.to_string()),
+                ScriptAction::Revert(name.to_string()),
+            ],
+            ScriptOrigin::User => vec![ScriptAction::Edit(name.to_string())],
+            ScriptOrigin::Global => Vec::new(),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ScriptAction::Edit(_) => "Edit Script",
+            ScriptAction::Override(_) => "Override Built-in Script",
+            ScriptAction::Revert(_) => "Revert to Built-in Script",
+        }
+    }
+}
+
+#[derive(Shrinkwrap, Gladis, Clone)]
 pub struct CommandPaletteDialogWidgets {
     #[shrinkwrap(main_field)]
     dialog: Dialog,
@@ -51,39 +91,49 @@ pub struct CommandPaletteDialogWidgets {
     search_bar: Entry,
 }
 
-#[derive(Shrinkwrap)]
+#[derive(Shrinkwrap, Clone)]
 pub struct CommandPaletteDialog {
     #[shrinkwrap(main_field)]
     widgets: CommandPaletteDialogWidgets,
 
     scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    store: gtk::ListStore,
     selected_script: Rc<OnceCell<String>>,
+    selected_action: Rc<OnceCell<ScriptAction>>,
 }
 
 impl CommandPaletteDialog {
-    pub(crate) fn new<P: IsA<Window>>(window: &P, scripts: Arc<RwLock<ScriptMap>>) -> Result<Self> {
+    pub(crate) fn new<P: IsA<Window>>(
+        window: &P,
+        scripts: Arc<RwLock<ScriptMap>>,
+        config: Arc<RwLock<Config>>,
+    ) -> Result<Self> {
         let widgets =
             CommandPaletteDialogWidgets::from_resource("/fyi/zoey/Boop-GTK/command-palette.glade")
                 .wrap_err("Failed to load command-palette.glade")?;
 
         let command_palette_dialog = CommandPaletteDialog {
             widgets,
-            scripts: scripts.clone(),
+            scripts,
+            config,
+            store: gtk::ListStore::new(&COLUMN_TYPES),
             selected_script: Rc::new(OnceCell::new()),
+            selected_action: Rc::new(OnceCell::new()),
         };
 
         command_palette_dialog.set_transient_for(Some(window));
 
         // create list store
         {
-            let store = gtk::ListStore::new(&COLUMN_TYPES);
+            let store = &command_palette_dialog.store;
 
             store.set_sort_column_id(
                 gtk::SortColumn::Index(SCORE_COLUMN),
                 gtk::SortType::Descending,
             );
 
-            let filtered_store = gtk::TreeModelFilter::new(&store, None);
+            let filtered_store = gtk::TreeModelFilter::new(store, None);
             filtered_store.set_visible_column(VISIBLE_COLUMN as i32);
 
             // icon column
@@ -133,27 +183,7 @@ impl CommandPaletteDialog {
                 }
             }
 
-            for (index, (name, script)) in scripts
-                .read()
-                .expect("scripts lock is poisoned")
-                .0
-                .iter()
-                .enumerate()
-            {
-                let mut icon_name = script.metadata.icon.to_lowercase();
-                icon_name.insert_str(0, "boop-gtk-");
-                icon_name.push_str("-symbolic");
-
-                let entry_text = format!(
-                    "<b>{}</b>\n<span size=\"smaller\">{}</span>",
-                    script.metadata.name.to_string(),
-                    script.metadata.description.to_string()
-                );
-
-                let values: [&dyn ToValue; 5] =
-                    [&icon_name, &entry_text, &name, &(-(index as i64)), &true];
-                store.set(&store.append(), &COLUMNS, &values);
-            }
+            command_palette_dialog.fill_store();
 
             command_palette_dialog
                 .dialog_tree_view
@@ -171,10 +201,69 @@ impl CommandPaletteDialog {
         Ok(command_palette_dialog)
     }
 
+    // adds a row for each enabled script
+    fn fill_store(&self) {
+        let scripts = self.scripts.read().expect("scripts lock is poisoned");
+        let config = self.config.read().expect("Config lock poisoned");
+
+        for (index, (name, script)) in scripts.scripts.iter().enumerate() {
+            // disabled scripts are hidden, they can be enabled in the script manager
+            if config.is_disabled(name) {
+                continue;
+            }
+
+            let mut icon_name = script.metadata.icon.to_lowercase();
+            icon_name.insert_str(0, "boop-gtk-");
+            icon_name.push_str("-symbolic");
+
+            // user and global scripts can replace built-ins with the same name
+            let overridden = if scripts.is_overridden(name) {
+                " <span size=\"smaller\"><i>overridden</i></span>"
+            } else {
+                ""
+            };
+
+            // scripts in subfolders are grouped by folder
+            let category = script
+                .category()
+                .map(|category| format!("{} · ", glib::markup_escape_text(&category)))
+                .unwrap_or_default();
+
+            let entry_text = format!(
+                "<b>{}</b>{}\n<span size=\"smaller\">{}{}</span>",
+                script.metadata.name.to_string(),
+                overridden,
+                category,
+                script.metadata.description.to_string()
+            );
+
+            let values: [&dyn ToValue; 5] =
+                [&icon_name, &entry_text, &name, &(-(index as i64)), &true];
+            self.store.set(&self.store.append(), &COLUMNS, &values);
+        }
+    }
+
+    // rebuilds the rows after the scripts changed while the palette is open, the search is kept
+    pub(crate) fn refresh(&self) -> Result<()> {
+        self.store.clear();
+        self.fill_store();
+
+        CommandPaletteDialog::on_changed(
+            &self.search_bar,
+            &self.dialog_tree_view,
+            self.scripts.clone(),
+        )
+    }
+
     pub(crate) fn get_selected(&self) -> Option<&String> {
         self.selected_script.get()
     }
 
+    // action chosen from the context menu, the dialog responds with ACTION_RESPONSE
+    pub(crate) fn get_action(&self) -> Option<&ScriptAction> {
+        self.selected_action.get()
+    }
+
     fn register_handlers(&self) {
         {
             let lb = self.dialog_tree_view.clone();
@@ -196,6 +285,29 @@ impl CommandPaletteDialog {
             });
         }
 
+        // context menu on right click
+        {
+            let dialog = self.dialog.clone();
+            let scripts = self.scripts.clone();
+            let action = self.selected_action.clone();
+            self.dialog_tree_view
+                .connect_button_press_event(move |tv, event| {
+                    CommandPaletteDialog::on_button_press(tv, event, &dialog, &scripts, &action)
+                        .expect("On button press handler failed")
+                });
+        }
+
+        // context menu from the keyboard (menu key or shift+F10)
+        {
+            let dialog = self.dialog.clone();
+            let scripts = self.scripts.clone();
+            let action = self.selected_action.clone();
+            self.dialog_tree_view.connect_popup_menu(move |tv| {
+                CommandPaletteDialog::show_context_menu(tv, None, &dialog, &scripts, &action)
+                    .expect("Failed to show context menu")
+            });
+        }
+
         {
             let dialog = self.dialog.clone();
             let selected = self.selected_script.clone();
@@ -257,23 +369,8 @@ impl CommandPaletteDialog {
         dialog: &Dialog,
         selected: &OnceCell<String>,
     ) -> Result<()> {
-        let model: gtk::TreeModelFilter = dialog_tree_view.get_model().unwrap().downcast().unwrap();
-
         if let (Some(path), _) = dialog_tree_view.get_cursor() {
-            let value = model.get_value(
-                &model
-                    .get_iter(&path)
-                    .wrap_err_with(|| format!("failed to get iter for path: {:?}", path))?,
-                NAME_COLUMN as i32,
-            );
-
-            let value_string = value
-                .downcast::<String>()
-                .map_err(|value| eyre!("Value: {:?}", value))
-                .wrap_err("Cannot downcast value to String")?
-                .get();
-
-            if let Some(v) = value_string {
+            if let Some(v) = CommandPaletteDialog::get_name(dialog_tree_view, &path)? {
                 debug!("v: {}", v);
                 selected.set(v).unwrap();
                 debug!("selected: {:?}", selected.get());
@@ -285,6 +382,104 @@ impl CommandPaletteDialog {
         Ok(())
     }
 
+    // name of the script in a row
+    fn get_name(dialog_tree_view: &TreeView, path: &TreePath) -> Result<Option<String>> {
+        let model: gtk::TreeModelFilter = dialog_tree_view.get_model().unwrap().downcast().unwrap();
+
+        let value = model.get_value(
+            &model
+                .get_iter(path)
+                .wrap_err_with(|| format!("failed to get iter for path: {:?}", path))?,
+            NAME_COLUMN as i32,
+        );
+
+        Ok(value
+            .downcast::<String>()
+            .map_err(|value| eyre!("Value: {:?}", value))
+            .wrap_err("Cannot downcast value to String")?
+            .get())
+    }
+
+    fn on_button_press(
+        dialog_tree_view: &TreeView,
+        event: &EventButton,
+        dialog: &Dialog,
+        scripts: &Arc<RwLock<ScriptMap>>,
+        action: &Rc<OnceCell<ScriptAction>>,
+    ) -> Result<Inhibit> {
+        if event.get_event_type() != gdk::EventType::ButtonPress || event.get_button() != 3 {
+            return Ok(Inhibit(false));
+        }
+
+        // right clicking a row selects it
+        let (x, y) = event.get_position();
+        if let Some((Some(path), _, _, _)) = dialog_tree_view.get_path_at_pos(x as i32, y as i32) {
+            dialog_tree_view.set_cursor(&path, gtk::NONE_TREE_VIEW_COLUMN, false);
+        }
+
+        CommandPaletteDialog::show_context_menu(
+            dialog_tree_view,
+            Some(event),
+            dialog,
+            scripts,
+            action,
+        )?;
+
+        Ok(Inhibit(true))
+    }
+
+    // shows the actions of the script under the cursor, returns false if it has none
+    fn show_context_menu(
+        dialog_tree_view: &TreeView,
+        event: Option<&EventButton>,
+        dialog: &Dialog,
+        scripts: &Arc<RwLock<ScriptMap>>,
+        action: &Rc<OnceCell<ScriptAction>>,
+    ) -> Result<bool> {
+        let name = match dialog_tree_view.get_cursor() {
+            (Some(path), _) => CommandPaletteDialog::get_name(dialog_tree_view, &path)?,
+            _ => None,
+        };
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(false),
+        };
+
+        let actions =
+            ScriptAction::available(&scripts.read().expect("scripts lock is poisoned"), &name);
+        if actions.is_empty() {
+            return Ok(false);
+        }
+
+        let menu = Menu::new();
+        for script_action in actions {
+            let item = MenuItem::with_label(script_action.label());
+
+            let dialog = dialog.clone();
+            let action = action.clone();
+            item.connect_activate(move |_| {
+                action.set(script_action.clone()).ok();
+                dialog.response(ACTION_RESPONSE);
+            });
+
+            menu.append(&item);
+        }
+
+        menu.set_property_attach_widget(Some(dialog_tree_view));
+        menu.show_all();
+        match event {
+            Some(event) => menu.popup_at_pointer(Some(&**event)),
+            None => menu.popup_at_widget(
+                dialog_tree_view,
+                gdk::Gravity::Center,
+                gdk::Gravity::NorthWest,
+                None,
+            ),
+        }
+
+        Ok(true)
+    }
+
     fn on_changed(
         searchbar: &Entry,
         dialog_tree_view: &TreeView,
@@ -302,11 +497,10 @@ impl CommandPaletteDialog {
         let searchbar_text = searchbar.get_text().to_owned();
         let script_count = store.iter_n_children(None);
         let scripts_ref = scripts.read().expect("scripts lock is poisoned");
-        let script_vec = scripts_ref.0.values().collect::<Vec<&Script>>();
 
         if searchbar_text.is_empty() {
             let script_order: HashMap<String, usize> = scripts_ref
-                .0
+                .scripts
                 .iter()
                 .enumerate()
                 .map(|(idx, (name, _))| (name.clone(), idx))
@@ -334,11 +528,7 @@ impl CommandPaletteDialog {
                 store.set(&iter, &[SCORE_COLUMN, VISIBLE_COLUMN], &values);
             }
         } else {
-            let results: HashMap<String, f64> = Fuse::default()
-                .search_text_in_fuse_list(&searchbar_text, &*script_vec)
-                .into_iter()
-                .map(|result| (script_vec[result.index].metadata.name.clone(), result.score))
-                .collect();
+            let results = scripts_ref.search(&searchbar_text);
 
             for i in 0..script_count {
                 let mut path = gtk::TreePath::new();
@@ -1,5 +1,8 @@
 pub(crate) mod about_dialog;
 pub(crate) mod app;
+pub(crate) mod catalog_dialog;
 pub(crate) mod command_palette;
 pub(crate) mod preferences_dialog;
+pub(crate) mod script_editor;
+pub(crate) mod script_manager;
 pub(crate) mod shortcuts_window;
@@ -0,0 +1,292 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, RwLock},
+};
+
+use eyre::{Context, Result};
+use gladis::Gladis;
+use glib::SignalHandlerId;
+use gtk::{prelude::*, Button, HeaderBar, Label, Window};
+use sourceview::prelude::*;
+
+use crate::{
+    config::Config,
+    script::{Metadata, ParseScriptError, Script},
+    scriptmap::ScriptMap,
+    util::SourceViewExt,
+};
+
+// pre-filled when the editor is opened or "New" is clicked
+pub const SCRIPT_TEMPLATE: &str = r#"/**
+	{
+		"api": 1,
+		"name": "New Script",
+		"description": "What does your script do?",
+		"author": "",
+		"icon": "type",
+		"tags": "new,script"
+	}
+**/
+
+function main(state) {
+	state.text = state.text;
+}
+"#;
+
+#[derive(Gladis, Clone, Shrinkwrap)]
+pub struct ScriptEditorWidgets {
+    #[shrinkwrap(main_field)]
+    script_editor_window: Window,
+
+    script_editor_header_bar: HeaderBar,
+    script_source_view: sourceview::View,
+    validation_label: Label,
+    new_script_button: Button,
+    save_script_button: Button,
+    run_script_button: Button,
+}
+
+#[derive(Clone, Shrinkwrap)]
+pub struct ScriptEditor {
+    #[shrinkwrap(main_field)]
+    widgets: ScriptEditorWidgets,
+    // where the script was saved, new scripts are named after the script when first saved
+    path: Rc<RefCell<Option<PathBuf>>>,
+}
+
+impl ScriptEditor {
+    pub(crate) fn new<P: IsA<Window>>(window: &P, config: Arc<RwLock<Config>>) -> Result<Self> {
+        let editor = ScriptEditor {
+            widgets: ScriptEditorWidgets::from_resource("/fyi/zoey/Boop-GTK/script-editor.glade")
+                .wrap_err("Failed to load script-editor.glade")?,
+            path: Rc::new(RefCell::new(None)),
+        };
+
+        editor.script_editor_window.set_transient_for(Some(window));
+        editor.configure(&config)?;
+
+        // validate the metadata as you type
+        {
+            let editor_ = editor.clone();
+            editor
+                .script_source_view
+                .get_sourceview_buffer()?
+                .connect_changed(move |_| editor_.update_validation());
+        }
+
+        {
+            let editor_ = editor.clone();
+            editor
+                .new_script_button
+                .connect_clicked(move |_| editor_.new_script());
+        }
+
+        {
+            let editor_ = editor.clone();
+            editor.save_script_button.connect_clicked(move |_| {
+                if let Err(err) = editor_.save() {
+                    error!("failed to save script: {:?}", err);
+                    editor_.validation_label.set_markup(&format!(
+                        r#"<span foreground="red" weight="bold">ERROR:</span> {}"#,
+                        glib::markup_escape_text(&err.to_string())
+                    ));
+                }
+            });
+        }
+
+        editor.new_script();
+
+        Ok(editor)
+    }
+
+    fn configure(&self, config: &Arc<RwLock<Config>>) -> Result<()> {
+        let buffer = self.script_source_view.get_sourceview_buffer()?;
+
+        let language = sourceview::LanguageManager::get_default()
+            .ok_or_else(|| eyre!("Failed to get language manager"))?
+            .get_language("js");
+        buffer.set_highlight_syntax(true);
+        buffer.set_language(language.as_ref());
+
+        let config = config
+            .read()
+            .map_err(|e| eyre!("Config lock poisoned: {}", e))?;
+        let scheme = sourceview::StyleSchemeManager::get_default()
+            .ok_or_else(|| eyre!("Failed to get default style scheme manager"))?
+            .get_scheme(&config.editor.colour_scheme_id);
+        buffer.set_style_scheme(scheme.as_ref());
+
+        Ok(())
+    }
+
+    fn source(&self) -> String {
+        self.script_source_view
+            .get_buffer()
+            .and_then(|buffer| {
+                buffer.get_text(&buffer.get_start_iter(), &buffer.get_end_iter(), false)
+            })
+            .map(|text| text.to_string())
+            .unwrap_or_default()
+    }
+
+    // replaces the source with the template, the next save creates a new file
+    fn new_script(&self) {
+        if !self.confirm_discard() {
+            return;
+        }
+
+        self.path.borrow_mut().take();
+        self.script_editor_header_bar.set_subtitle(Some("Unsaved"));
+
+        if let Some(buffer) = self.script_source_view.get_buffer() {
+            buffer.set_text(SCRIPT_TEMPLATE);
+            buffer.set_modified(false);
+        }
+    }
+
+    // edits an existing script, saving writes back to it
+    pub fn open(&self, path: &Path) -> Result<()> {
+        let source = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+        if let Some(buffer) = self.script_source_view.get_buffer() {
+            buffer.set_text(&source);
+            buffer.set_modified(false);
+        }
+        self.script_editor_header_bar
+            .set_subtitle(Some(&path.to_string_lossy()));
+        self.path.borrow_mut().replace(path.to_path_buf());
+
+        Ok(())
+    }
+
+    // asks before unsaved changes are replaced, true if there are none
+    fn confirm_discard(&self) -> bool {
+        let modified = self
+            .script_source_view
+            .get_buffer()
+            .map(|buffer| buffer.get_modified())
+            .unwrap_or(false);
+        if !modified {
+            return true;
+        }
+
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.script_editor_window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            "Discard unsaved changes?",
+        );
+        dialog.set_property_secondary_text(Some(
+            "The script in the editor has changes that haven't been saved.",
+        ));
+        dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+        dialog.add_button("Discard", gtk::ResponseType::Accept);
+        dialog.set_default_response(gtk::ResponseType::Cancel);
+
+        let response = dialog.run();
+        dialog.close();
+
+        response == gtk::ResponseType::Accept
+    }
+
+    fn update_validation(&self) {
+        let markup = match validate(&self.source()) {
+            Ok(metadata) => format!(
+                "\"{}\" is valid, uses script API version {}",
+                glib::markup_escape_text(&metadata.name),
+                metadata.api
+            ),
+            Err(err) => format!(
+                r#"<span foreground="red">{}</span>"#,
+                glib::markup_escape_text(&err.to_string())
+            ),
+        };
+
+        self.validation_label.set_markup(&markup);
+    }
+
+    // writes the source to the user scripts directory, the watcher loads it from there
+    fn save(&self) -> Result<()> {
+        let source = self.source();
+        let metadata = validate(&source).map_err(|err| eyre!("Can't save script, {}", err))?;
+
+        let path = match self.path.borrow().clone() {
+            Some(path) => path,
+            None => {
+                let dir = ScriptMap::user_scripts_dir();
+                fs::create_dir_all(&dir)
+                    .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+
+                let path = dir.join(ScriptMap::file_name(&metadata.name));
+                if path.exists() {
+                    return Err(eyre!("{} already exists", path.display()));
+                }
+                path
+            }
+        };
+
+        fs::write(&path, source).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+        info!("saved script to {}", path.display());
+
+        self.script_editor_header_bar
+            .set_subtitle(Some(&path.to_string_lossy()));
+        self.validation_label.set_markup(&format!(
+            "Saved \"{}\"",
+            glib::markup_escape_text(&metadata.name)
+        ));
+        self.path.borrow_mut().replace(path);
+        if let Some(buffer) = self.script_source_view.get_buffer() {
+            buffer.set_modified(false);
+        }
+
+        Ok(())
+    }
+
+    // f is called with the unsaved source when "Run on Current Buffer" is clicked
+    pub fn connect_run<F: Fn(&str) + 'static>(&self, f: F) -> SignalHandlerId {
+        let editor = self.clone();
+        self.run_script_button
+            .connect_clicked(move |_| f(&editor.source()))
+    }
+}
+
+// parses the metadata header of source
+pub(crate) fn validate(source: &str) -> Result<Metadata, ParseScriptError> {
+    Script::from_source(source.to_string(), PathBuf::new()).map(|script| script.metadata.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let metadata = validate(SCRIPT_TEMPLATE).unwrap();
+        assert_eq!(metadata.api, 1);
+        assert_eq!(metadata.name, "New Script");
+        assert_eq!(ScriptMap::file_name(&metadata.name), "NewScript.js");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(matches!(
+            validate("function main(state) {}"),
+            Err(ParseScriptError::NoMetadata)
+        ));
+        assert!(matches!(
+            validate(r#"/** { "api": 1, "name": "Missing Fields" } **/"#),
+            Err(ParseScriptError::InvalidMetadata(_))
+        ));
+        assert!(matches!(
+            validate(
+                r#"/** { "api": 99, "name": "Future", "description": "", "icon": "type" } **/"#
+            ),
+            Err(ParseScriptError::UnsupportedApi(_, 99))
+        ));
+    }
+}
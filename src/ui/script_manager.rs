@@ -0,0 +1,744 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use eyre::{Context, Result};
+use gladis::Gladis;
+use glib::Type;
+use gtk::{prelude::*, Button, Dialog, Image, InfoBar, Label, TreeView, Window};
+use shrinkwraprs::Shrinkwrap;
+
+use crate::{
+    config::Config,
+    install::{self, Collision, Package},
+    script::{Script, ScriptOrigin},
+    scriptmap::ScriptMap,
+};
+
+const ENABLED_COLUMN: u32 = 0;
+const ICON_COLUMN: u32 = 1;
+const TEXT_COLUMN: u32 = 2;
+const NAME_COLUMN: u32 = 3;
+const ORIGIN_COLUMN: u32 = 4;
+const PATH_COLUMN: u32 = 5;
+
+const COLUMNS: [u32; 6] = [
+    ENABLED_COLUMN,
+    ICON_COLUMN,
+    TEXT_COLUMN,
+    NAME_COLUMN,
+    ORIGIN_COLUMN,
+    PATH_COLUMN,
+];
+const COLUMN_TYPES: [Type; 6] = [
+    Type::Bool,
+    Type::String,
+    Type::String,
+    Type::String,
+    Type::String,
+    Type::String,
+];
+
+#[derive(Shrinkwrap, Gladis, Clone)]
+pub struct ScriptManagerDialogWidgets {
+    #[shrinkwrap(main_field)]
+    script_manager_dialog: Dialog,
+    script_manager_tree_view: TreeView,
+    reload_button: Button,
+    install_button: Button,
+
+    load_errors_info_bar: InfoBar,
+    load_errors_label: Label,
+
+    details_box: gtk::Box,
+    details_icon: Image,
+    details_name_label: Label,
+    details_description_label: Label,
+    details_author_label: Label,
+    details_tags_label: Label,
+    details_api_label: Label,
+    details_permissions_label: Label,
+    details_persistent_label: Label,
+    details_origin_label: Label,
+    details_path_label: Label,
+    details_override_label: Label,
+    details_last_run_label: Label,
+    details_pack_label: Label,
+    manager_status_label: Label,
+
+    open_file_button: Button,
+    open_folder_button: Button,
+    duplicate_button: Button,
+    delete_button: Button,
+    uninstall_pack_button: Button,
+}
+
+// lists every script with its metadata and a toggle to hide it from the command palette, user
+// scripts can be managed from here
+#[derive(Shrinkwrap, Clone)]
+pub struct ScriptManagerDialog {
+    #[shrinkwrap(main_field)]
+    widgets: ScriptManagerDialogWidgets,
+
+    scripts: Arc<RwLock<ScriptMap>>,
+    config: Arc<RwLock<Config>>,
+    store: gtk::ListStore,
+}
+
+impl ScriptManagerDialog {
+    pub(crate) fn new<P: IsA<Window>>(
+        window: &P,
+        scripts: Arc<RwLock<ScriptMap>>,
+        config: Arc<RwLock<Config>>,
+    ) -> Result<Self> {
+        let widgets =
+            ScriptManagerDialogWidgets::from_resource("/fyi/zoey/Boop-GTK/script-manager.glade")
+                .wrap_err("Failed to load script-manager.glade")?;
+
+        let dialog = ScriptManagerDialog {
+            widgets,
+            scripts,
+            config,
+            store: gtk::ListStore::new(&COLUMN_TYPES),
+        };
+        dialog.set_transient_for(Some(window));
+
+        // enabled column
+        {
+            let renderer = gtk::CellRendererToggle::new();
+
+            let store = dialog.store.clone();
+            let config = dialog.config.clone();
+            renderer.connect_toggled(move |_, path| {
+                if let Err(err) = ScriptManagerDialog::on_toggled(&store, path, &config) {
+                    error!("failed to toggle script: {:?}", err);
+                }
+            });
+
+            let column = gtk::TreeViewColumn::new();
+            column.set_title("Enabled");
+            column.pack_start(&renderer, false);
+            column.add_attribute(&renderer, "active", ENABLED_COLUMN as i32);
+            dialog.script_manager_tree_view.append_column(&column);
+        }
+
+        // script column
+        {
+            let icon_renderer = gtk::CellRendererPixbuf::new();
+            icon_renderer.set_padding(4, 4);
+
+            let text_renderer = gtk::CellRendererText::new();
+            text_renderer.set_property_ellipsize(pango::EllipsizeMode::End);
+
+            let column = gtk::TreeViewColumn::new();
+            column.set_title("Script");
+            column.set_expand(true);
+            column.pack_start(&icon_renderer, false);
+            column.add_attribute(&icon_renderer, "icon-name", ICON_COLUMN as i32);
+            column.pack_start(&text_renderer, true);
+            column.add_attribute(&text_renderer, "markup", TEXT_COLUMN as i32);
+            dialog.script_manager_tree_view.append_column(&column);
+        }
+
+        // origin column
+        {
+            let renderer = gtk::CellRendererText::new();
+
+            let column = gtk::TreeViewColumn::new();
+            column.set_title("Source");
+            column.pack_start(&renderer, false);
+            column.add_attribute(&renderer, "text", ORIGIN_COLUMN as i32);
+            dialog.script_manager_tree_view.append_column(&column);
+        }
+
+        dialog
+            .script_manager_tree_view
+            .set_search_column(NAME_COLUMN as i32);
+        dialog
+            .script_manager_tree_view
+            .set_tooltip_column(PATH_COLUMN as i32);
+        dialog
+            .script_manager_tree_view
+            .set_model(Some(&dialog.store));
+
+        dialog.refresh(None);
+        dialog.register_handlers();
+
+        Ok(dialog)
+    }
+
+    fn register_handlers(&self) {
+        {
+            let dialog = self.clone();
+            self.script_manager_tree_view
+                .get_selection()
+                .connect_changed(move |_| dialog.update_details());
+        }
+
+        {
+            let dialog = self.clone();
+            self.reload_button.connect_clicked(move |_| {
+                let load_error = dialog
+                    .scripts
+                    .write()
+                    .expect("Scripts lock is poisoned")
+                    .reload();
+                dialog.refresh(dialog.selected_name());
+
+                match load_error {
+                    Some(err) => dialog.post_error(&err),
+                    None => dialog.post_status("Reloaded scripts"),
+                }
+            });
+        }
+
+        {
+            let dialog = self.clone();
+            self.install_button.connect_clicked(move |_| {
+                if let Some(path) = dialog.choose_package() {
+                    if let Err(err) = dialog.install(&path) {
+                        dialog.post_error(&err);
+                    }
+                }
+            });
+        }
+
+        {
+            let dialog = self.clone();
+            self.uninstall_pack_button.connect_clicked(move |_| {
+                if let Err(err) = dialog.uninstall_selected_pack() {
+                    dialog.post_error(&err);
+                }
+            });
+        }
+
+        {
+            let dialog = self.clone();
+            self.open_file_button.connect_clicked(move |_| {
+                if let Some(path) = dialog.selected_path() {
+                    if let Err(err) = open::that(&path) {
+                        dialog.post_error(&eyre!("Failed to open {}: {}", path.display(), err));
+                    }
+                }
+            });
+        }
+
+        {
+            let dialog = self.clone();
+            self.open_folder_button.connect_clicked(move |_| {
+                if let Some(path) = dialog.selected_path() {
+                    if let Some(dir) = path.parent() {
+                        if let Err(err) = open::that(dir) {
+                            dialog.post_error(&eyre!("Failed to open {}: {}", dir.display(), err));
+                        }
+                    }
+                }
+            });
+        }
+
+        {
+            let dialog = self.clone();
+            self.duplicate_button.connect_clicked(move |_| {
+                if let Some(script) = dialog.selected_name() {
+                    let result = dialog
+                        .scripts
+                        .write()
+                        .expect("Scripts lock is poisoned")
+                        .duplicate(&script);
+
+                    match result {
+                        Ok(copy) => {
+                            dialog.refresh(Some(copy.clone()));
+                            dialog
+                                .post_status(&format!("Duplicated \"{}\" as \"{}\"", script, copy));
+                        }
+                        Err(err) => dialog.post_error(&err),
+                    }
+                }
+            });
+        }
+
+        {
+            let dialog = self.clone();
+            self.delete_button.connect_clicked(move |_| {
+                if let Some(script) = dialog.selected_name() {
+                    if !dialog.confirm_delete(&script) {
+                        return;
+                    }
+
+                    let result = dialog
+                        .scripts
+                        .write()
+                        .expect("Scripts lock is poisoned")
+                        .delete(&script);
+
+                    match result {
+                        Ok(()) => {
+                            dialog.refresh(Some(script.clone()));
+                            dialog.post_status(&format!("Deleted \"{}\"", script));
+                        }
+                        Err(err) => dialog.post_error(&err),
+                    }
+                }
+            });
+        }
+    }
+
+    // rebuilds the list from the script map and selects a script, or the first one if it is gone
+    fn refresh(&self, select: Option<String>) {
+        self.store.clear();
+
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+        let config = self.config.read().expect("Config lock poisoned");
+
+        let mut selected_iter = None;
+        for (name, script) in scripts.scripts.iter() {
+            let icon_name = format!("boop-gtk-{}-symbolic", script.metadata.icon.to_lowercase());
+
+            let text = format!(
+                "<b>{}</b>\n<span size=\"smaller\">{}</span>",
+                glib::markup_escape_text(&script.metadata.name),
+                glib::markup_escape_text(&script.metadata.description)
+            );
+
+            let origin = script.origin().to_string();
+
+            // shown when hovering a script
+            let path = match script.origin() {
+                ScriptOrigin::Builtin => "Included with Boop-GTK".to_string(),
+                _ => glib::markup_escape_text(&script.path.to_string_lossy()).to_string(),
+            };
+
+            let values: [&dyn ToValue; 6] = [
+                &!config.is_disabled(name),
+                &icon_name,
+                &text,
+                name,
+                &origin,
+                &path,
+            ];
+            let iter = self.store.append();
+            self.store.set(&iter, &COLUMNS, &values);
+
+            if select.as_ref() == Some(name) {
+                selected_iter = Some(iter);
+            }
+        }
+
+        // files in the script directories that couldn't be loaded
+        if scripts.load_errors.is_empty() {
+            self.load_errors_info_bar.hide();
+        } else {
+            let errors = scripts
+                .load_errors
+                .iter()
+                .map(|(path, err)| {
+                    format!(
+                        "<b>{}</b>: {}",
+                        glib::markup_escape_text(&path.to_string_lossy()),
+                        glib::markup_escape_text(err)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            self.load_errors_label.set_markup(&format!(
+                "{} script files couldn't be loaded:\n{}",
+                scripts.load_errors.len(),
+                errors
+            ));
+            self.load_errors_info_bar.show();
+        }
+
+        drop(scripts);
+        drop(config);
+
+        let selection = self.script_manager_tree_view.get_selection();
+        match selected_iter.or_else(|| self.store.get_iter_first()) {
+            Some(iter) => {
+                selection.select_iter(&iter);
+                if let Some(path) = self.store.get_path(&iter) {
+                    self.script_manager_tree_view.scroll_to_cell(
+                        Some(&path),
+                        gtk::NONE_TREE_VIEW_COLUMN,
+                        false,
+                        0.0,
+                        0.0,
+                    );
+                }
+            }
+            None => selection.unselect_all(),
+        }
+
+        self.update_details();
+    }
+
+    fn selected_name(&self) -> Option<String> {
+        let (model, iter) = self
+            .script_manager_tree_view
+            .get_selection()
+            .get_selected()?;
+
+        model
+            .get_value(&iter, NAME_COLUMN as i32)
+            .get::<String>()
+            .ok()
+            .flatten()
+    }
+
+    fn selected_path(&self) -> Option<PathBuf> {
+        let name = self.selected_name()?;
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+        scripts.scripts.get(&name).map(|script| script.path.clone())
+    }
+
+    // shows the metadata of the selected script
+    fn update_details(&self) {
+        self.manager_status_label.set_text("");
+
+        let name = self.selected_name();
+        let scripts = self.scripts.read().expect("Scripts lock is poisoned");
+        let script = match name.as_ref().and_then(|name| scripts.scripts.get(name)) {
+            Some(script) => script,
+            None => {
+                self.details_box.set_sensitive(false);
+                return;
+            }
+        };
+        self.details_box.set_sensitive(true);
+
+        let metadata = &script.metadata;
+        let origin = script.origin();
+
+        self.details_icon.set_from_icon_name(
+            Some(&format!(
+                "boop-gtk-{}-symbolic",
+                metadata.icon.to_lowercase()
+            )),
+            gtk::IconSize::Dialog,
+        );
+        self.details_name_label.set_markup(&format!(
+            "<big><b>{}</b></big>",
+            glib::markup_escape_text(&metadata.name)
+        ));
+        self.details_description_label
+            .set_text(&metadata.description);
+        self.details_author_label
+            .set_text(metadata.author.as_deref().unwrap_or("Unknown"));
+        self.details_tags_label
+            .set_text(metadata.tags.as_deref().unwrap_or("None"));
+        self.details_api_label.set_text(&metadata.api.to_string());
+        self.details_permissions_label
+            .set_text(&if metadata.permissions.is_empty() {
+                "None".to_string()
+            } else {
+                metadata
+                    .permissions
+                    .iter()
+                    .map(|permission| permission.description())
+                    .collect::<Vec<&str>>()
+                    .join("\n")
+            });
+        self.details_persistent_label
+            .set_text(if metadata.persistent { "Yes" } else { "No" });
+        self.details_origin_label.set_text(&origin.to_string());
+        self.details_path_label.set_text(&match origin {
+            ScriptOrigin::Builtin => "Included with Boop-GTK".to_string(),
+            _ => script.path.to_string_lossy().to_string(),
+        });
+        self.details_override_label
+            .set_text(if scripts.is_overridden(&metadata.name) {
+                "The built-in script with the same name"
+            } else {
+                "Nothing"
+            });
+        self.details_last_run_label.set_text(&last_run(script));
+
+        let config = self.config.read().expect("Config lock poisoned");
+        let pack = config.pack_of(&metadata.name);
+        self.details_pack_label.set_text(&match pack {
+            Some(pack) if pack.version.is_empty() => pack.name.clone(),
+            Some(pack) => format!("{} {}", pack.name, pack.version),
+            None => "None".to_string(),
+        });
+        self.uninstall_pack_button.set_sensitive(pack.is_some());
+
+        // built-in scripts have no file, only user scripts are ours to delete
+        self.open_file_button
+            .set_sensitive(origin != ScriptOrigin::Builtin);
+        self.open_folder_button
+            .set_sensitive(origin != ScriptOrigin::Builtin);
+        self.delete_button
+            .set_sensitive(origin == ScriptOrigin::User);
+    }
+
+    fn confirm_delete(&self, script: &str) -> bool {
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.script_manager_dialog),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &format!("Delete \"{}\"?", script),
+        );
+        dialog.set_property_secondary_text(Some(
+            "The script file is deleted, a built-in script it overrides is restored.",
+        ));
+        dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+        dialog.add_button("Delete", gtk::ResponseType::Accept);
+        dialog.set_default_response(gtk::ResponseType::Cancel);
+
+        let responce = dialog.run();
+        dialog.close();
+
+        responce == gtk::ResponseType::Accept
+    }
+
+    // asks for a script or script pack to install
+    fn choose_package(&self) -> Option<PathBuf> {
+        let chooser = gtk::FileChooserDialog::with_buttons(
+            Some("Install Script"),
+            Some(&self.script_manager_dialog),
+            gtk::FileChooserAction::Open,
+            &[
+                ("Cancel", gtk::ResponseType::Cancel),
+                ("Install", gtk::ResponseType::Accept),
+            ],
+        );
+
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some("Scripts and script packs"));
+        for pattern in &["*.js", "*.zip", "*.tar"] {
+            filter.add_pattern(pattern);
+        }
+        chooser.add_filter(&filter);
+
+        let path = match chooser.run() {
+            gtk::ResponseType::Accept => chooser.get_filename(),
+            _ => None,
+        };
+        chooser.close();
+
+        path
+    }
+
+    fn install(&self, path: &Path) -> Result<()> {
+        let package = Package::open(path)?;
+
+        let collisions = package.collisions(
+            &self.scripts.read().expect("Scripts lock is poisoned"),
+            &self.config.read().expect("Config lock poisoned"),
+        );
+        if !self.confirm_install(&package, &collisions) {
+            return Ok(());
+        }
+
+        let installed = {
+            let mut scripts = self.scripts.write().expect("Scripts lock is poisoned");
+            let mut config = self.config.write().expect("Config lock poisoned");
+            let installed = package.install(&mut scripts, &mut config)?;
+            config.save().wrap_err("Failed to save config")?;
+            installed
+        };
+
+        self.refresh(installed.first().cloned());
+        self.post_status(&match package.pack_name() {
+            Some(pack) => format!("Installed {} scripts from \"{}\"", installed.len(), pack),
+            None => format!("Installed \"{}\"", installed.join("\", \"")),
+        });
+
+        Ok(())
+    }
+
+    // lists what the package contains and anything it replaces, installations that would replace
+    // user scripts or files can only be cancelled
+    fn confirm_install(&self, package: &Package, collisions: &[Collision]) -> bool {
+        let blocked = collisions.iter().any(Collision::is_blocking);
+
+        let title = match package.pack_name() {
+            Some(pack) if blocked => format!("Can't install \"{}\"", pack),
+            Some(pack) => format!("Install \"{}\"?", pack),
+            None if blocked => "Can't install script".to_string(),
+            None => "Install script?".to_string(),
+        };
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.script_manager_dialog),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            if blocked {
+                gtk::MessageType::Error
+            } else {
+                gtk::MessageType::Question
+            },
+            gtk::ButtonsType::None,
+            &title,
+        );
+
+        let mut text = String::new();
+        if let Some(manifest) = &package.manifest {
+            if !manifest.description.is_empty() {
+                text.push_str(&format!("{}\n\n", manifest.description));
+            }
+        }
+        text.push_str(&format!(
+            "Scripts: {}",
+            package.script_names().collect::<Vec<&str>>().join(", ")
+        ));
+        if !collisions.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(
+                &collisions
+                    .iter()
+                    .map(|collision| collision.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            );
+        }
+        dialog.set_property_secondary_text(Some(&text));
+
+        dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+        if !blocked {
+            dialog.add_button("Install", gtk::ResponseType::Accept);
+        }
+        dialog.set_default_response(gtk::ResponseType::Cancel);
+
+        let responce = dialog.run();
+        dialog.close();
+
+        responce == gtk::ResponseType::Accept
+    }
+
+    fn uninstall_selected_pack(&self) -> Result<()> {
+        let name = match self.selected_name() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let pack = match self
+            .config
+            .read()
+            .expect("Config lock poisoned")
+            .pack_of(&name)
+        {
+            Some(pack) => pack.clone(),
+            None => return Ok(()),
+        };
+
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.script_manager_dialog),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &format!("Uninstall \"{}\"?", pack.name),
+        );
+        dialog.set_property_secondary_text(Some(&format!(
+            "Deletes {}, and the libraries they share.",
+            pack.scripts.join(", ")
+        )));
+        dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+        dialog.add_button("Uninstall", gtk::ResponseType::Accept);
+        dialog.set_default_response(gtk::ResponseType::Cancel);
+
+        let responce = dialog.run();
+        dialog.close();
+        if responce != gtk::ResponseType::Accept {
+            return Ok(());
+        }
+
+        {
+            let mut scripts = self.scripts.write().expect("Scripts lock is poisoned");
+            let mut config = self.config.write().expect("Config lock poisoned");
+            install::uninstall(&pack.name, &mut scripts, &mut config)?;
+            config.save().wrap_err("Failed to save config")?;
+        }
+
+        self.refresh(Some(name));
+        self.post_status(&format!("Uninstalled \"{}\"", pack.name));
+
+        Ok(())
+    }
+
+    fn post_status(&self, text: &str) {
+        self.manager_status_label.set_text(text);
+    }
+
+    fn post_error(&self, err: &eyre::Report) {
+        error!("script manager: {:?}", err);
+        self.manager_status_label.set_markup(&format!(
+            r#"<span foreground="red" weight="bold">ERROR:</span> {}"#,
+            glib::markup_escape_text(&err.to_string())
+        ));
+    }
+
+    fn on_toggled(
+        store: &gtk::ListStore,
+        path: gtk::TreePath,
+        config: &Arc<RwLock<Config>>,
+    ) -> Result<()> {
+        let iter = store
+            .get_iter(&path)
+            .ok_or_else(|| eyre!("failed to get iter for path: {:?}", path))?;
+
+        let enabled: bool = store
+            .get_value(&iter, ENABLED_COLUMN as i32)
+            .get_some()
+            .wrap_err("Enabled column isn't a bool")?;
+        let name: String = store
+            .get_value(&iter, NAME_COLUMN as i32)
+            .get()
+            .wrap_err("Name column isn't a string")?
+            .ok_or_else(|| eyre!("Name column is empty"))?;
+
+        info!(
+            "{} {}",
+            if enabled { "disabling" } else { "enabling" },
+            name
+        );
+
+        store.set_value(&iter, ENABLED_COLUMN, &(!enabled).to_value());
+
+        let mut config = config.write().expect("Config lock poisoned");
+        config.set_disabled(&name, enabled);
+        config.save().wrap_err("Failed to save config")
+    }
+}
+
+// when a script last ran, executions are only tracked while Boop-GTK is open
+fn last_run(script: &Script) -> String {
+    match script.last_used() {
+        Some(instant) => format_elapsed(instant.elapsed()),
+        None => "Not since Boop-GTK was opened".to_string(),
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let minutes = elapsed.as_secs() / 60;
+    let hours = minutes / 60;
+
+    match (hours, minutes) {
+        (0, 0) => "Just now".to_string(),
+        (0, 1) => "1 minute ago".to_string(),
+        (0, minutes) => format!("{} minutes ago", minutes),
+        (1, _) => "1 hour ago".to_string(),
+        (hours, _) => format!("{} hours ago", hours),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(59)), "Just now");
+        assert_eq!(format_elapsed(Duration::from_secs(60)), "1 minute ago");
+        assert_eq!(
+            format_elapsed(Duration::from_secs(45 * 60)),
+            "45 minutes ago"
+        );
+        assert_eq!(format_elapsed(Duration::from_secs(90 * 60)), "1 hour ago");
+        assert_eq!(
+            format_elapsed(Duration::from_secs(5 * 60 * 60)),
+            "5 hours ago"
+        );
+    }
+}
@@ -1,21 +1,63 @@
-use std::string::FromUtf8Error;
-
 use eyre::Result;
 use glib::Cast;
 use gtk::TextViewExt;
 
-pub trait StringExt {
-    fn remove_null_bytes(self) -> Result<String, FromUtf8Error>;
+pub trait BytesExt {
+    fn hex_dump(&self) -> String;
 }
 
-impl StringExt for String {
-    fn remove_null_bytes(self) -> Result<String, FromUtf8Error> {
-        String::from_utf8(
-            self.into_bytes()
-                .into_iter()
-                .filter(|b| *b != 0)
-                .collect::<Vec<u8>>(),
-        )
+impl BytesExt for [u8] {
+    // offset, 16 bytes in hex and their printable ASCII characters on each line, like `hexdump -C`
+    fn hex_dump(&self) -> String {
+        let mut dump = String::with_capacity(self.len() * 5);
+
+        for (line, chunk) in self.chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+
+            dump.push_str(&format!(
+                "{:08x}  {:<47}  |{}|\n",
+                line * 16,
+                hex.join(" "),
+                ascii
+            ));
+        }
+
+        dump
+    }
+}
+
+// JavaScript strings are indexed in UTF-16 code units, these convert between those offsets and byte
+// offsets into a rust string
+pub trait Utf16Ext {
+    fn utf16_len(&self) -> usize;
+    fn utf16_to_byte_offset(&self, offset: usize) -> usize;
+}
+
+impl Utf16Ext for str {
+    fn utf16_len(&self) -> usize {
+        self.encode_utf16().count()
+    }
+
+    // offsets past the end of the string are clamped to its length
+    fn utf16_to_byte_offset(&self, offset: usize) -> usize {
+        let mut utf16_offset = 0;
+        for (byte_offset, c) in self.char_indices() {
+            if utf16_offset >= offset {
+                return byte_offset;
+            }
+            utf16_offset += c.len_utf16();
+        }
+        self.len()
     }
 }
 